@@ -0,0 +1,8 @@
+CREATE TABLE `data_exports`(
+    `token` CHAR(64) NOT NULL PRIMARY KEY,
+    `user_id` CHAR(36) NOT NULL,
+    `content` MEDIUMBLOB NOT NULL,
+    `expiry` DATETIME NOT NULL
+);
+ALTER TABLE
+    `data_exports` ADD CONSTRAINT `data_exports_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
@@ -0,0 +1,14 @@
+-- Frees the username of the placeholder user by renaming any existing user who has it, using their unique ID.
+UPDATE
+    `users`
+SET
+    `username` = CONCAT('deleted-', `id`)
+WHERE
+    `username` = 'deleted'
+    AND `id` != '00000000-0000-0000-0000-000000000000';
+
+-- The presence column is left to its default, since it is added by a later migration.
+INSERT INTO
+    `users`(`id`, `username`, `display_name`, `email`, `password`, `creation_date`)
+VALUES
+    ('00000000-0000-0000-0000-000000000000', 'deleted', 'Deleted User', '', '', NOW());
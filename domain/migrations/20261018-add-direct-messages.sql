@@ -0,0 +1,27 @@
+CREATE TABLE `dms`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `creation_date` DATETIME NOT NULL
+);
+CREATE TABLE `dm_participants`(
+    `dm_id` CHAR(36) NOT NULL,
+    `user_id` CHAR(36) NOT NULL,
+    PRIMARY KEY(`dm_id`, `user_id`)
+);
+CREATE TABLE `dm_messages`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `dm_id` CHAR(36) NOT NULL,
+    `sender` CHAR(36) NOT NULL,
+    `content` TEXT NOT NULL,
+    `attachment` CHAR(36) NULL,
+    `send_time` DATETIME NOT NULL
+);
+ALTER TABLE
+    `dm_participants` ADD CONSTRAINT `dm_participants_dm_id_foreign` FOREIGN KEY(`dm_id`) REFERENCES `dms`(`id`);
+ALTER TABLE
+    `dm_participants` ADD CONSTRAINT `dm_participants_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_dm_id_foreign` FOREIGN KEY(`dm_id`) REFERENCES `dms`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_sender_foreign` FOREIGN KEY(`sender`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_attachment_foreign` FOREIGN KEY(`attachment`) REFERENCES `files`(`id`);
@@ -0,0 +1,9 @@
+ALTER TABLE
+    `users` ADD `email_verified` BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE TABLE `email_verifications`(
+    `user_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` CHAR(64) NOT NULL UNIQUE,
+    `expiry` DATETIME NOT NULL
+);
+ALTER TABLE
+    `email_verifications` ADD CONSTRAINT `email_verifications_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
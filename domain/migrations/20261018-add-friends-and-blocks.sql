@@ -0,0 +1,21 @@
+CREATE TABLE `friends`(
+    `user_id` CHAR(36) NOT NULL,
+    `friend_id` CHAR(36) NOT NULL,
+    `accepted` BOOLEAN NOT NULL,
+    `request_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `friend_id`)
+);
+CREATE TABLE `blocks`(
+    `user_id` CHAR(36) NOT NULL,
+    `blocked_id` CHAR(36) NOT NULL,
+    `block_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `blocked_id`)
+);
+ALTER TABLE
+    `friends` ADD CONSTRAINT `friends_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `friends` ADD CONSTRAINT `friends_friend_id_foreign` FOREIGN KEY(`friend_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `blocks` ADD CONSTRAINT `blocks_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `blocks` ADD CONSTRAINT `blocks_blocked_id_foreign` FOREIGN KEY(`blocked_id`) REFERENCES `users`(`id`);
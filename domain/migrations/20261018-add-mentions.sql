@@ -0,0 +1,13 @@
+CREATE TABLE `mentions`(
+    `message_id` CHAR(36) NOT NULL,
+    `position` INT NOT NULL,
+    `kind` VARCHAR(16) NOT NULL,
+    `user_id` CHAR(36) NULL,
+    PRIMARY KEY(`message_id`, `position`)
+);
+ALTER TABLE
+    `mentions` ADD INDEX `mentions_user_id_index`(`user_id`);
+ALTER TABLE
+    `mentions` ADD CONSTRAINT `mentions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `mentions` ADD CONSTRAINT `mentions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
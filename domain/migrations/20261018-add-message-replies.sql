@@ -0,0 +1,2 @@
+ALTER TABLE
+    `messages` ADD `reply_to` CHAR(36) NULL;
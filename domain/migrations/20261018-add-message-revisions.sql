@@ -0,0 +1,12 @@
+ALTER TABLE
+    `messages` ADD `edited_at` DATETIME NULL;
+CREATE TABLE `message_revisions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `message_id` CHAR(36) NOT NULL,
+    `content` TEXT NOT NULL,
+    `edit_time` DATETIME NOT NULL
+);
+ALTER TABLE
+    `message_revisions` ADD INDEX `message_revisions_message_id_index`(`message_id`);
+ALTER TABLE
+    `message_revisions` ADD CONSTRAINT `message_revisions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
@@ -0,0 +1,2 @@
+ALTER TABLE
+    `messages` ADD FULLTEXT INDEX `messages_content_fulltext`(`content`);
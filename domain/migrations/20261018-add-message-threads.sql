@@ -0,0 +1,4 @@
+ALTER TABLE
+    `messages` ADD `thread` CHAR(36) NULL;
+ALTER TABLE
+    `messages` ADD INDEX `messages_thread_index`(`thread`);
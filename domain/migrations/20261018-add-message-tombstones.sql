@@ -0,0 +1,8 @@
+ALTER TABLE
+    `messages` ADD `deleted_by` CHAR(36) NULL;
+ALTER TABLE
+    `messages` ADD `delete_reason` TEXT NULL;
+ALTER TABLE
+    `messages` ADD `deleted_at` DATETIME NULL;
+ALTER TABLE
+    `messages` ADD CONSTRAINT `messages_deleted_by_foreign` FOREIGN KEY(`deleted_by`) REFERENCES `users`(`id`);
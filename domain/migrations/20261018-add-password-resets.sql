@@ -0,0 +1,7 @@
+CREATE TABLE `password_resets`(
+    `user_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` CHAR(64) NOT NULL UNIQUE,
+    `expiry` DATETIME NOT NULL
+);
+ALTER TABLE
+    `password_resets` ADD CONSTRAINT `password_resets_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
@@ -0,0 +1,9 @@
+CREATE TABLE `pins`(
+    `message_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `pinned_by` CHAR(36) NOT NULL,
+    `pin_time` DATETIME NOT NULL
+);
+ALTER TABLE
+    `pins` ADD CONSTRAINT `pins_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `pins` ADD CONSTRAINT `pins_pinned_by_foreign` FOREIGN KEY(`pinned_by`) REFERENCES `users`(`id`);
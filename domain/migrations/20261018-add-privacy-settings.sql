@@ -0,0 +1,4 @@
+ALTER TABLE
+    `users` ADD `email_visibility` VARCHAR(16) NOT NULL DEFAULT 'nobody';
+ALTER TABLE
+    `users` ADD `bio_visibility` VARCHAR(16) NOT NULL DEFAULT 'everyone';
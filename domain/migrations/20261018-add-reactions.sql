@@ -0,0 +1,13 @@
+CREATE TABLE `reactions`(
+    `message_id` CHAR(36) NOT NULL,
+    `user_id` CHAR(36) NOT NULL,
+    `emoji` VARCHAR(64) NOT NULL,
+    `creation_date` DATETIME NOT NULL,
+    PRIMARY KEY(`message_id`, `user_id`, `emoji`)
+);
+ALTER TABLE
+    `reactions` ADD INDEX `reactions_user_id_index`(`user_id`);
+ALTER TABLE
+    `reactions` ADD CONSTRAINT `reactions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `reactions` ADD CONSTRAINT `reactions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
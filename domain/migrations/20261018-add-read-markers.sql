@@ -0,0 +1,13 @@
+CREATE TABLE `read_markers`(
+    `user_id` CHAR(36) NOT NULL,
+    `subset_id` CHAR(36) NOT NULL,
+    `message_id` CHAR(36) NULL,
+    `read_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `subset_id`)
+);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_subset_id_foreign` FOREIGN KEY(`subset_id`) REFERENCES `subsets`(`id`);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
@@ -0,0 +1,32 @@
+CREATE TABLE `roles`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `set_id` CHAR(36) NOT NULL,
+    `name` VARCHAR(255) NOT NULL,
+    `kind` VARCHAR(16) NOT NULL,
+    `permissions` INT NOT NULL,
+    `creation_date` DATETIME NOT NULL
+);
+ALTER TABLE
+    `roles` ADD INDEX `roles_set_id_index`(`set_id`);
+ALTER TABLE
+    `roles` ADD CONSTRAINT `roles_set_id_foreign` FOREIGN KEY(`set_id`) REFERENCES `sets`(`id`);
+INSERT INTO `roles` (`id`, `set_id`, `name`, `kind`, `permissions`, `creation_date`)
+    SELECT UUID(), `id`, 'Owner', 'owner', 63, NOW() FROM `sets`;
+INSERT INTO `roles` (`id`, `set_id`, `name`, `kind`, `permissions`, `creation_date`)
+    SELECT UUID(), `id`, 'Moderator', 'moderator', 30, NOW() FROM `sets`;
+INSERT INTO `roles` (`id`, `set_id`, `name`, `kind`, `permissions`, `creation_date`)
+    SELECT UUID(), `id`, 'Member', 'member', 0, NOW() FROM `sets`;
+ALTER TABLE
+    `memberships` ADD `role` CHAR(36) NULL;
+UPDATE `memberships`
+    JOIN `roles` ON `roles`.`set_id` = `memberships`.`set_id`
+    AND `roles`.`kind` = IF(`memberships`.`admin`, 'owner', 'member')
+    SET `memberships`.`role` = `roles`.`id`;
+ALTER TABLE
+    `memberships` MODIFY `role` CHAR(36) NOT NULL;
+ALTER TABLE
+    `memberships` DROP COLUMN `admin`;
+ALTER TABLE
+    `memberships` ADD INDEX `memberships_role_index`(`role`);
+ALTER TABLE
+    `memberships` ADD CONSTRAINT `memberships_role_foreign` FOREIGN KEY(`role`) REFERENCES `roles`(`id`);
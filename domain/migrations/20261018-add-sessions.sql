@@ -0,0 +1,17 @@
+CREATE TABLE `sessions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` VARCHAR(255) NOT NULL UNIQUE,
+    `user_id` CHAR(36) NOT NULL,
+    `device` VARCHAR(255) NULL,
+    `ip` VARCHAR(45) NOT NULL,
+    `creation_date` DATETIME NOT NULL,
+    `last_used` DATETIME NOT NULL
+);
+INSERT INTO `sessions` (`id`, `token`, `user_id`, `device`, `ip`, `creation_date`, `last_used`)
+    SELECT UUID(), `token`, `id`, NULL, '', NOW(), NOW() FROM `users` WHERE `token` IS NOT NULL;
+ALTER TABLE
+    `users` DROP INDEX `users_token_index`;
+ALTER TABLE
+    `users` DROP COLUMN `token`;
+ALTER TABLE
+    `sessions` ADD CONSTRAINT `sessions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
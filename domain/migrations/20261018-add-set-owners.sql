@@ -0,0 +1,25 @@
+ALTER TABLE
+    `sets` ADD `owner` CHAR(36) NULL;
+UPDATE `sets`
+    SET `owner` = (
+        SELECT `memberships`.`user_id` FROM `memberships`
+        JOIN `roles` ON `memberships`.`role` = `roles`.`id`
+        WHERE `memberships`.`set_id` = `sets`.`id` AND `roles`.`kind` = 'owner'
+        ORDER BY `memberships`.`creation_date` ASC LIMIT 1
+    );
+UPDATE `sets`
+    SET `owner` = (
+        SELECT `memberships`.`user_id` FROM `memberships`
+        WHERE `memberships`.`set_id` = `sets`.`id`
+        ORDER BY `memberships`.`creation_date` ASC LIMIT 1
+    )
+    WHERE `owner` IS NULL;
+UPDATE `memberships`
+    JOIN `sets` ON `memberships`.`set_id` = `sets`.`id`
+    JOIN `roles` ON `roles`.`set_id` = `sets`.`id`
+    AND `roles`.`kind` = IF(`memberships`.`user_id` = `sets`.`owner`, 'owner', 'moderator')
+    JOIN `roles` AS `current` ON `memberships`.`role` = `current`.`id`
+    SET `memberships`.`role` = `roles`.`id`
+    WHERE `memberships`.`user_id` = `sets`.`owner` OR `current`.`kind` = 'owner';
+ALTER TABLE
+    `sets` ADD CONSTRAINT `sets_owner_foreign` FOREIGN KEY(`owner`) REFERENCES `users`(`id`);
@@ -0,0 +1,15 @@
+CREATE TABLE `subset_permissions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `subset_id` CHAR(36) NOT NULL,
+    `role_id` CHAR(36) NOT NULL,
+    `can_view` TINYINT(1) NOT NULL,
+    `can_post` TINYINT(1) NOT NULL
+);
+ALTER TABLE
+    `subset_permissions` ADD INDEX `subset_permissions_subset_id_index`(`subset_id`);
+ALTER TABLE
+    `subset_permissions` ADD INDEX `subset_permissions_role_id_index`(`role_id`);
+ALTER TABLE
+    `subset_permissions` ADD CONSTRAINT `subset_permissions_subset_id_foreign` FOREIGN KEY(`subset_id`) REFERENCES `subsets`(`id`);
+ALTER TABLE
+    `subset_permissions` ADD CONSTRAINT `subset_permissions_role_id_foreign` FOREIGN KEY(`role_id`) REFERENCES `roles`(`id`);
@@ -0,0 +1,14 @@
+ALTER TABLE
+    `sessions` ADD `expiry` DATETIME NOT NULL DEFAULT NOW();
+ALTER TABLE
+    `sessions` ADD `refresh_token` VARCHAR(255) NULL;
+ALTER TABLE
+    `sessions` ADD `refresh_expiry` DATETIME NOT NULL DEFAULT NOW();
+UPDATE
+    `sessions` SET `expiry` = DATE_ADD(NOW(), INTERVAL 60 MINUTE), `refresh_token` = UUID(), `refresh_expiry` = DATE_ADD(NOW(), INTERVAL 30 DAY);
+ALTER TABLE
+    `sessions` MODIFY `expiry` DATETIME NOT NULL;
+ALTER TABLE
+    `sessions` MODIFY `refresh_token` VARCHAR(255) NOT NULL UNIQUE;
+ALTER TABLE
+    `sessions` MODIFY `refresh_expiry` DATETIME NOT NULL;
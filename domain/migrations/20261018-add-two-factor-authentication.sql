@@ -0,0 +1,22 @@
+ALTER TABLE
+    `users` ADD `totp_secret` VARCHAR(128) NULL;
+ALTER TABLE
+    `users` ADD `totp_enabled` BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE
+    `users` ADD `totp_counter` BIGINT UNSIGNED NULL;
+CREATE TABLE `recovery_codes`(
+    `user_id` CHAR(36) NOT NULL,
+    `token` CHAR(64) NOT NULL,
+    PRIMARY KEY(`user_id`, `token`)
+);
+CREATE TABLE `login_challenges`(
+    `token` CHAR(64) NOT NULL PRIMARY KEY,
+    `user_id` CHAR(36) NOT NULL,
+    `device` VARCHAR(255) NULL,
+    `ip` VARCHAR(45) NOT NULL,
+    `expiry` DATETIME NOT NULL
+);
+ALTER TABLE
+    `recovery_codes` ADD CONSTRAINT `recovery_codes_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `login_challenges` ADD CONSTRAINT `login_challenges_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
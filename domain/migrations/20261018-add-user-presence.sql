@@ -0,0 +1,6 @@
+ALTER TABLE
+    `users` ADD `presence` VARCHAR(16) NOT NULL DEFAULT 'online';
+ALTER TABLE
+    `users` ADD `status` VARCHAR(128) NULL;
+ALTER TABLE
+    `users` ADD `status_expiry` DATETIME NULL;
@@ -0,0 +1,6 @@
+DELETE FROM
+    `sessions`;
+ALTER TABLE
+    `sessions` MODIFY `token` CHAR(64) NOT NULL;
+ALTER TABLE
+    `sessions` MODIFY `refresh_token` CHAR(64) NOT NULL;
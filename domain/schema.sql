@@ -9,19 +9,26 @@ CREATE TABLE `users`(
     `image` CHAR(36) NULL,
     `bio` TEXT NULL,
     `password` VARCHAR(255) NOT NULL,
-    `token` VARCHAR(255) NULL,
-    `creation_date` DATETIME NOT NULL
+    `creation_date` DATETIME NOT NULL,
+    `presence` VARCHAR(16) NOT NULL DEFAULT 'online',
+    `status` VARCHAR(128) NULL,
+    `status_expiry` DATETIME NULL,
+    `email_verified` BOOLEAN NOT NULL DEFAULT FALSE,
+    `totp_secret` VARCHAR(128) NULL,
+    `totp_enabled` BOOLEAN NOT NULL DEFAULT FALSE,
+    `totp_counter` BIGINT UNSIGNED NULL,
+    `email_visibility` VARCHAR(16) NOT NULL DEFAULT 'nobody',
+    `bio_visibility` VARCHAR(16) NOT NULL DEFAULT 'everyone'
 );
 ALTER TABLE
     `users` ADD INDEX `users_username_index`(`username`);
 ALTER TABLE
     `users` ADD INDEX `users_email_index`(`email`);
-ALTER TABLE
-    `users` ADD INDEX `users_token_index`(`token`);
 CREATE TABLE `sets`(
     `id` CHAR(36) NOT NULL PRIMARY KEY,
     `name` VARCHAR(255) NOT NULL,
     `icon` CHAR(255) NOT NULL,
+    `owner` CHAR(36) NULL,
     `creation_date` DATETIME NOT NULL
 );
 CREATE TABLE `invites`(
@@ -44,29 +51,170 @@ CREATE TABLE `subsets`(
 );
 ALTER TABLE
     `subsets` ADD INDEX `subsets_set_id_index`(`set_id`);
+CREATE TABLE `subset_permissions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `subset_id` CHAR(36) NOT NULL,
+    `role_id` CHAR(36) NOT NULL,
+    `can_view` TINYINT(1) NOT NULL,
+    `can_post` TINYINT(1) NOT NULL
+);
+ALTER TABLE
+    `subset_permissions` ADD INDEX `subset_permissions_subset_id_index`(`subset_id`);
+ALTER TABLE
+    `subset_permissions` ADD INDEX `subset_permissions_role_id_index`(`role_id`);
 CREATE TABLE `memberships`(
     `id` CHAR(36) NOT NULL PRIMARY KEY,
     `user_id` CHAR(36) NOT NULL,
     `set_id` CHAR(36) NOT NULL,
-    `admin` TINYINT(1) NOT NULL,
+    `role` CHAR(36) NOT NULL,
     `creation_date` DATETIME NOT NULL
 );
 ALTER TABLE
     `memberships` ADD INDEX `memberships_user_id_index`(`user_id`);
 ALTER TABLE
     `memberships` ADD INDEX `memberships_set_id_index`(`set_id`);
+ALTER TABLE
+    `memberships` ADD INDEX `memberships_role_index`(`role`);
+CREATE TABLE `roles`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `set_id` CHAR(36) NOT NULL,
+    `name` VARCHAR(255) NOT NULL,
+    `kind` VARCHAR(16) NOT NULL,
+    `permissions` INT NOT NULL,
+    `creation_date` DATETIME NOT NULL
+);
+ALTER TABLE
+    `roles` ADD INDEX `roles_set_id_index`(`set_id`);
 CREATE TABLE `messages`(
     `id` CHAR(36) NOT NULL PRIMARY KEY,
     `content` TEXT NOT NULL,
     `subset` CHAR(36) NOT NULL,
     `sender` CHAR(36) NOT NULL,
     `send_time` DATETIME NOT NULL,
-    `attachment` CHAR(36) NULL
+    `attachment` CHAR(36) NULL,
+    `reply_to` CHAR(36) NULL,
+    `thread` CHAR(36) NULL,
+    `edited_at` DATETIME NULL,
+    `deleted_by` CHAR(36) NULL,
+    `delete_reason` TEXT NULL,
+    `deleted_at` DATETIME NULL
 );
 ALTER TABLE
     `messages` ADD INDEX `messages_subset_index`(`subset`);
 ALTER TABLE
     `messages` ADD INDEX `messages_send_time_index`(`send_time`);
+ALTER TABLE
+    `messages` ADD INDEX `messages_thread_index`(`thread`);
+ALTER TABLE
+    `messages` ADD FULLTEXT INDEX `messages_content_fulltext`(`content`);
+CREATE TABLE `message_revisions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `message_id` CHAR(36) NOT NULL,
+    `content` TEXT NOT NULL,
+    `edit_time` DATETIME NOT NULL
+);
+ALTER TABLE
+    `message_revisions` ADD INDEX `message_revisions_message_id_index`(`message_id`);
+CREATE TABLE `reactions`(
+    `message_id` CHAR(36) NOT NULL,
+    `user_id` CHAR(36) NOT NULL,
+    `emoji` VARCHAR(64) NOT NULL,
+    `creation_date` DATETIME NOT NULL,
+    PRIMARY KEY(`message_id`, `user_id`, `emoji`)
+);
+ALTER TABLE
+    `reactions` ADD INDEX `reactions_user_id_index`(`user_id`);
+CREATE TABLE `pins`(
+    `message_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `pinned_by` CHAR(36) NOT NULL,
+    `pin_time` DATETIME NOT NULL
+);
+CREATE TABLE `mentions`(
+    `message_id` CHAR(36) NOT NULL,
+    `position` INT NOT NULL,
+    `kind` VARCHAR(16) NOT NULL,
+    `user_id` CHAR(36) NULL,
+    PRIMARY KEY(`message_id`, `position`)
+);
+ALTER TABLE
+    `mentions` ADD INDEX `mentions_user_id_index`(`user_id`);
+CREATE TABLE `read_markers`(
+    `user_id` CHAR(36) NOT NULL,
+    `subset_id` CHAR(36) NOT NULL,
+    `message_id` CHAR(36) NULL,
+    `read_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `subset_id`)
+);
+CREATE TABLE `dms`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `creation_date` DATETIME NOT NULL
+);
+CREATE TABLE `dm_participants`(
+    `dm_id` CHAR(36) NOT NULL,
+    `user_id` CHAR(36) NOT NULL,
+    PRIMARY KEY(`dm_id`, `user_id`)
+);
+CREATE TABLE `dm_messages`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `dm_id` CHAR(36) NOT NULL,
+    `sender` CHAR(36) NOT NULL,
+    `content` TEXT NOT NULL,
+    `attachment` CHAR(36) NULL,
+    `send_time` DATETIME NOT NULL
+);
+CREATE TABLE `friends`(
+    `user_id` CHAR(36) NOT NULL,
+    `friend_id` CHAR(36) NOT NULL,
+    `accepted` BOOLEAN NOT NULL,
+    `request_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `friend_id`)
+);
+CREATE TABLE `blocks`(
+    `user_id` CHAR(36) NOT NULL,
+    `blocked_id` CHAR(36) NOT NULL,
+    `block_time` DATETIME NOT NULL,
+    PRIMARY KEY(`user_id`, `blocked_id`)
+);
+CREATE TABLE `sessions`(
+    `id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` CHAR(64) NOT NULL UNIQUE,
+    `user_id` CHAR(36) NOT NULL,
+    `device` VARCHAR(255) NULL,
+    `ip` VARCHAR(45) NOT NULL,
+    `creation_date` DATETIME NOT NULL,
+    `last_used` DATETIME NOT NULL,
+    `expiry` DATETIME NOT NULL,
+    `refresh_token` CHAR(64) NOT NULL UNIQUE,
+    `refresh_expiry` DATETIME NOT NULL
+);
+CREATE TABLE `password_resets`(
+    `user_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` CHAR(64) NOT NULL UNIQUE,
+    `expiry` DATETIME NOT NULL
+);
+CREATE TABLE `email_verifications`(
+    `user_id` CHAR(36) NOT NULL PRIMARY KEY,
+    `token` CHAR(64) NOT NULL UNIQUE,
+    `expiry` DATETIME NOT NULL
+);
+CREATE TABLE `recovery_codes`(
+    `user_id` CHAR(36) NOT NULL,
+    `token` CHAR(64) NOT NULL,
+    PRIMARY KEY(`user_id`, `token`)
+);
+CREATE TABLE `login_challenges`(
+    `token` CHAR(64) NOT NULL PRIMARY KEY,
+    `user_id` CHAR(36) NOT NULL,
+    `device` VARCHAR(255) NULL,
+    `ip` VARCHAR(45) NOT NULL,
+    `expiry` DATETIME NOT NULL
+);
+CREATE TABLE `data_exports`(
+    `token` CHAR(64) NOT NULL PRIMARY KEY,
+    `user_id` CHAR(36) NOT NULL,
+    `content` MEDIUMBLOB NOT NULL,
+    `expiry` DATETIME NOT NULL
+);
 CREATE TABLE `files`(
     `id` CHAR(36) NOT NULL PRIMARY KEY,
     `name` VARCHAR(255) NOT NULL,
@@ -75,6 +223,8 @@ CREATE TABLE `files`(
 );
 ALTER TABLE
     `users` ADD CONSTRAINT `users_image_foreign` FOREIGN KEY(`image`) REFERENCES `files`(`id`);
+ALTER TABLE
+    `sets` ADD CONSTRAINT `sets_owner_foreign` FOREIGN KEY(`owner`) REFERENCES `users`(`id`);
 ALTER TABLE
     `invites` ADD CONSTRAINT `invites_set_id_foreign` FOREIGN KEY(`set_id`) REFERENCES `sets`(`id`);
 ALTER TABLE
@@ -83,6 +233,14 @@ ALTER TABLE
     `memberships` ADD CONSTRAINT `memberships_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
 ALTER TABLE
     `memberships` ADD CONSTRAINT `memberships_set_id_foreign` FOREIGN KEY(`set_id`) REFERENCES `sets`(`id`);
+ALTER TABLE
+    `memberships` ADD CONSTRAINT `memberships_role_foreign` FOREIGN KEY(`role`) REFERENCES `roles`(`id`);
+ALTER TABLE
+    `subset_permissions` ADD CONSTRAINT `subset_permissions_subset_id_foreign` FOREIGN KEY(`subset_id`) REFERENCES `subsets`(`id`);
+ALTER TABLE
+    `subset_permissions` ADD CONSTRAINT `subset_permissions_role_id_foreign` FOREIGN KEY(`role_id`) REFERENCES `roles`(`id`);
+ALTER TABLE
+    `roles` ADD CONSTRAINT `roles_set_id_foreign` FOREIGN KEY(`set_id`) REFERENCES `sets`(`id`);
 ALTER TABLE
     `messages` ADD CONSTRAINT `messages_subset_foreign` FOREIGN KEY(`subset`) REFERENCES `subsets`(`id`);
 ALTER TABLE
@@ -90,4 +248,60 @@ ALTER TABLE
 ALTER TABLE
     `messages` ADD CONSTRAINT `messages_attachment_foreign` FOREIGN KEY(`attachment`) REFERENCES `files`(`id`);
 ALTER TABLE
-    `files` ADD CONSTRAINT `files_owner_foreign` FOREIGN KEY(`owner`) REFERENCES `users`(`id`);
\ No newline at end of file
+    `messages` ADD CONSTRAINT `messages_deleted_by_foreign` FOREIGN KEY(`deleted_by`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `message_revisions` ADD CONSTRAINT `message_revisions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `reactions` ADD CONSTRAINT `reactions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `reactions` ADD CONSTRAINT `reactions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `pins` ADD CONSTRAINT `pins_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `pins` ADD CONSTRAINT `pins_pinned_by_foreign` FOREIGN KEY(`pinned_by`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `mentions` ADD CONSTRAINT `mentions_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `mentions` ADD CONSTRAINT `mentions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_subset_id_foreign` FOREIGN KEY(`subset_id`) REFERENCES `subsets`(`id`);
+ALTER TABLE
+    `read_markers` ADD CONSTRAINT `read_markers_message_id_foreign` FOREIGN KEY(`message_id`) REFERENCES `messages`(`id`);
+ALTER TABLE
+    `dm_participants` ADD CONSTRAINT `dm_participants_dm_id_foreign` FOREIGN KEY(`dm_id`) REFERENCES `dms`(`id`);
+ALTER TABLE
+    `dm_participants` ADD CONSTRAINT `dm_participants_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_dm_id_foreign` FOREIGN KEY(`dm_id`) REFERENCES `dms`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_sender_foreign` FOREIGN KEY(`sender`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `dm_messages` ADD CONSTRAINT `dm_messages_attachment_foreign` FOREIGN KEY(`attachment`) REFERENCES `files`(`id`);
+ALTER TABLE
+    `friends` ADD CONSTRAINT `friends_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `friends` ADD CONSTRAINT `friends_friend_id_foreign` FOREIGN KEY(`friend_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `blocks` ADD CONSTRAINT `blocks_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `blocks` ADD CONSTRAINT `blocks_blocked_id_foreign` FOREIGN KEY(`blocked_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `sessions` ADD CONSTRAINT `sessions_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `password_resets` ADD CONSTRAINT `password_resets_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `email_verifications` ADD CONSTRAINT `email_verifications_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `recovery_codes` ADD CONSTRAINT `recovery_codes_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `login_challenges` ADD CONSTRAINT `login_challenges_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `data_exports` ADD CONSTRAINT `data_exports_user_id_foreign` FOREIGN KEY(`user_id`) REFERENCES `users`(`id`);
+ALTER TABLE
+    `files` ADD CONSTRAINT `files_owner_foreign` FOREIGN KEY(`owner`) REFERENCES `users`(`id`);
+INSERT INTO
+    `users`(`id`, `username`, `display_name`, `email`, `password`, `creation_date`, `presence`)
+VALUES
+    ('00000000-0000-0000-0000-000000000000', 'deleted', 'Deleted User', '', '', NOW(), 'offline');
\ No newline at end of file
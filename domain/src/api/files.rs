@@ -32,13 +32,17 @@ pub fn handler(request: Request, state: Arc<State>) -> Response {
 fn get_file(request: Request, state: Arc<State>) -> Result<Response, String> {
     let id = request.uri.strip_prefix("/api/v1/files/").unwrap();
 
-    state.get_file(id).map(|file| {
+    // Data exports are kept apart from other files, but are downloaded in the same way
+    let file = state.get_file(id).or_else(|_| state.take_data_export(id));
+
+    file.map(|file| {
         Response::empty(StatusCode::OK)
             .with_bytes(file.content)
             .with_header(HeaderType::AccessControlAllowOrigin, "*")
             .with_header(
                 HeaderType::ContentType,
-                MimeType::from_extension(file.name.split('.').last().unwrap_or("")).to_string(),
+                MimeType::from_extension(file.name.split('.').next_back().unwrap_or(""))
+                    .to_string(),
             )
     })
 }
@@ -57,6 +61,8 @@ fn update_user_image(request: Request, state: Arc<State>) -> Result<Response, St
         .to_string();
     let content = request.content.ok_or("No file content provided")?;
 
+    state.check_token_expiry(&token)?;
+
     state.update_user_image(token, file_name, content).map(|_| {
         Response::empty(StatusCode::OK)
             .with_bytes(r#"{"success":true}"#)
@@ -1,6 +1,6 @@
 //! Provides handlers for HTTP endpoints.
 
-use crate::api::{files, matcher};
+use crate::api::{check_rate_limit, check_token_expiry, files, matcher, proxy, rate_limited};
 use crate::State;
 
 use humphrey::http::headers::HeaderType;
@@ -21,14 +21,23 @@ pub fn handler(request: Request, state: Arc<State>) -> Response {
         return files::handler(request, state);
     }
 
+    let addr = proxy::client_addr(&request);
+
     let json = request
         .content
         .and_then(|v| String::from_utf8(v).ok())
         .and_then(|s| humphrey_json::from_str(&s).ok());
 
     let response_body: Value = if let Some(json) = json {
-        if let Some(handler) = matcher(route) {
-            handler(state, json)
+        if let Err(retry_after) = check_rate_limit(&state, route, &json, addr) {
+            rate_limited(retry_after)
+        } else if let Err(e) = check_token_expiry(&state, &json) {
+            json!({
+                "success": false,
+                "error": e
+            })
+        } else if let Some(handler) = matcher(route) {
+            handler(state, json, addr)
         } else {
             json!({
                 "success": false,
@@ -47,8 +56,14 @@ pub fn handler(request: Request, state: Arc<State>) -> Response {
         .and_then(|v| v.as_bool())
         .unwrap_or(false);
 
+    let retry_after = response_body.get("retryAfter").and_then(|v| v.as_number());
+
+    // Humphrey has no `429 Too Many Requests` status and cannot send custom ones, so rate-limited requests
+    //   use `503 Service Unavailable`, which also supports the `Retry-After` header.
     let status_code = if success {
         StatusCode::OK
+    } else if retry_after.is_some() {
+        StatusCode::ServiceUnavailable
     } else {
         StatusCode::BadRequest
     };
@@ -58,7 +73,7 @@ pub fn handler(request: Request, state: Arc<State>) -> Response {
     if !success {
         crate::log!(
             "{} Error: {}",
-            request.address,
+            addr,
             response_body.get("error").and_then(|v| v.as_str()).unwrap()
         );
     }
@@ -71,13 +86,18 @@ pub fn handler(request: Request, state: Arc<State>) -> Response {
         "{} {} {} {} {}",
         status_code_number,
         status_code_string,
-        request.address,
+        addr,
         request.method,
         request.uri
     );
 
-    Response::empty(status_code)
+    let response = Response::empty(status_code)
         .with_bytes(serialized)
         .with_header(HeaderType::ContentType, "application/json")
-        .with_header(HeaderType::AccessControlAllowOrigin, "*")
+        .with_header(HeaderType::AccessControlAllowOrigin, "*");
+
+    match retry_after {
+        Some(retry_after) => response.with_header("Retry-After", retry_after.to_string()),
+        None => response,
+    }
 }
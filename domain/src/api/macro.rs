@@ -23,7 +23,7 @@ macro_rules! declare_endpoints {
             )*
         }
 
-        static __ENDPOINTS: &[(&str, fn(std::sync::Arc<$crate::State>, humphrey_json::Value) -> humphrey_json::Value)] = &[
+        static __ENDPOINTS: &[(&str, fn(std::sync::Arc<$crate::State>, humphrey_json::Value, std::net::SocketAddr) -> humphrey_json::Value)] = &[
             $(
                 ($endpoint, __endpoints::$function)
             ),*
@@ -52,16 +52,21 @@ macro_rules! declare_endpoints {
 /// declare_endpoint("v1/baz", baz("param1"), "responseFieldName");
 /// ```
 ///
-/// Optional and numeric parameters:
+/// Optional, numeric and list parameters:
 /// ```
-/// declare_endpoint!("v1/foo", foo("param1", (optional "param2"), (numeric "param3"), (numeric optional "param4")), None);
+/// declare_endpoint!("v1/foo", foo("param1", (optional "param2"), (numeric "param3"), (numeric optional "param4"), (list "param5")), None);
+/// ```
+///
+/// The IP address of the client making the request, rather than a parameter from the request body:
+/// ```
+/// declare_endpoint!("v1/foo", foo("param1", (address)), None);
 /// ```
 macro_rules! declare_endpoint {
     // Endpoints which do not return anything.
     ($endpoint:expr, $function:ident ( $( $param:tt ),* ), None) => {
-        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value) -> humphrey_json::Value {
+        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value, address_binding!(addr; $( $param ),*): std::net::SocketAddr) -> humphrey_json::Value {
             $crate::api::error_context(|| {
-                state.$function($( endpoint_param!(&json, $param) ),*)?;
+                state.$function($( endpoint_param!(&json, addr, $param) ),*)?;
 
                 Ok(humphrey_json::Value::Object(vec![
                     ("success".to_string(), humphrey_json::Value::Bool(true))
@@ -72,9 +77,9 @@ macro_rules! declare_endpoint {
 
     // Endpoints which return specific key-value pairs from the returned object.
     ($endpoint:expr, $function:ident ( $( $param:tt ),* ), { $( $output_key:tt : $value:tt ),* }) => {
-        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value) -> humphrey_json::Value {
+        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value, address_binding!(addr; $( $param ),*): std::net::SocketAddr) -> humphrey_json::Value {
             $crate::api::error_context(|| {
-                let response = state.$function($( endpoint_param!(&json, $param) ),*)?;
+                let response = state.$function($( endpoint_param!(&json, addr, $param) ),*)?;
 
                 Ok(humphrey_json::Value::Object(vec![
                     ("success".to_string(), humphrey_json::Value::Bool(true)),
@@ -88,9 +93,9 @@ macro_rules! declare_endpoint {
 
     // Endpoints which return the entire returned object.
     ($endpoint:expr, $function:ident ( $( $param:tt ),* ), $output:expr) => {
-        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value) -> humphrey_json::Value {
+        pub fn $function(state: std::sync::Arc<$crate::State>, json: humphrey_json::Value, address_binding!(addr; $( $param ),*): std::net::SocketAddr) -> humphrey_json::Value {
             $crate::api::error_context(|| {
-                let response = state.$function($( endpoint_param!(&json, $param) ),*)?;
+                let response = state.$function($( endpoint_param!(&json, addr, $param) ),*)?;
 
                 Ok(humphrey_json::Value::Object(vec![
                     ("success".to_string(), humphrey_json::Value::Bool(true)),
@@ -101,35 +106,60 @@ macro_rules! declare_endpoint {
     };
 }
 
+/// Binds the address of the client to the given name if the endpoint takes it as a parameter, or ignores it otherwise.
+macro_rules! address_binding {
+    ($addr:ident; ) => {
+        _
+    };
+
+    ($addr:ident; (address) $(, $param:tt )*) => {
+        $addr
+    };
+
+    ($addr:ident; $first:tt $(, $param:tt )*) => {
+        address_binding!($addr; $( $param ),*)
+    };
+}
+
 /// Parses an endpoint parameter.
 macro_rules! endpoint_param {
     // Optional numeric parameter.
-    ($json:expr, (numeric optional $key:expr)) => {
+    ($json:expr, $addr:expr, (numeric optional $key:expr)) => {
         $crate::api::get_int($json, $key).ok().map(|v| v as usize)
     };
 
     // Optional boolean parameter.
-    ($json:expr, (boolean optional $key:expr)) => {
+    ($json:expr, $addr:expr, (boolean optional $key:expr)) => {
         $crate::api::get_bool($json, $key).ok()
     };
 
     // Optional string parameter.
-    ($json:expr, (optional $key:expr)) => {
+    ($json:expr, $addr:expr, (optional $key:expr)) => {
         $crate::api::get_string($json, $key).ok()
     };
 
     // Required numeric parameter.
-    ($json:expr, (numeric $key:expr)) => {
+    ($json:expr, $addr:expr, (numeric $key:expr)) => {
         $crate::api::get_int($json, $key)? as usize
     };
 
     // Required boolean parameter.
-    ($json:expr, (boolean $key:expr)) => {
+    ($json:expr, $addr:expr, (boolean $key:expr)) => {
         $crate::api::get_bool($json, $key)?
     };
 
+    // Required list of strings parameter.
+    ($json:expr, $addr:expr, (list $key:expr)) => {
+        $crate::api::get_string_list($json, $key)?
+    };
+
+    // Address of the client making the request.
+    ($json:expr, $addr:expr, (address)) => {
+        $addr.ip().to_string()
+    };
+
     // Required string parameter.
-    ($json:expr, $key:expr) => {
+    ($json:expr, $addr:expr, $key:expr) => {
         $crate::api::get_string($json, $key)?
     };
 }
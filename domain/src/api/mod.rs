@@ -2,34 +2,88 @@
 
 pub mod files;
 pub mod http;
+pub mod proxy;
 pub mod ws;
 
 #[macro_use]
 pub mod r#macro;
 
+use crate::ratelimit::Limit;
 use crate::State;
 
 use humphrey_json::prelude::*;
 use humphrey_json::Value;
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 declare_endpoints! {
     // Authentication endpoints
-    "v1/signup" => signup("username", "password", "displayName", "email") -> {
+    "v1/signup" => signup("username", "password", "displayName", "email", (optional "device"), (address)) -> {
         "uid": uid,
-        "token": token
+        "token": token,
+        "refreshToken": refresh_token,
+        "expiry": expiry
     },
-    "v1/login" => login("username", "password") -> {
+    "v1/login" => login("username", "password", (optional "device"), (address)) -> {
         "uid": uid,
-        "token": token
+        "token": token,
+        "refreshToken": refresh_token,
+        "expiry": expiry,
+        "challenge": challenge
+    },
+    "v1/login2fa" => login_2fa("challenge", "code") -> {
+        "uid": uid,
+        "token": token,
+        "refreshToken": refresh_token,
+        "expiry": expiry
+    },
+    "v1/refresh" => refresh("refreshToken") -> {
+        "uid": uid,
+        "token": token,
+        "refreshToken": refresh_token,
+        "expiry": expiry
     },
     "v1/logout" => logout("token") -> None,
     "v1/validateToken" => validate_token("token") -> "uid",
+    "v1/changePassword" => change_password("token", "password", "newPassword", (optional "device"), (address)) -> {
+        "uid": uid,
+        "token": token,
+        "refreshToken": refresh_token,
+        "expiry": expiry
+    },
+    "v1/requestPasswordReset" => request_password_reset("username") -> None,
+    "v1/resetPassword" => reset_password("code", "password") -> None,
+    "v1/verifyEmail" => verify_email("token", "code") -> None,
+    "v1/resendVerification" => resend_verification("token") -> None,
+    "v1/enable2fa" => enable_2fa("token") -> {
+        "secret": secret,
+        "uri": uri
+    },
+    "v1/confirm2fa" => confirm_2fa("token", "code") -> "recoveryCodes",
+    "v1/disable2fa" => disable_2fa("token", "code") -> None,
+
+    // Session endpoints
+    "v1/sessions" => sessions("token") -> "sessions",
+    "v1/revokeSession" => revoke_session("token", "session") -> None,
+    "v1/logoutEverywhere" => logout_everywhere("token") -> None,
 
     // User endpoints
-    "v1/user" => get_user("uid") -> "user",
-    "v1/updateUser" => update_user("token", (optional "displayName"), (optional "email"), (optional "bio")) -> None,
+    "v1/user" => get_user("uid", (optional "token")) -> "user",
+    "v1/userByUsername" => get_user_by_username("username", (optional "token")) -> "user",
+    "v1/searchUsers" => search_users("token", "query", (numeric optional "limit")) -> "users",
+    "v1/updateUser" => update_user("token", (optional "username"), (optional "displayName"), (optional "email"), (optional "bio")) -> None,
+    "v1/setPrivacy" => set_privacy("token", (optional "email"), (optional "bio")) -> None,
+    "v1/setPresence" => set_presence("token", "presence", (optional "status"), (numeric optional "duration")) -> None,
+    "v1/exportData" => export_data("token") -> "file",
+    "v1/deleteAccount" => delete_account("token", "password", (optional "code")) -> None,
+
+    // Friends endpoints
+    "v1/friends" => friends("token") -> "friends",
+    "v1/friendRequest" => friend_request("token", "uid") -> None,
+    "v1/respondFriendRequest" => respond_friend_request("token", "uid", (boolean "accept")) -> None,
+    "v1/block" => block("token", "uid") -> None,
+    "v1/unblock" => unblock("token", "uid") -> None,
 
     // Sets endpoints
     "v1/sets" => get_sets("token") -> "sets",
@@ -38,9 +92,17 @@ declare_endpoints! {
     "v1/createSubset" => create_subset("token", "set", "name") -> "id",
     "v1/updateSet" => update_set("token", "set", (optional "name"), (optional "icon"), (boolean optional "delete")) -> None,
     "v1/updateSubset" => update_subset("token", "subset", (optional "name"), (boolean optional "delete")) -> None,
+    "v1/subsetPermissions" => get_subset_permissions("token", "subset") -> "permissions",
+    "v1/updateSubsetPermissions" => update_subset_permissions("token", "subset", "role", (boolean "view"), (boolean "post")) -> None,
     "v1/joinSet" => join_set("token", "code") -> "id",
     "v1/leaveSet" => leave_set("token", "set") -> None,
     "v1/kick" => kick("token", "set", "uid") -> None,
+    "v1/transferOwnership" => transfer_ownership("token", "set", "uid") -> None,
+
+    // Roles endpoints
+    "v1/roles" => get_roles("token", "set") -> "roles",
+    "v1/createRole" => create_role("token", "set", "name", (numeric "permissions")) -> "id",
+    "v1/assignRole" => assign_role("token", "set", "uid", "role") -> None,
 
     // Invites endpoints
     "v1/invites" => get_invites("token", "set") -> "invites",
@@ -50,13 +112,79 @@ declare_endpoints! {
 
     // Messages endpoints
     "v1/messages" => messages("token", "subset", (optional "before"), (numeric optional "limit")) -> "messages",
-    "v1/sendMessage" => send_message("token", "subset", "message", (optional "attachment.name"), (optional "attachment.data")) -> None,
-    "v1/updateMessage" => update_message("token", "message", (optional "content"), (boolean optional "delete")) -> None,
-    "v1/typing" => set_typing("token", "subset") -> None
+    "v1/thread" => thread("token", "message", (optional "before"), (numeric optional "limit")) -> "messages",
+    "v1/sendMessage" => send_message("token", "subset", "message", (optional "attachment.name"), (optional "attachment.data"), (optional "replyTo"), (optional "thread")) -> None,
+    "v1/updateMessage" => update_message("token", "message", (optional "content"), (boolean optional "delete"), (optional "reason")) -> None,
+    "v1/messageHistory" => message_history("token", "message") -> "revisions",
+    "v1/react" => react("token", "message", "emoji") -> None,
+    "v1/unreact" => unreact("token", "message", "emoji") -> None,
+    "v1/pins" => pins("token", "subset") -> "messages",
+    "v1/pin" => pin("token", "message") -> None,
+    "v1/unpin" => unpin("token", "message") -> None,
+    "v1/search" => search("token", "query", (optional "set"), (optional "subset"), (optional "author"), (boolean optional "hasAttachment"), (numeric optional "after"), (numeric optional "before"), (numeric optional "offset"), (numeric optional "limit")) -> "results",
+    "v1/markRead" => mark_read("token", "subset", (optional "message")) -> None,
+    "v1/typing" => set_typing("token", "subset") -> None,
+
+    // Direct messages endpoints
+    "v1/dms" => dms("token") -> "dms",
+    "v1/createDm" => create_dm("token", (list "users")) -> "id",
+    "v1/dmMessages" => dm_messages("token", "dm", (optional "before"), (numeric optional "limit")) -> "messages",
+    "v1/sendDm" => send_dm("token", "dm", "message", (optional "attachment.name"), (optional "attachment.data")) -> None
 }
 
+/// The rate limits of endpoints which are vulnerable to brute-force attacks or spam.
+///
+/// Each endpoint is limited both per IP address and per account, where the account is identified by the given request parameter.
+/// Accounts identified by `token` are limited by the user the token belongs to, so that a user's sessions share the same limit.
+static RATE_LIMITS: &[(&str, Limit, &str, Limit)] = &[
+    (
+        "v1/login",
+        Limit::new(20, 30),
+        "username",
+        Limit::new(5, 60),
+    ),
+    (
+        "v1/login2fa",
+        Limit::new(20, 30),
+        "challenge",
+        Limit::new(5, 60),
+    ),
+    (
+        "v1/signup",
+        Limit::new(5, 600),
+        "username",
+        Limit::new(3, 60),
+    ),
+    (
+        "v1/requestPasswordReset",
+        Limit::new(5, 600),
+        "username",
+        Limit::new(3, 600),
+    ),
+    (
+        "v1/sendMessage",
+        Limit::new(30, 1),
+        "token",
+        Limit::new(10, 1),
+    ),
+    ("v1/typing", Limit::new(20, 2), "token", Limit::new(5, 2)),
+    ("v1/joinSet", Limit::new(10, 60), "token", Limit::new(5, 60)),
+    (
+        "v1/friendRequest",
+        Limit::new(20, 60),
+        "token",
+        Limit::new(10, 60),
+    ),
+    (
+        "v1/createDm",
+        Limit::new(20, 60),
+        "token",
+        Limit::new(10, 60),
+    ),
+];
+
 /// Represents a function able to handle requests.
-pub type Handler = fn(Arc<State>, Value) -> Value;
+pub type Handler = fn(Arc<State>, Value, SocketAddr) -> Value;
 
 /// Matches the command to the appropriate handler.
 ///
@@ -82,6 +210,62 @@ where
     }
 }
 
+/// Rejects the request if it was made with an expired access token.
+///
+/// This is checked before any handler runs so that every endpoint taking a token reports expiry in the same way.
+pub fn check_token_expiry(state: &State, json: &Value) -> Result<(), String> {
+    match get_string(json, "token") {
+        Ok(token) => state.check_token_expiry(token),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Rejects the request if the client or account has made too many requests to the command recently.
+///
+/// The error contains the number of seconds after which the request can be retried.
+pub fn check_rate_limit(
+    state: &State,
+    command: &str,
+    json: &Value,
+    addr: SocketAddr,
+) -> Result<(), u64> {
+    if let Some((_, ip_limit, key, account_limit)) = RATE_LIMITS
+        .iter()
+        .find(|(endpoint, ..)| *endpoint == command)
+    {
+        state
+            .rate_limiter
+            .check(&format!("{} ip {}", command, addr.ip()), *ip_limit)?;
+
+        let account = match (*key, get_string(json, key)) {
+            ("token", Ok(token)) => state
+                .uid_by_token(token)
+                .ok()
+                .flatten()
+                .map(|uid| format!("uid {}", uid)),
+            (_, Ok(value)) => Some(format!("{} {}", key, value)),
+            (_, Err(_)) => None,
+        };
+
+        if let Some(account) = account {
+            state
+                .rate_limiter
+                .check(&format!("{} {}", command, account), *account_limit)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the error response for a rate-limited request.
+pub fn rate_limited(retry_after: u64) -> Value {
+    json!({
+        "success": false,
+        "error": "Rate limited",
+        "retryAfter": retry_after
+    })
+}
+
 /// Attempts to get a string at the given key from the JSON value.
 pub fn get_string(json: &Value, key: &str) -> Result<String, String> {
     deep_index(json, key)
@@ -90,6 +274,23 @@ pub fn get_string(json: &Value, key: &str) -> Result<String, String> {
         .map(|s| s.to_string())
 }
 
+/// Attempts to get a list of strings at the given key from the JSON value.
+pub fn get_string_list(json: &Value, key: &str) -> Result<Vec<String>, String> {
+    deep_index(json, key)
+        .ok_or_else(|| format!("Missing {}", key))
+        .and_then(|v| v.as_array().ok_or_else(|| format!("Invalid {}", key)))
+        .and_then(|values| {
+            values
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| format!("Invalid {}", key))
+                })
+                .collect()
+        })
+}
+
 /// Attempts to get an integer at the given key from the JSON value.
 pub fn get_int(json: &Value, key: &str) -> Result<u64, String> {
     deep_index(json, key)
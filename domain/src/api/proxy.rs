@@ -0,0 +1,83 @@
+//! Finds the address of the client behind a reverse proxy, so that rate limits and sessions use the client's IP address rather than the proxy's.
+//!
+//! Proxy headers are only believed for connections from the addresses or ranges listed in the comma-separated `EQUION_TRUSTED_PROXIES` environment variable, for example `172.16.0.0/12`, since any other client could set them to whatever it likes.
+
+use humphrey::http::Request;
+
+use once_cell::sync::Lazy;
+
+use std::net::{IpAddr, SocketAddr};
+
+/// The address ranges of trusted proxies, as network addresses and prefix lengths.
+static TRUSTED_PROXIES: Lazy<Vec<(IpAddr, u32)>> = Lazy::new(|| {
+    std::env::var("EQUION_TRUSTED_PROXIES")
+        .map(|proxies| proxies.split(',').filter_map(parse_range).collect())
+        .unwrap_or_default()
+});
+
+/// Gets the address of the client which made the given request.
+///
+/// If the request came through a trusted proxy, the IP address is taken from the `X-Real-IP` header, or otherwise the last address in `X-Forwarded-For`.
+/// The port is always that of the connection, so that connections through the same proxy can still be told apart.
+pub fn client_addr(request: &Request) -> SocketAddr {
+    let peer = peer_addr(request);
+
+    if !TRUSTED_PROXIES
+        .iter()
+        .any(|&range| in_range(peer.ip(), range))
+    {
+        return peer;
+    }
+
+    let ip = request
+        .headers
+        .get("X-Real-IP")
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(request.address.origin_addr);
+
+    SocketAddr::new(ip, peer.port())
+}
+
+/// Gets the address of the connection the given request was made on, ignoring any proxy headers.
+pub fn peer_addr(request: &Request) -> SocketAddr {
+    let ip = request
+        .address
+        .proxies
+        .last()
+        .copied()
+        .unwrap_or(request.address.origin_addr);
+
+    SocketAddr::new(ip, request.address.port)
+}
+
+/// Parses an address range in CIDR notation, or a single address.
+fn parse_range(range: &str) -> Option<(IpAddr, u32)> {
+    let range = range.trim();
+
+    match range.split_once('/') {
+        Some((network, prefix)) => Some((network.parse().ok()?, prefix.parse().ok()?)),
+        None => {
+            let ip: IpAddr = range.parse().ok()?;
+            Some((ip, if ip.is_ipv4() { 32 } else { 128 }))
+        }
+    }
+}
+
+/// Checks whether the given address is in the given range.
+fn in_range(ip: IpAddr, (network, prefix): (IpAddr, u32)) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) ^ u32::from(network))
+                .checked_shr(32_u32.saturating_sub(prefix))
+                .unwrap_or(0)
+                == 0
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            (u128::from(ip) ^ u128::from(network))
+                .checked_shr(128_u32.saturating_sub(prefix))
+                .unwrap_or(0)
+                == 0
+        }
+        _ => false,
+    }
+}
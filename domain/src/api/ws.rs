@@ -1,17 +1,40 @@
 //! Provides the WebSocket interface for routing events.
 
-use crate::api::{error_context, get_string, matcher};
+use crate::api::{
+    check_rate_limit, check_token_expiry, error_context, get_string, matcher, proxy, rate_limited,
+};
 use crate::voice;
-use crate::voice::user::WrappedVoiceUser;
 use crate::State;
 
-use humphrey_ws::{AsyncStream, Message};
+use humphrey::http::Request;
+use humphrey::stream::Stream;
+use humphrey_ws::{async_websocket_handler, AsyncStream, Message, WebsocketStream};
 
 use humphrey_json::prelude::*;
 use humphrey_json::Value;
 
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// Creates the handler for new WebSocket connections, which passes them to the WebSocket app through the given hook.
+///
+/// Since the client address is only known from the headers of the connection request, it is recorded here for use by later messages.
+pub fn connect_handler(
+    hook: Arc<Mutex<Sender<WebsocketStream>>>,
+) -> impl Fn(Request, Stream, Arc<State>) {
+    let handshake = async_websocket_handler(hook);
+
+    move |request: Request, stream: Stream, state: Arc<State>| {
+        state
+            .client_addrs
+            .write()
+            .unwrap()
+            .insert(proxy::peer_addr(&request), proxy::client_addr(&request));
+
+        handshake(request, stream, state)
+    }
+}
 
 /// The core WebSocket request handler.
 ///
@@ -55,26 +78,47 @@ pub fn handler_internal(input: Option<Value>, addr: SocketAddr, state: Arc<State
         Some((v, command, id))
     });
 
+    // Rate limits and endpoints use the address of the client, while subscriptions use the address of the connection
+    let client_addr = state
+        .client_addrs
+        .read()
+        .unwrap()
+        .get(&addr)
+        .copied()
+        .unwrap_or(addr);
+
     let response_body: Value = if let Some((json, command, id)) = json {
         let handler = matcher(&command);
 
-        let mut response = match handler {
-            Some(handler) => handler(state, json),
-            None => match command.as_str() {
-                "v1/subscribe" => subscribe(state, json, addr),
-                "v1/unsubscribe" => unsubscribe(state, json, addr),
-                "v1/connectUserVoice" => voice::ws::connect_user_voice(state, json, addr),
-                "v1/disconnectUserVoice" => voice::ws::disconnect_user_voice(state, json, addr),
-                "v1/connectToVoiceChannel" => {
-                    voice::ws::connect_to_voice_channel(state, json, addr)
-                }
-                "v1/leaveVoiceChannel" => voice::ws::leave_voice_channel(state, json, addr),
-                "v1/ping" => ping(),
-                _ => json!({
-                    "success": false,
-                    "error": "Invalid command"
-                }),
-            },
+        let mut response = if let Err(retry_after) =
+            check_rate_limit(&state, &command, &json, client_addr)
+        {
+            rate_limited(retry_after)
+        } else if let Err(e) = check_token_expiry(&state, &json) {
+            json!({
+                "success": false,
+                "error": e
+            })
+        } else {
+            match handler {
+                Some(handler) => handler(state, json, client_addr),
+                None => match command.as_str() {
+                    "v1/authenticate" => authenticate(state, json, addr),
+                    "v1/subscribe" => subscribe(state, json, addr),
+                    "v1/unsubscribe" => unsubscribe(state, json, addr),
+                    "v1/connectUserVoice" => voice::ws::connect_user_voice(state, json, addr),
+                    "v1/disconnectUserVoice" => voice::ws::disconnect_user_voice(state, json, addr),
+                    "v1/connectToVoiceChannel" => {
+                        voice::ws::connect_to_voice_channel(state, json, addr)
+                    }
+                    "v1/leaveVoiceChannel" => voice::ws::leave_voice_channel(state, json, addr),
+                    "v1/ping" => ping(),
+                    _ => json!({
+                        "success": false,
+                        "error": "Invalid command"
+                    }),
+                },
+            }
         };
 
         if matches!(response, Value::Object(_)) {
@@ -94,6 +138,19 @@ pub fn handler_internal(input: Option<Value>, addr: SocketAddr, state: Arc<State
     response_body
 }
 
+/// Authenticates the connection as the user, without subscribing to any set.
+pub fn authenticate(state: Arc<State>, json: Value, addr: SocketAddr) -> Value {
+    error_context(|| {
+        let token = get_string(&json, "token")?;
+
+        state.authenticate(token, addr)?;
+
+        Ok(json!({
+            "success": true
+        }))
+    })
+}
+
 /// Subscribes the user to events for the specified set.
 pub fn subscribe(state: Arc<State>, json: Value, addr: SocketAddr) -> Value {
     error_context(|| {
@@ -135,31 +192,10 @@ pub fn unsubscribe_all(stream: AsyncStream, state: Arc<State>) {
 
     drop(subscriptions);
 
-    let voice_user = {
-        let online_users = state.voice.online_users.read().unwrap();
-        online_users
-            .values()
-            .find(|u| u.socket_addr == addr)
-            .cloned()
-    };
+    state.connections.write().unwrap().remove(&addr);
+    state.client_addrs.write().unwrap().remove(&addr);
 
-    if let Some(voice_user) = voice_user {
-        if let Some(channel_id) = voice_user.channel_id {
-            if let Ok(user) = state.get_user(&voice_user.uid) {
-                state.voice.leave_voice_channel(&voice_user.uid).ok();
-                state.broadcast_left_vc(
-                    channel_id,
-                    WrappedVoiceUser {
-                        user,
-                        peer_id: voice_user.peer_id,
-                    },
-                );
-            }
-        }
-
-        state.voice.disconnect_user_voice(&voice_user.uid);
-        state.broadcast_user_offline(&voice_user.uid);
-    }
+    state.disconnect_voice(addr);
 }
 
 /// Returns a `v1/pong` event.
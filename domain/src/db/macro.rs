@@ -14,6 +14,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<Option<$ret>, String> {
             use mysql::prelude::Queryable;
 
@@ -37,6 +38,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, String> {
             use mysql::prelude::Queryable;
 
@@ -60,6 +62,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, String> {
             use mysql::prelude::Queryable;
 
@@ -81,6 +84,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, String> {
             use mysql::prelude::Queryable;
 
@@ -104,6 +108,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<$ret, String> {
             use mysql::prelude::Queryable;
 
@@ -125,6 +130,7 @@ macro_rules! db {
         #[doc = "```sql"]
         #[doc = $sql]
         #[doc = "```"]
+        #[allow(clippy::too_many_arguments)]
         pub fn $name(&mut self, $($arg: $ty),*) -> Result<(), String> {
             use mysql::prelude::Queryable;
 
@@ -7,12 +7,24 @@ mod r#macro;
 
 #[cfg(not(test))]
 use crate::server::{
+    account::{ExportedDmMessage, ExportedMembership, ExportedMessage},
+    dms::Dm,
     files::FileResponse,
+    friends::Friend,
     invites::Invite,
-    messages::Message,
-    sets::{Set, Subset},
+    mentions::Mention,
+    messages::{Message, Reply, Revision},
+    reactions::Reaction,
+    roles::Role,
+    sessions::Session,
+    sets::{Set, Subset, SubsetPermission},
     user::User,
 };
+#[cfg(not(test))]
+use crate::util::Token;
+
+#[cfg(not(test))]
+use mysql::Value;
 
 #[cfg(test)]
 pub use crate::tests::mock::MockDatabase as Database;
@@ -59,7 +71,7 @@ impl Database {
 #[cfg(not(test))]
 impl Connection {
     /// Starts a new transaction.
-    pub fn transaction(&mut self) -> Result<Transaction, String> {
+    pub fn transaction(&mut self) -> Result<Transaction<'_>, String> {
         Ok(Transaction {
             inner: self
                 .inner
@@ -92,10 +104,15 @@ impl<'a> Transaction<'a> {
             username: &str,
             password: &str,
             display_name: &str,
-            email: &str,
-            token: &str
+            email: &str
         ) {
-            "INSERT INTO users (id, username, password, display_name, email, token, creation_date) VALUES (?, ?, ?, ?, ?, ?, NOW())"
+            "INSERT INTO users (id, username, password, display_name, email, creation_date) VALUES (?, ?, ?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        select_id_by_username(username: &str) -> Option<String> {
+            first("SELECT id FROM users WHERE username = ?")
         }
     }
 
@@ -106,354 +123,1419 @@ impl<'a> Transaction<'a> {
     }
 
     db! {
-        select_id_by_token(token: &str) -> Option<String> {
-            first("SELECT id FROM users WHERE token = ?")
+        select_id_and_password_by_token(token: Token) -> Option<(String, String)> {
+            first("SELECT users.id, users.password FROM users
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW()")
         }
     }
 
     db! {
-        select_username_by_token(token: &str) -> Option<String> {
-            first("SELECT username FROM users WHERE token = ?")
+        select_id_and_email_by_username(username: &str) -> Option<(String, String)> {
+            first("SELECT id, email FROM users WHERE username = ?")
         }
     }
 
     db! {
-        update_token_by_id(token: &str, id: &str) {
-            "UPDATE users SET token = ? WHERE id = ?"
+        update_user_password(password: &str, uid: &str) {
+            "UPDATE users SET password = ? WHERE id = ?"
         }
     }
 
     db! {
-        update_invalidate_token(token: &str) {
-            "UPDATE users SET token = NULL WHERE token = ?"
+        insert_password_reset(uid: &str, token: Token, lifetime: usize) {
+            "REPLACE INTO password_resets (user_id, token, expiry) VALUES (?, ?, DATE_ADD(NOW(), INTERVAL ? MINUTE))"
         }
     }
 
     db! {
-        select_file_by_id(id: &str) -> Option<FileResponse> {
-            first("SELECT id, name, content, owner FROM files WHERE id = ?") => FileResponse::from_row
+        select_password_reset(token: Token) -> Option<(String, bool)> {
+            first("SELECT user_id, expiry <= NOW() FROM password_resets WHERE token = ?")
         }
     }
 
     db! {
-        insert_file(id: &str, name: &str, content: Vec<u8>, owner: &str) {
-            "INSERT INTO files (id, name, content, owner) VALUES (?, ?, ?, ?)"
+        delete_password_reset(uid: &str) {
+            "DELETE FROM password_resets WHERE user_id = ?"
         }
     }
 
     db! {
-        select_username_by_subset_membership_token(token: &str, subset: &str) -> Option<String> {
-            first("SELECT users.username FROM users
-                JOIN memberships ON users.id = memberships.user_id
+        select_totp_secret(uid: &str) -> Option<(String, Option<u64>)> {
+            first("SELECT totp_secret, totp_counter FROM users WHERE id = ? AND totp_enabled AND totp_secret IS NOT NULL")
+        }
+    }
+
+    db! {
+        select_pending_totp_secret(uid: &str) -> Option<String> {
+            first("SELECT totp_secret FROM users WHERE id = ? AND NOT totp_enabled AND totp_secret IS NOT NULL")
+        }
+    }
+
+    db! {
+        update_user_totp(secret: &Option<String>, enabled: bool, uid: &str) {
+            "UPDATE users SET totp_secret = ?, totp_enabled = ?, totp_counter = NULL WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_totp_counter(counter: u64, uid: &str) {
+            "UPDATE users SET totp_counter = ? WHERE id = ?"
+        }
+    }
+
+    db! {
+        insert_recovery_code(uid: &str, token: Token) {
+            "INSERT INTO recovery_codes (user_id, token) VALUES (?, ?)"
+        }
+    }
+
+    db! {
+        select_recovery_code(uid: &str, token: Token) -> bool {
+            first("SELECT 1 FROM recovery_codes WHERE user_id = ? AND token = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        delete_recovery_code(uid: &str, token: Token) {
+            "DELETE FROM recovery_codes WHERE user_id = ? AND token = ?"
+        }
+    }
+
+    db! {
+        delete_recovery_codes(uid: &str) {
+            "DELETE FROM recovery_codes WHERE user_id = ?"
+        }
+    }
+
+    db! {
+        insert_login_challenge(token: Token, uid: &str, device: &Option<String>, ip: &str, lifetime: usize) {
+            "INSERT INTO login_challenges (token, user_id, device, ip, expiry) VALUES (?, ?, ?, ?, DATE_ADD(NOW(), INTERVAL ? MINUTE))"
+        }
+    }
+
+    db! {
+        select_login_challenge(token: Token) -> Option<(String, Option<String>, String, bool)> {
+            first("SELECT user_id, device, ip, expiry <= NOW() FROM login_challenges WHERE token = ?")
+        }
+    }
+
+    db! {
+        delete_login_challenge(token: Token) {
+            "DELETE FROM login_challenges WHERE token = ?"
+        }
+    }
+
+    db! {
+        delete_expired_login_challenges() {
+            "DELETE FROM login_challenges WHERE expiry <= NOW()"
+        }
+    }
+
+    db! {
+        select_export_memberships(uid: &str) -> Vec<ExportedMembership> {
+            "SELECT sets.id, sets.name, roles.name, memberships.creation_date FROM memberships
                 JOIN sets ON memberships.set_id = sets.id
-                JOIN subsets ON sets.id = subsets.set_id
-                WHERE users.token = ? AND subsets.id = ?")
+                JOIN roles ON memberships.role = roles.id
+                WHERE memberships.user_id = ?
+                ORDER BY memberships.creation_date" => ExportedMembership::from_row
         }
     }
 
     db! {
-        select_messages_before(subset: &str, before: &str, limit: usize) -> Vec<Message> {
-            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name FROM messages
-                JOIN users ON messages.sender = users.id
+        select_export_messages(uid: &str) -> Vec<ExportedMessage> {
+            "SELECT messages.id, subsets.set_id, messages.subset, messages.content, messages.send_time FROM messages
                 JOIN subsets ON messages.subset = subsets.id
-                LEFT JOIN files ON messages.attachment = files.id
-                WHERE subsets.id = ? AND messages.send_time < (
-                    SELECT send_time FROM messages WHERE id = ?
-                )
-                ORDER BY messages.send_time DESC
-                LIMIT ?" => Message::from_row
+                WHERE messages.sender = ?
+                ORDER BY messages.send_time" => ExportedMessage::from_row
         }
     }
 
     db! {
-        select_messages(subset: &str, limit: usize) -> Vec<Message> {
-            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name FROM messages
-                JOIN users ON messages.sender = users.id
-                JOIN subsets ON messages.subset = subsets.id
-                LEFT JOIN files ON messages.attachment = files.id
-                WHERE subsets.id = ?
-                ORDER BY messages.send_time DESC
-                LIMIT ?" => Message::from_row
+        select_export_dm_messages(uid: &str) -> Vec<ExportedDmMessage> {
+            "SELECT id, dm_id, content, send_time FROM dm_messages
+                WHERE sender = ?
+                ORDER BY send_time" => ExportedDmMessage::from_row
         }
     }
 
     db! {
-        select_message_by_id_and_token(message: &str, token: &str) -> Option<Message> {
-            first("SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name FROM messages
-                JOIN users ON messages.sender = users.id
-                LEFT JOIN files ON messages.attachment = files.id
-                WHERE messages.id = ? AND users.token = ?") => Message::from_row
+        select_files_by_owner(uid: &str) -> Vec<FileResponse> {
+            "SELECT id, name, content, owner FROM files WHERE owner = ?" => FileResponse::from_row
         }
     }
 
     db! {
-        select_subset_metadata(token: &str, subset: &str) -> Option<(String, String, String, Option<String>)> {
-            first("SELECT sets.id, users.id, users.display_name, users.image FROM sets
-                JOIN memberships ON sets.id = memberships.set_id
-                JOIN users ON memberships.user_id = users.id
-                JOIN subsets ON sets.id = subsets.set_id
-                WHERE users.token = ? AND subsets.id = ?")
+        select_set_successor(set: &str, uid: &str) -> Option<String> {
+            first("SELECT memberships.user_id FROM memberships
+                JOIN roles ON memberships.role = roles.id
+                WHERE memberships.set_id = ? AND memberships.user_id != ?
+                ORDER BY roles.kind = 'moderator' DESC, memberships.creation_date ASC")
         }
     }
 
     db! {
-        insert_message(id: &str, content: &str, subset: &str, sender: &str, attachment_id: &Option<String>) {
-            "INSERT INTO messages (id, content, subset, sender, send_time, attachment) VALUES (?, ?, ?, ?, NOW(), ?)"
+        update_messages_sender(new_uid: &str, uid: &str) {
+            "UPDATE messages SET sender = ? WHERE sender = ?"
         }
     }
 
     db! {
-        select_set_by_subset(subset: &str) -> Option<String> {
-            first("SELECT set_id FROM subsets WHERE id = ?")
+        update_messages_deleted_by(new_uid: &str, uid: &str) {
+            "UPDATE messages SET deleted_by = ? WHERE deleted_by = ?"
         }
     }
 
     db! {
-        select_message_set_and_subset(message: &str) -> Option<(String, String)> {
-            first("SELECT subsets.set_id, subsets.id FROM subsets
-                JOIN messages ON subsets.id = messages.subset
-                WHERE messages.id = ?")
+        update_pins_pinned_by(new_uid: &str, uid: &str) {
+            "UPDATE pins SET pinned_by = ? WHERE pinned_by = ?"
         }
     }
 
     db! {
-        select_sets_by_token(token: &str) -> Vec<Set> {
-            "SELECT sets.id, sets.name, sets.icon, memberships.admin FROM sets
-                JOIN memberships ON sets.id = memberships.set_id
-                JOIN users ON memberships.user_id = users.id
-                WHERE users.token = ?
-                ORDER BY memberships.creation_date ASC" => Set::from_row
+        update_mentions_user(new_uid: &str, uid: &str) {
+            "UPDATE mentions SET user_id = ? WHERE user_id = ?"
         }
     }
 
     db! {
-        select_subsets_by_set(set: &str) -> Vec<Subset> {
-            "SELECT id, name FROM subsets WHERE set_id = ? ORDER BY creation_date ASC" => Subset::from_row
+        update_dm_messages_sender(new_uid: &str, uid: &str) {
+            "UPDATE dm_messages SET sender = ? WHERE sender = ?"
         }
     }
 
     db! {
-        select_users_by_set(set: &str) -> Vec<User> {
-            "SELECT users.id, username, display_name, email, image, bio FROM users
-                JOIN memberships ON users.id = memberships.user_id
-                WHERE memberships.set_id = ?
-                ORDER BY display_name ASC" => User::from_row
+        update_files_owner(new_uid: &str, uid: &str) {
+            "UPDATE files SET owner = ? WHERE owner = ?"
         }
     }
 
     db! {
-        select_membership(token: &str, set: &str) -> Option<(bool, String)> {
-            first("SELECT memberships.admin, users.id FROM memberships
-                JOIN users ON users.id = memberships.user_id
-                WHERE users.token = ? AND memberships.set_id = ?")
+        delete_user_reactions(uid: &str) {
+            "DELETE FROM reactions WHERE user_id = ?"
         }
     }
 
     db! {
-        select_set_by_id_and_token(token: &str, id: &str) -> Option<Set> {
-            first("SELECT sets.id, sets.name, sets.icon, memberships.admin FROM sets
-                JOIN memberships ON sets.id = memberships.set_id
-                JOIN users ON memberships.user_id = users.id
-                WHERE users.token = ? AND sets.id = ?") => Set::from_row
+        delete_user_read_markers(uid: &str) {
+            "DELETE FROM read_markers WHERE user_id = ?"
         }
     }
 
     db! {
-        insert_set(id: &str, name: &str, icon: &str) {
-            "INSERT INTO sets (id, name, icon, creation_date) VALUES (?, ?, ?, NOW())"
+        delete_user_memberships(uid: &str) {
+            "DELETE FROM memberships WHERE user_id = ?"
         }
     }
 
     db! {
-        insert_membership(id: &str, user_id: &str, set_id: &str, admin: bool) {
-            "INSERT INTO memberships (id, user_id, set_id, admin, creation_date) VALUES (?, ?, ?, ?, NOW())"
+        delete_user_dm_participants(uid: &str) {
+            "DELETE FROM dm_participants WHERE user_id = ?"
         }
     }
 
     db! {
-        insert_subset(id: &str, name: &str, set_id: &str) {
-            "INSERT INTO subsets (id, name, set_id, creation_date) VALUES (?, ?, ?, NOW())"
+        delete_all_friends_for_user(uid: &str) {
+            "DELETE FROM friends WHERE ? IN (user_id, friend_id)"
         }
     }
 
     db! {
-        select_subset_metadata_for_update(token: &str, subset: &str) -> Option<(bool, String, String, String)> {
-            first("SELECT memberships.admin, subsets.set_id, subsets.name, users.id FROM memberships
-                JOIN users ON memberships.user_id = users.id
-                JOIN subsets ON memberships.set_id = subsets.set_id
-                WHERE users.token = ? AND subsets.id = ?")
+        delete_all_blocks_for_user(uid: &str) {
+            "DELETE FROM blocks WHERE ? IN (user_id, blocked_id)"
         }
     }
 
     db! {
-        delete_subset_messages(subset: &str) {
-            "DELETE FROM messages WHERE subset = ?"
+        delete_user_login_challenges(uid: &str) {
+            "DELETE FROM login_challenges WHERE user_id = ?"
         }
     }
 
     db! {
-        delete_subset(subset: &str) {
-            "DELETE FROM subsets WHERE id = ?"
+        insert_data_export(token: Token, uid: &str, content: Vec<u8>, lifetime: usize) {
+            "INSERT INTO data_exports (token, user_id, content, expiry) VALUES (?, ?, ?, DATE_ADD(NOW(), INTERVAL ? MINUTE))"
         }
     }
 
     db! {
-        delete_message(message: &str) {
-            "DELETE FROM messages WHERE id = ?"
+        select_data_export(token: Token) -> Option<(String, Vec<u8>, bool)> {
+            first("SELECT user_id, content, expiry <= NOW() FROM data_exports WHERE token = ?")
         }
     }
 
     db! {
-        update_set_name(name: &str, set: &str) {
-            "UPDATE sets SET name = ? WHERE id = ?"
+        delete_data_export(token: Token) {
+            "DELETE FROM data_exports WHERE token = ?"
         }
     }
 
     db! {
-        update_set_icon(icon: &str, set: &str) {
-            "UPDATE sets SET icon = ? WHERE id = ?"
+        delete_user_data_exports(uid: &str) {
+            "DELETE FROM data_exports WHERE user_id = ?"
         }
     }
 
     db! {
-        update_subset_name(name: &str, subset: &str) {
-            "UPDATE subsets SET name = ? WHERE id = ?"
+        delete_expired_data_exports() {
+            "DELETE FROM data_exports WHERE expiry <= NOW()"
         }
     }
 
     db! {
-        select_user_by_token(token: &str) -> Option<User> {
-            first("SELECT id, username, display_name, email, image, bio FROM users WHERE token = ?") => User::from_row
+        delete_user(uid: &str) {
+            "DELETE FROM users WHERE id = ?"
         }
     }
 
     db! {
-        select_user_by_uid(uid: &str) -> Option<User> {
-            first("SELECT id, username, display_name, email, image, bio FROM users WHERE id = ?") => User::from_row
+        select_email_verified(uid: &str) -> bool {
+            first("SELECT email_verified FROM users WHERE id = ?") => |result: Option<bool>| {
+                result.unwrap_or(false)
+            }
         }
     }
 
     db! {
-        select_user_has_membership(uid: &str, set: &str) -> bool {
-            first("SELECT 1 FROM memberships WHERE user_id = ? AND set_id = ?") => |has_membership: Option<u8>| {
-                has_membership.unwrap_or(0) != 0
+        update_user_email_verified(uid: &str) {
+            "UPDATE users SET email_verified = TRUE WHERE id = ?"
+        }
+    }
+
+    db! {
+        insert_email_verification(uid: &str, token: Token, lifetime: usize) {
+            "REPLACE INTO email_verifications (user_id, token, expiry) VALUES (?, ?, DATE_ADD(NOW(), INTERVAL ? MINUTE))"
+        }
+    }
+
+    db! {
+        select_email_verification(token: Token) -> Option<(String, bool)> {
+            first("SELECT user_id, expiry <= NOW() FROM email_verifications WHERE token = ?")
+        }
+    }
+
+    db! {
+        delete_email_verification(uid: &str) {
+            "DELETE FROM email_verifications WHERE user_id = ?"
+        }
+    }
+
+    db! {
+        select_id_by_token(token: Token) -> Option<String> {
+            first("SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW()")
+        }
+    }
+
+    db! {
+        select_username_by_token(token: Token) -> Option<String> {
+            first("SELECT users.username FROM users
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW()")
+        }
+    }
+
+    db! {
+        insert_session(id: &str, token: Token, refresh_token: Token, uid: &str, device: &Option<String>, ip: &str, lifetime: usize, refresh_lifetime: usize) {
+            "INSERT INTO sessions (id, token, refresh_token, user_id, device, ip, creation_date, last_used, expiry, refresh_expiry)
+                VALUES (?, ?, ?, ?, ?, ?, NOW(), NOW(), DATE_ADD(NOW(), INTERVAL ? MINUTE), DATE_ADD(NOW(), INTERVAL ? MINUTE))"
+        }
+    }
+
+    db! {
+        select_is_token_expired(token: Token) -> bool {
+            first("SELECT 1 FROM sessions WHERE token = ? AND expiry <= NOW()") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
             }
         }
     }
 
     db! {
-        delete_membership(user_id: &str, set_id: &str) {
-            "DELETE FROM memberships WHERE user_id = ? AND set_id = ?"
+        select_session_by_refresh_token(refresh_token: Token) -> Option<(String, String, bool)> {
+            first("SELECT id, user_id, refresh_expiry <= NOW() FROM sessions WHERE refresh_token = ?")
         }
     }
 
     db! {
-        select_user_set_ids(user_id: &str) -> Vec<String> {
-            "SELECT set_id FROM memberships WHERE user_id = ?"
+        update_session_tokens(token: Token, refresh_token: Token, lifetime: usize, refresh_lifetime: usize, id: &str) {
+            "UPDATE sessions SET token = ?, refresh_token = ?, last_used = NOW(),
+                expiry = DATE_ADD(NOW(), INTERVAL ? MINUTE), refresh_expiry = DATE_ADD(NOW(), INTERVAL ? MINUTE)
+                WHERE id = ?"
         }
     }
 
     db! {
-        update_user_display_name(display_name: &str, token: &str) {
-            "UPDATE users SET display_name = ? WHERE token = ?"
+        select_session_id_by_token(token: Token) -> Option<String> {
+            first("SELECT id FROM sessions WHERE token = ? AND expiry > NOW()")
         }
     }
 
     db! {
-        update_user_email(email: &str, token: &str) {
-            "UPDATE users SET email = ? WHERE token = ?"
+        select_sessions_by_user(token: Token, uid: &str) -> Vec<Session> {
+            "SELECT id, device, ip, creation_date, last_used, token = ? FROM sessions
+                WHERE user_id = ?
+                ORDER BY last_used DESC" => Session::from_row
         }
     }
 
     db! {
-        update_user_bio(bio: &str, token: &str) {
-            "UPDATE users SET bio = ? WHERE token = ?"
+        select_session_owner(id: &str) -> Option<String> {
+            first("SELECT user_id FROM sessions WHERE id = ?")
         }
     }
 
     db! {
-        update_user_image(image: &str, token: &str) {
-            "UPDATE users SET image = ? WHERE token = ?"
+        update_session_last_used(token: Token) {
+            "UPDATE sessions SET last_used = NOW() WHERE token = ?"
         }
     }
 
     db! {
-        update_message(message: &str, id: &str) {
-            "UPDATE messages SET content = ? WHERE id = ?"
+        delete_session(id: &str) {
+            "DELETE FROM sessions WHERE id = ?"
         }
     }
 
     db! {
-        select_invites_by_set(set: &str) -> Vec<Invite> {
-            "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
-            JOIN sets ON sets.id = invites.set_id
-            WHERE set_id = ? AND (expiry_date > NOW() OR expiry_date IS NULL)" => Invite::from_row
+        delete_session_by_token(token: Token) {
+            "DELETE FROM sessions WHERE token = ?"
         }
     }
 
     db! {
-        select_invite_by_code(code: &str) -> Option<Invite> {
-            first(
-                "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
-                JOIN sets ON sets.id = invites.set_id WHERE code = ?"
-            ) => Invite::from_row
+        delete_user_sessions(uid: &str) {
+            "DELETE FROM sessions WHERE user_id = ?"
         }
     }
 
     db! {
-        select_invite_by_id(id: &str) -> Option<Invite> {
-            first(
-                "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
-                JOIN sets ON sets.id = invites.set_id WHERE invites.id = ?"
-            ) => Invite::from_row
+        delete_expired_sessions() {
+            "DELETE FROM sessions WHERE refresh_expiry <= NOW()"
         }
     }
 
     db! {
-        insert_invite(id: &str, set: &str, code: &str) {
-            "INSERT INTO invites (id, set_id, code, creation_date, expiry_date) VALUES (?, ?, ?, NOW(), NULL)"
+        select_file_by_id(id: &str) -> Option<FileResponse> {
+            first("SELECT id, name, content, owner FROM files WHERE id = ?") => FileResponse::from_row
         }
     }
 
     db! {
-        insert_invite_with_duration(id: &str, set: &str, code: &str, minutes: usize) {
-            "INSERT INTO invites (id, set_id, code, creation_date, expiry_date) VALUES (?, ?, ?, NOW(), DATE_ADD(NOW(), INTERVAL ? MINUTE))"
+        delete_file(id: &str) {
+            "DELETE FROM files WHERE id = ?"
         }
     }
 
     db! {
-        delete_invite(id: &str) {
-            "DELETE FROM invites WHERE id = ?"
+        insert_file(id: &str, name: &str, content: Vec<u8>, owner: &str) {
+            "INSERT INTO files (id, name, content, owner) VALUES (?, ?, ?, ?)"
         }
     }
 
     db! {
-        increment_invite_uses(id: &str) {
-            "UPDATE invites SET uses = uses + 1 WHERE id = ?"
+        select_username_by_subset_membership_token(token: Token, subset: &str, manage_subsets: u64) -> Option<String> {
+            first("SELECT users.username FROM users
+                JOIN memberships ON users.id = memberships.user_id
+                JOIN roles ON memberships.role = roles.id
+                JOIN subsets ON memberships.set_id = subsets.set_id
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW() AND subsets.id = ?
+                AND (roles.permissions & ? != 0 OR COALESCE(subset_permissions.can_view, 1) = 1)")
         }
     }
 
     db! {
-        delete_set_messages(set: &str) {
-            "DELETE messages FROM messages JOIN subsets ON messages.subset = subsets.id WHERE subsets.set_id = ?"
+        select_messages_before(subset: &str, before: &str, limit: usize) -> Vec<Message> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM messages
+                JOIN users ON messages.sender = users.id
+                JOIN subsets ON messages.subset = subsets.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE subsets.id = ? AND messages.thread IS NULL
+                AND (messages.deleted_by IS NULL OR EXISTS (
+                    SELECT 1 FROM messages AS replies WHERE replies.thread = messages.id AND replies.deleted_by IS NULL
+                ))
+                AND messages.send_time < (
+                    SELECT send_time FROM messages WHERE id = ?
+                )
+                ORDER BY messages.send_time DESC
+                LIMIT ?" => Message::from_row
         }
     }
 
     db! {
-        delete_set_subsets(set: &str) {
-            "DELETE FROM subsets WHERE set_id = ?"
+        select_messages(subset: &str, limit: usize) -> Vec<Message> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM messages
+                JOIN users ON messages.sender = users.id
+                JOIN subsets ON messages.subset = subsets.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE subsets.id = ? AND messages.thread IS NULL
+                AND (messages.deleted_by IS NULL OR EXISTS (
+                    SELECT 1 FROM messages AS replies WHERE replies.thread = messages.id AND replies.deleted_by IS NULL
+                ))
+                ORDER BY messages.send_time DESC
+                LIMIT ?" => Message::from_row
         }
     }
 
     db! {
-        delete_set_invites(set: &str) {
-            "DELETE FROM invites WHERE set_id = ?"
+        select_thread_messages_before(thread: &str, before: &str, limit: usize) -> Vec<Message> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM messages
+                JOIN users ON messages.sender = users.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE messages.thread = ? AND messages.deleted_by IS NULL AND messages.send_time < (
+                    SELECT send_time FROM messages WHERE id = ?
+                )
+                ORDER BY messages.send_time DESC
+                LIMIT ?" => Message::from_row
         }
     }
 
     db! {
-        delete_set_memberships(set: &str) {
-            "DELETE FROM memberships WHERE set_id = ?"
+        select_thread_messages(thread: &str, limit: usize) -> Vec<Message> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM messages
+                JOIN users ON messages.sender = users.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE messages.thread = ? AND messages.deleted_by IS NULL
+                ORDER BY messages.send_time DESC
+                LIMIT ?" => Message::from_row
+        }
+    }
+
+    db! {
+        select_search_messages(uid: &str, query: &str, manage_subsets: u64, set: &Option<String>, subset: &Option<String>, author: &Option<String>, has_attachment: bool, after: &Option<u64>, before: &Option<u64>, limit: usize, offset: usize) -> Vec<(Message, String, String)> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by, subsets.set_id, subsets.id FROM messages
+                JOIN users ON messages.sender = users.id
+                JOIN subsets ON messages.subset = subsets.id
+                JOIN memberships ON memberships.set_id = subsets.set_id AND memberships.user_id = ?
+                JOIN roles ON memberships.role = roles.id
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE MATCH(messages.content) AGAINST (? IN BOOLEAN MODE)
+                AND messages.deleted_by IS NULL
+                AND (roles.permissions & ? != 0 OR COALESCE(subset_permissions.can_view, 1) = 1)
+                AND subsets.set_id = COALESCE(?, subsets.set_id)
+                AND subsets.id = COALESCE(?, subsets.id)
+                AND messages.sender = COALESCE(?, messages.sender)
+                AND (? = FALSE OR messages.attachment IS NOT NULL)
+                AND messages.send_time >= COALESCE(FROM_UNIXTIME(?), messages.send_time)
+                AND messages.send_time < COALESCE(FROM_UNIXTIME(?), DATE_ADD(messages.send_time, INTERVAL 1 SECOND))
+                ORDER BY messages.send_time DESC
+                LIMIT ? OFFSET ?" => |mut row: mysql::Row| {
+                    // The message row is followed by its set and subset, which is more columns than a tuple can be converted from
+                    let location = (row.take(12).unwrap(), row.take(13).unwrap());
+
+                    let message = Message::from_row((
+                        row.take(0).unwrap(),
+                        row.take(1).unwrap(),
+                        row.take(2).unwrap(),
+                        row.take(3).unwrap(),
+                        row.take(4).unwrap(),
+                        row.take(5).unwrap(),
+                        row.take(6).unwrap(),
+                        row.take(7).unwrap(),
+                        row.take(8).unwrap(),
+                        row.take(9).unwrap(),
+                        row.take(10).unwrap(),
+                        row.take(11).unwrap(),
+                    ));
+
+                    (message, location.0, location.1)
+                }
+        }
+    }
+
+    db! {
+        select_thread_summaries(messages: &str) -> Vec<(String, u64, Value)> {
+            "SELECT messages.thread, COUNT(*), MAX(messages.send_time) FROM messages
+                JOIN JSON_TABLE(?, '$[*]' COLUMNS (id CHAR(36) PATH '$')) AS ids ON messages.thread = ids.id
+                WHERE messages.deleted_by IS NULL
+                GROUP BY messages.thread"
+        }
+    }
+
+    db! {
+        select_reply_previews(messages: &str) -> Vec<Reply> {
+            "SELECT messages.id, users.display_name, messages.content FROM messages
+                JOIN JSON_TABLE(?, '$[*]' COLUMNS (id CHAR(36) PATH '$')) AS ids ON messages.id = ids.id
+                JOIN users ON messages.sender = users.id
+                WHERE messages.deleted_by IS NULL" => Reply::from_row
+        }
+    }
+
+    db! {
+        select_reactions_by_messages(uid: &str, messages: &str) -> Vec<(String, Reaction)> {
+            "SELECT reactions.message_id, reactions.emoji, COUNT(*), MAX(reactions.user_id = ?) FROM reactions
+                JOIN JSON_TABLE(?, '$[*]' COLUMNS (id CHAR(36) PATH '$')) AS ids ON reactions.message_id = ids.id
+                GROUP BY reactions.message_id, reactions.emoji
+                ORDER BY MIN(reactions.creation_date) ASC" => |row: (String, String, u64, bool)| {
+                (row.0, Reaction::from_row((row.1, row.2, row.3)))
+            }
+        }
+    }
+
+    db! {
+        select_mentions_by_messages(messages: &str) -> Vec<(String, Mention)> {
+            "SELECT mentions.message_id, mentions.kind, mentions.user_id FROM mentions
+                JOIN JSON_TABLE(?, '$[*]' COLUMNS (id CHAR(36) PATH '$')) AS ids ON mentions.message_id = ids.id
+                ORDER BY mentions.position ASC" => |row: (String, String, Option<String>)| {
+                (row.0, Mention::from_row((row.1, row.2)))
+            }
+        }
+    }
+
+    db! {
+        select_delete_reasons(messages: &str) -> Vec<(String, String)> {
+            "SELECT messages.id, messages.delete_reason FROM messages
+                JOIN JSON_TABLE(?, '$[*]' COLUMNS (id CHAR(36) PATH '$')) AS ids ON messages.id = ids.id
+                WHERE messages.delete_reason IS NOT NULL"
+        }
+    }
+
+    db! {
+        select_message_subset_and_thread(message: &str) -> Option<(String, Option<String>)> {
+            first("SELECT subset, thread FROM messages WHERE id = ? AND deleted_by IS NULL")
+        }
+    }
+
+    db! {
+        select_message_by_id(message: &str) -> Option<Message> {
+            first("SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM messages
+                JOIN users ON messages.sender = users.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE messages.id = ? AND messages.deleted_by IS NULL") => Message::from_row
+        }
+    }
+
+    db! {
+        select_subset_metadata(token: Token, subset: &str, manage_subsets: u64) -> Option<(String, String, String, Option<String>)> {
+            first("SELECT subsets.set_id, users.id, users.display_name, users.image FROM subsets
+                JOIN memberships ON subsets.set_id = memberships.set_id
+                JOIN users ON memberships.user_id = users.id
+                JOIN roles ON memberships.role = roles.id
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW() AND subsets.id = ?
+                AND (roles.permissions & ? != 0 OR COALESCE(subset_permissions.can_post, 1) = 1)")
+        }
+    }
+
+    db! {
+        insert_message(id: &str, content: &str, subset: &str, sender: &str, attachment_id: &Option<String>, reply_to: &Option<String>, thread: &Option<String>) {
+            "INSERT INTO messages (id, content, subset, sender, send_time, attachment, reply_to, thread) VALUES (?, ?, ?, ?, NOW(), ?, ?, ?)"
+        }
+    }
+
+    db! {
+        select_reply_preview(message: &str) -> Option<Reply> {
+            first("SELECT messages.id, users.display_name, messages.content FROM messages
+                JOIN users ON messages.sender = users.id
+                WHERE messages.id = ? AND messages.deleted_by IS NULL") => Reply::from_row
+        }
+    }
+
+    db! {
+        select_set_by_subset(subset: &str) -> Option<String> {
+            first("SELECT set_id FROM subsets WHERE id = ?")
+        }
+    }
+
+    db! {
+        select_message_set_and_subset(message: &str) -> Option<(String, String)> {
+            first("SELECT subsets.set_id, subsets.id FROM subsets
+                JOIN messages ON subsets.id = messages.subset
+                WHERE messages.id = ?")
+        }
+    }
+
+    db! {
+        select_sets_by_token(token: Token) -> Vec<Set> {
+            "SELECT sets.id, sets.name, sets.icon, sets.owner, roles.id, roles.permissions FROM sets
+                JOIN memberships ON sets.id = memberships.set_id
+                JOIN roles ON memberships.role = roles.id
+                JOIN users ON memberships.user_id = users.id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW()
+                ORDER BY memberships.creation_date ASC" => Set::from_row
+        }
+    }
+
+    db! {
+        select_subsets_by_set_and_role(manage_subsets: u64, role: &str, set: &str) -> Vec<Subset> {
+            "SELECT subsets.id, subsets.name, roles.manages_subsets OR COALESCE(subset_permissions.can_post, 1) = 1 FROM subsets
+                JOIN (SELECT id, permissions & ? != 0 AS manages_subsets FROM roles WHERE id = ?) AS roles
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                WHERE subsets.set_id = ? AND (roles.manages_subsets OR COALESCE(subset_permissions.can_view, 1) = 1)
+                ORDER BY subsets.creation_date ASC" => Subset::from_row
+        }
+    }
+
+    db! {
+        select_user_can_post_in_subset(uid: &str, subset: &str, manage_subsets: u64) -> bool {
+            first("SELECT 1 FROM memberships
+                JOIN roles ON memberships.role = roles.id
+                JOIN subsets ON memberships.set_id = subsets.set_id
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                WHERE memberships.user_id = ? AND subsets.id = ?
+                AND (roles.permissions & ? != 0 OR COALESCE(subset_permissions.can_post, 1) = 1)") => |can_post: Option<u8>| {
+                can_post.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        select_user_ids_by_subset_access(subset: &str, manage_subsets: u64) -> Vec<String> {
+            "SELECT memberships.user_id FROM memberships
+                JOIN roles ON memberships.role = roles.id
+                JOIN subsets ON memberships.set_id = subsets.set_id
+                LEFT JOIN subset_permissions ON subset_permissions.subset_id = subsets.id AND subset_permissions.role_id = roles.id
+                WHERE subsets.id = ? AND (roles.permissions & ? != 0 OR COALESCE(subset_permissions.can_view, 1) = 1)"
+        }
+    }
+
+    db! {
+        select_subset_permissions(subset: &str) -> Vec<SubsetPermission> {
+            "SELECT role_id, can_view, can_post FROM subset_permissions WHERE subset_id = ?" => SubsetPermission::from_row
+        }
+    }
+
+    db! {
+        insert_subset_permission(id: &str, subset: &str, role: &str, can_view: bool, can_post: bool) {
+            "INSERT INTO subset_permissions (id, subset_id, role_id, can_view, can_post) VALUES (?, ?, ?, ?, ?)"
+        }
+    }
+
+    db! {
+        delete_subset_permission(subset: &str, role: &str) {
+            "DELETE FROM subset_permissions WHERE subset_id = ? AND role_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_permissions(subset: &str) {
+            "DELETE FROM subset_permissions WHERE subset_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_subset_permissions(set: &str) {
+            "DELETE subset_permissions FROM subset_permissions JOIN subsets ON subset_permissions.subset_id = subsets.id WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        select_users_by_set(set: &str) -> Vec<User> {
+            "SELECT users.id, username, display_name, email, image, bio, presence, IF(status_expiry IS NULL OR status_expiry > NOW(), status, NULL), email_visibility, bio_visibility FROM users
+                JOIN memberships ON users.id = memberships.user_id
+                WHERE memberships.set_id = ?
+                ORDER BY display_name ASC" => User::from_row
+        }
+    }
+
+    db! {
+        select_membership(token: Token, set: &str) -> Option<(u64, String)> {
+            first("SELECT roles.permissions, users.id FROM memberships
+                JOIN users ON users.id = memberships.user_id
+                JOIN roles ON memberships.role = roles.id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW() AND memberships.set_id = ?")
+        }
+    }
+
+    db! {
+        select_set_by_id_and_token(token: Token, id: &str) -> Option<Set> {
+            first("SELECT sets.id, sets.name, sets.icon, sets.owner, roles.id, roles.permissions FROM sets
+                JOIN memberships ON sets.id = memberships.set_id
+                JOIN roles ON memberships.role = roles.id
+                JOIN users ON memberships.user_id = users.id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW() AND sets.id = ?") => Set::from_row
+        }
+    }
+
+    db! {
+        insert_set(id: &str, name: &str, icon: &str, owner: &str) {
+            "INSERT INTO sets (id, name, icon, owner, creation_date) VALUES (?, ?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        select_set_owner(set: &str) -> Option<String> {
+            first("SELECT owner FROM sets WHERE id = ? AND owner IS NOT NULL")
+        }
+    }
+
+    db! {
+        update_set_owner(owner: &str, set: &str) {
+            "UPDATE sets SET owner = ? WHERE id = ?"
+        }
+    }
+
+    db! {
+        insert_membership(id: &str, user_id: &str, set_id: &str, role: &str) {
+            "INSERT INTO memberships (id, user_id, set_id, role, creation_date) VALUES (?, ?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        insert_subset(id: &str, name: &str, set_id: &str) {
+            "INSERT INTO subsets (id, name, set_id, creation_date) VALUES (?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        select_subset_metadata_for_update(token: Token, subset: &str) -> Option<(u64, String, String, String)> {
+            first("SELECT roles.permissions, subsets.set_id, subsets.name, users.id FROM memberships
+                JOIN users ON memberships.user_id = users.id
+                JOIN roles ON memberships.role = roles.id
+                JOIN subsets ON memberships.set_id = subsets.set_id
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW() AND subsets.id = ?")
+        }
+    }
+
+    db! {
+        delete_subset_messages(subset: &str) {
+            "DELETE FROM messages WHERE subset = ?"
+        }
+    }
+
+    db! {
+        delete_subset(subset: &str) {
+            "DELETE FROM subsets WHERE id = ?"
+        }
+    }
+
+    db! {
+        select_reaction_exists(message: &str, uid: &str, emoji: &str) -> bool {
+            first("SELECT 1 FROM reactions WHERE message_id = ? AND user_id = ? AND emoji = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        select_reaction_count(message: &str, emoji: &str) -> u64 {
+            first("SELECT COUNT(*) FROM reactions WHERE message_id = ? AND emoji = ?") => |result: Option<u64>| {
+                result.unwrap_or(0)
+            }
+        }
+    }
+
+    db! {
+        insert_reaction(message: &str, uid: &str, emoji: &str) {
+            "INSERT INTO reactions (message_id, user_id, emoji, creation_date) VALUES (?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        delete_reaction(message: &str, uid: &str, emoji: &str) {
+            "DELETE FROM reactions WHERE message_id = ? AND user_id = ? AND emoji = ?"
+        }
+    }
+
+    db! {
+        soft_delete_message(deleted_by: &str, reason: &Option<String>, message: &str) {
+            "UPDATE messages SET content = '', attachment = NULL, deleted_by = ?, delete_reason = ?, deleted_at = NOW() WHERE id = ?"
+        }
+    }
+
+    db! {
+        select_message_is_deleted(message: &str) -> bool {
+            first("SELECT 1 FROM messages WHERE id = ? AND deleted_by IS NOT NULL") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        delete_message_reactions(message: &str) {
+            "DELETE FROM reactions WHERE message_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_reactions(subset: &str) {
+            "DELETE reactions FROM reactions JOIN messages ON reactions.message_id = messages.id WHERE messages.subset = ?"
+        }
+    }
+
+    db! {
+        delete_set_reactions(set: &str) {
+            "DELETE reactions FROM reactions
+                JOIN messages ON reactions.message_id = messages.id
+                JOIN subsets ON messages.subset = subsets.id
+                WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_revisions(subset: &str) {
+            "DELETE message_revisions FROM message_revisions JOIN messages ON message_revisions.message_id = messages.id WHERE messages.subset = ?"
+        }
+    }
+
+    db! {
+        delete_set_revisions(set: &str) {
+            "DELETE message_revisions FROM message_revisions
+                JOIN messages ON message_revisions.message_id = messages.id
+                JOIN subsets ON messages.subset = subsets.id
+                WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        select_pinned_messages(subset: &str) -> Vec<Message> {
+            "SELECT messages.id, messages.content, messages.sender, users.display_name, users.image, messages.send_time, messages.attachment, files.name, messages.reply_to, messages.thread, messages.edited_at, messages.deleted_by FROM pins
+                JOIN messages ON pins.message_id = messages.id
+                JOIN users ON messages.sender = users.id
+                LEFT JOIN files ON messages.attachment = files.id
+                WHERE messages.subset = ?
+                ORDER BY pins.pin_time ASC, messages.send_time ASC" => Message::from_row
+        }
+    }
+
+    db! {
+        select_pin_exists(message: &str) -> bool {
+            first("SELECT 1 FROM pins WHERE message_id = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        insert_pin(message: &str, uid: &str) {
+            "INSERT INTO pins (message_id, pinned_by, pin_time) VALUES (?, ?, NOW())"
+        }
+    }
+
+    db! {
+        delete_pin(message: &str) {
+            "DELETE FROM pins WHERE message_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_pins(subset: &str) {
+            "DELETE pins FROM pins JOIN messages ON pins.message_id = messages.id WHERE messages.subset = ?"
+        }
+    }
+
+    db! {
+        delete_set_pins(set: &str) {
+            "DELETE pins FROM pins
+                JOIN messages ON pins.message_id = messages.id
+                JOIN subsets ON messages.subset = subsets.id
+                WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        insert_mention(message: &str, position: usize, kind: &str, uid: &Option<String>) {
+            "INSERT INTO mentions (message_id, position, kind, user_id) VALUES (?, ?, ?, ?)"
+        }
+    }
+
+    db! {
+        delete_message_mentions(message: &str) {
+            "DELETE FROM mentions WHERE message_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_mentions(subset: &str) {
+            "DELETE mentions FROM mentions JOIN messages ON mentions.message_id = messages.id WHERE messages.subset = ?"
+        }
+    }
+
+    db! {
+        delete_set_mentions(set: &str) {
+            "DELETE mentions FROM mentions
+                JOIN messages ON mentions.message_id = messages.id
+                JOIN subsets ON messages.subset = subsets.id
+                WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        select_unread_counts(uid: &str, set: &str) -> Vec<(String, u64, u64)> {
+            "SELECT subsets.id,
+                COUNT(CASE WHEN messages.thread IS NULL THEN 1 END),
+                COUNT(CASE WHEN EXISTS (
+                    SELECT 1 FROM mentions WHERE mentions.message_id = messages.id
+                    AND (mentions.user_id = memberships.user_id OR mentions.kind IN ('here', 'everyone'))
+                ) AND NOT EXISTS (
+                    SELECT 1 FROM blocks WHERE blocks.user_id = memberships.user_id AND blocks.blocked_id = messages.sender
+                ) THEN 1 END)
+                FROM messages
+                JOIN subsets ON messages.subset = subsets.id
+                JOIN memberships ON memberships.set_id = subsets.set_id AND memberships.user_id = ?
+                LEFT JOIN read_markers ON read_markers.user_id = memberships.user_id AND read_markers.subset_id = subsets.id
+                WHERE subsets.set_id = ? AND messages.deleted_by IS NULL
+                AND messages.sender != memberships.user_id
+                AND messages.send_time > COALESCE(read_markers.read_time, memberships.creation_date)
+                GROUP BY subsets.id"
+        }
+    }
+
+    db! {
+        update_read_marker(uid: &str, subset: &str, message: &str) {
+            "REPLACE INTO read_markers (user_id, subset_id, message_id, read_time)
+                SELECT ?, ?, id, send_time FROM messages WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_read_marker_to_latest(uid: &str, subset: &str, latest_in_subset: &str) {
+            "REPLACE INTO read_markers (user_id, subset_id, message_id, read_time)
+                VALUES (?, ?, (SELECT id FROM messages WHERE subset = ? ORDER BY send_time DESC LIMIT 1), NOW())"
+        }
+    }
+
+    db! {
+        delete_membership_read_markers(uid: &str, set: &str) {
+            "DELETE read_markers FROM read_markers
+                JOIN subsets ON read_markers.subset_id = subsets.id
+                WHERE read_markers.user_id = ? AND subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        delete_subset_read_markers(subset: &str) {
+            "DELETE FROM read_markers WHERE subset_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_read_markers(set: &str) {
+            "DELETE read_markers FROM read_markers
+                JOIN subsets ON read_markers.subset_id = subsets.id
+                WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        select_dms_by_user(uid: &str) -> Vec<Dm> {
+            "SELECT dms.id, MAX(dm_messages.send_time) FROM dms
+                JOIN dm_participants ON dm_participants.dm_id = dms.id
+                LEFT JOIN dm_messages ON dm_messages.dm_id = dms.id
+                WHERE dm_participants.user_id = ?
+                GROUP BY dms.id, dms.creation_date
+                ORDER BY COALESCE(MAX(dm_messages.send_time), dms.creation_date) DESC" => Dm::from_row
+        }
+    }
+
+    db! {
+        select_dm_participants(dm: &str) -> Vec<User> {
+            "SELECT users.id, username, display_name, email, image, bio, presence, IF(status_expiry IS NULL OR status_expiry > NOW(), status, NULL), email_visibility, bio_visibility FROM users
+                JOIN dm_participants ON users.id = dm_participants.user_id
+                WHERE dm_participants.dm_id = ?
+                ORDER BY display_name ASC" => User::from_row
+        }
+    }
+
+    db! {
+        select_dm_participant_ids(dm: &str) -> Vec<String> {
+            "SELECT user_id FROM dm_participants WHERE dm_id = ?"
+        }
+    }
+
+    db! {
+        select_dm_ids_by_user(uid: &str) -> Vec<String> {
+            "SELECT dm_id FROM dm_participants WHERE user_id = ?"
+        }
+    }
+
+    db! {
+        select_is_dm_participant(dm: &str, uid: &str) -> bool {
+            first("SELECT 1 FROM dm_participants WHERE dm_id = ? AND user_id = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        insert_dm(id: &str) {
+            "INSERT INTO dms (id, creation_date) VALUES (?, NOW())"
+        }
+    }
+
+    db! {
+        insert_dm_participant(dm: &str, uid: &str) {
+            "INSERT INTO dm_participants (dm_id, user_id) VALUES (?, ?)"
+        }
+    }
+
+    db! {
+        select_dm_messages(dm: &str, limit: usize) -> Vec<Message> {
+            "SELECT dm_messages.id, dm_messages.content, dm_messages.sender, users.display_name, users.image, dm_messages.send_time, dm_messages.attachment, files.name, NULL, NULL, NULL, NULL FROM dm_messages
+                JOIN users ON dm_messages.sender = users.id
+                LEFT JOIN files ON dm_messages.attachment = files.id
+                WHERE dm_messages.dm_id = ?
+                ORDER BY dm_messages.send_time DESC
+                LIMIT ?" => Message::from_row
+        }
+    }
+
+    db! {
+        select_dm_messages_before(dm: &str, before: &str, limit: usize) -> Vec<Message> {
+            "SELECT dm_messages.id, dm_messages.content, dm_messages.sender, users.display_name, users.image, dm_messages.send_time, dm_messages.attachment, files.name, NULL, NULL, NULL, NULL FROM dm_messages
+                JOIN users ON dm_messages.sender = users.id
+                LEFT JOIN files ON dm_messages.attachment = files.id
+                WHERE dm_messages.dm_id = ?
+                AND dm_messages.send_time < (
+                    SELECT send_time FROM dm_messages WHERE id = ?
+                )
+                ORDER BY dm_messages.send_time DESC
+                LIMIT ?" => Message::from_row
+        }
+    }
+
+    db! {
+        insert_dm_message(id: &str, dm: &str, sender: &str, content: &str, attachment: &Option<String>) {
+            "INSERT INTO dm_messages (id, dm_id, sender, content, attachment, send_time) VALUES (?, ?, ?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        select_friends(requester: &str, recipient: &str) -> Vec<Friend> {
+            "SELECT users.id, users.username, users.display_name, users.email, users.image, users.bio, users.presence, IF(users.status_expiry IS NULL OR users.status_expiry > NOW(), users.status, NULL), users.email_visibility, users.bio_visibility, IF(friends.accepted, 'friend', 'outgoing') FROM friends
+                JOIN users ON users.id = friends.friend_id
+                WHERE friends.user_id = ?
+            UNION SELECT users.id, users.username, users.display_name, users.email, users.image, users.bio, users.presence, IF(users.status_expiry IS NULL OR users.status_expiry > NOW(), users.status, NULL), users.email_visibility, users.bio_visibility, IF(friends.accepted, 'friend', 'incoming') FROM friends
+                JOIN users ON users.id = friends.user_id
+                WHERE friends.friend_id = ?" => Friend::from_row
+        }
+    }
+
+    db! {
+        select_friend_request(from: &str, to: &str) -> Option<bool> {
+            first("SELECT accepted FROM friends WHERE user_id = ? AND friend_id = ?") => |accepted: u8| {
+                accepted != 0
+            }
+        }
+    }
+
+    db! {
+        insert_friend_request(from: &str, to: &str) {
+            "INSERT INTO friends (user_id, friend_id, accepted, request_time) VALUES (?, ?, FALSE, NOW())"
+        }
+    }
+
+    db! {
+        update_accept_friend_request(from: &str, to: &str) {
+            "UPDATE friends SET accepted = TRUE WHERE user_id = ? AND friend_id = ?"
+        }
+    }
+
+    db! {
+        delete_friend_request(from: &str, to: &str) {
+            "DELETE FROM friends WHERE user_id = ? AND friend_id = ?"
+        }
+    }
+
+    db! {
+        select_is_blocked(uid: &str, blocked: &str) -> bool {
+            first("SELECT 1 FROM blocks WHERE user_id = ? AND blocked_id = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        select_blocked_ids(uid: &str) -> Vec<String> {
+            "SELECT blocked_id FROM blocks WHERE user_id = ?"
+        }
+    }
+
+    db! {
+        select_blocker_ids(uid: &str) -> Vec<String> {
+            "SELECT user_id FROM blocks WHERE blocked_id = ?"
+        }
+    }
+
+    db! {
+        insert_block(uid: &str, blocked: &str) {
+            "INSERT INTO blocks (user_id, blocked_id, block_time) VALUES (?, ?, NOW())"
+        }
+    }
+
+    db! {
+        delete_block(uid: &str, blocked: &str) {
+            "DELETE FROM blocks WHERE user_id = ? AND blocked_id = ?"
+        }
+    }
+
+    db! {
+        update_set_name(name: &str, set: &str) {
+            "UPDATE sets SET name = ? WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_set_icon(icon: &str, set: &str) {
+            "UPDATE sets SET icon = ? WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_subset_name(name: &str, subset: &str) {
+            "UPDATE subsets SET name = ? WHERE id = ?"
+        }
+    }
+
+    db! {
+        select_user_by_token(token: Token) -> Option<User> {
+            first("SELECT users.id, username, display_name, email, image, bio, presence, IF(status_expiry IS NULL OR status_expiry > NOW(), status, NULL), email_visibility, bio_visibility FROM users
+                JOIN sessions ON sessions.user_id = users.id
+                WHERE sessions.token = ? AND sessions.expiry > NOW()") => User::from_row
+        }
+    }
+
+    db! {
+        select_user_by_uid(uid: &str) -> Option<User> {
+            first("SELECT id, username, display_name, email, image, bio, presence, IF(status_expiry IS NULL OR status_expiry > NOW(), status, NULL), email_visibility, bio_visibility FROM users WHERE id = ?") => User::from_row
+        }
+    }
+
+    db! {
+        select_user_by_username(username: &str) -> Option<User> {
+            first("SELECT id, username, display_name, email, image, bio, presence, IF(status_expiry IS NULL OR status_expiry > NOW(), status, NULL), email_visibility, bio_visibility FROM users WHERE username = ?") => User::from_row
+        }
+    }
+
+    db! {
+        select_search_users(uid: &str, requester: &str, deleted: &str, username: &str, display_name: &str, limit: usize) -> Vec<User> {
+            "SELECT DISTINCT users.id, users.username, users.display_name, users.email, users.image, users.bio, users.presence, IF(users.status_expiry IS NULL OR users.status_expiry > NOW(), users.status, NULL), users.email_visibility, users.bio_visibility FROM users
+                JOIN memberships AS theirs ON theirs.user_id = users.id
+                JOIN memberships AS mine ON mine.set_id = theirs.set_id AND mine.user_id = ?
+                WHERE users.id != ? AND users.id != ?
+                AND (users.username LIKE CONCAT(?, '%') OR users.display_name LIKE CONCAT(?, '%'))
+                ORDER BY users.username
+                LIMIT ?" => User::from_row
+        }
+    }
+
+    db! {
+        select_user_has_membership(uid: &str, set: &str) -> bool {
+            first("SELECT 1 FROM memberships WHERE user_id = ? AND set_id = ?") => |has_membership: Option<u8>| {
+                has_membership.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        delete_membership(user_id: &str, set_id: &str) {
+            "DELETE FROM memberships WHERE user_id = ? AND set_id = ?"
+        }
+    }
+
+    db! {
+        select_user_set_ids(user_id: &str) -> Vec<String> {
+            "SELECT set_id FROM memberships WHERE user_id = ?"
+        }
+    }
+
+    db! {
+        update_user_display_name(display_name: &str, token: Token) {
+            "UPDATE users SET display_name = ? WHERE id = (SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW())"
+        }
+    }
+
+    db! {
+        update_user_username(username: &str, token: Token) {
+            "UPDATE users SET username = ? WHERE id = (SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW())"
+        }
+    }
+
+    db! {
+        update_user_privacy(email_visibility: &Option<String>, bio_visibility: &Option<String>, uid: &str) {
+            "UPDATE users SET email_visibility = COALESCE(?, email_visibility), bio_visibility = COALESCE(?, bio_visibility) WHERE id = ?"
+        }
+    }
+
+    db! {
+        select_shares_set(uid: &str, other_uid: &str) -> bool {
+            first("SELECT 1 FROM memberships AS mine
+                JOIN memberships AS theirs ON theirs.set_id = mine.set_id
+                WHERE mine.user_id = ? AND theirs.user_id = ?") => |result: Option<u8>| {
+                result.unwrap_or(0) != 0
+            }
+        }
+    }
+
+    db! {
+        update_user_email(email: &str, token: Token) {
+            "UPDATE users SET email = ?, email_verified = FALSE WHERE id = (SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW())"
+        }
+    }
+
+    db! {
+        update_user_bio(bio: &str, token: Token) {
+            "UPDATE users SET bio = ? WHERE id = (SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW())"
+        }
+    }
+
+    db! {
+        update_user_presence(presence: &str, status: &Option<String>, uid: &str) {
+            "UPDATE users SET presence = ?, status = ?, status_expiry = NULL WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_user_presence_with_duration(presence: &str, status: &Option<String>, minutes: usize, uid: &str) {
+            "UPDATE users SET presence = ?, status = ?, status_expiry = DATE_ADD(NOW(), INTERVAL ? MINUTE) WHERE id = ?"
+        }
+    }
+
+    db! {
+        update_user_image(image: &str, token: Token) {
+            "UPDATE users SET image = ? WHERE id = (SELECT user_id FROM sessions WHERE token = ? AND expiry > NOW())"
+        }
+    }
+
+    db! {
+        update_message(message: &str, id: &str) {
+            "UPDATE messages SET content = ?, edited_at = NOW() WHERE id = ?"
+        }
+    }
+
+    db! {
+        insert_message_revision(id: &str, message: &str, content: &str) {
+            "INSERT INTO message_revisions (id, message_id, content, edit_time) VALUES (?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        select_message_revisions(message: &str) -> Vec<Revision> {
+            "SELECT content, edit_time FROM message_revisions
+                WHERE message_id = ?
+                ORDER BY edit_time ASC" => Revision::from_row
+        }
+    }
+
+    db! {
+        select_invites_by_set(set: &str) -> Vec<Invite> {
+            "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
+            JOIN sets ON sets.id = invites.set_id
+            WHERE set_id = ? AND (expiry_date > NOW() OR expiry_date IS NULL)" => Invite::from_row
+        }
+    }
+
+    db! {
+        select_invite_by_code(code: &str) -> Option<Invite> {
+            first(
+                "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
+                JOIN sets ON sets.id = invites.set_id WHERE code = ?"
+            ) => Invite::from_row
+        }
+    }
+
+    db! {
+        select_invite_by_id(id: &str) -> Option<Invite> {
+            first(
+                "SELECT invites.id, invites.set_id, sets.name, sets.icon, invites.code, invites.creation_date, invites.expiry_date, invites.uses FROM invites
+                JOIN sets ON sets.id = invites.set_id WHERE invites.id = ?"
+            ) => Invite::from_row
+        }
+    }
+
+    db! {
+        insert_invite(id: &str, set: &str, code: &str) {
+            "INSERT INTO invites (id, set_id, code, creation_date, expiry_date) VALUES (?, ?, ?, NOW(), NULL)"
+        }
+    }
+
+    db! {
+        insert_invite_with_duration(id: &str, set: &str, code: &str, minutes: usize) {
+            "INSERT INTO invites (id, set_id, code, creation_date, expiry_date) VALUES (?, ?, ?, NOW(), DATE_ADD(NOW(), INTERVAL ? MINUTE))"
+        }
+    }
+
+    db! {
+        delete_invite(id: &str) {
+            "DELETE FROM invites WHERE id = ?"
+        }
+    }
+
+    db! {
+        increment_invite_uses(id: &str) {
+            "UPDATE invites SET uses = uses + 1 WHERE id = ?"
+        }
+    }
+
+    db! {
+        delete_set_messages(set: &str) {
+            "DELETE messages FROM messages JOIN subsets ON messages.subset = subsets.id WHERE subsets.set_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_subsets(set: &str) {
+            "DELETE FROM subsets WHERE set_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_invites(set: &str) {
+            "DELETE FROM invites WHERE set_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_memberships(set: &str) {
+            "DELETE FROM memberships WHERE set_id = ?"
+        }
+    }
+
+    db! {
+        delete_set_roles(set: &str) {
+            "DELETE FROM roles WHERE set_id = ?"
+        }
+    }
+
+    db! {
+        select_roles_by_set(set: &str) -> Vec<Role> {
+            "SELECT id, name, kind, permissions FROM roles WHERE set_id = ? ORDER BY creation_date ASC" => Role::from_row
+        }
+    }
+
+    db! {
+        select_user_ids_by_role(role: &str) -> Vec<String> {
+            "SELECT user_id FROM memberships WHERE role = ? ORDER BY creation_date ASC"
+        }
+    }
+
+    db! {
+        select_role_by_id_and_set(role: &str, set: &str) -> Option<Role> {
+            first("SELECT id, name, kind, permissions FROM roles WHERE id = ? AND set_id = ?") => Role::from_row
+        }
+    }
+
+    db! {
+        select_role_by_user_and_set(user_id: &str, set: &str) -> Option<Role> {
+            first("SELECT roles.id, roles.name, roles.kind, roles.permissions FROM roles
+                JOIN memberships ON memberships.role = roles.id
+                WHERE memberships.user_id = ? AND memberships.set_id = ?") => Role::from_row
+        }
+    }
+
+    db! {
+        select_role_id_by_kind(set: &str, kind: &str) -> Option<String> {
+            first("SELECT id FROM roles WHERE set_id = ? AND kind = ?")
+        }
+    }
+
+    db! {
+        insert_role(id: &str, set_id: &str, name: &str, kind: &str, permissions: u64) {
+            "INSERT INTO roles (id, set_id, name, kind, permissions, creation_date) VALUES (?, ?, ?, ?, ?, NOW())"
+        }
+    }
+
+    db! {
+        update_membership_role(role: &str, user_id: &str, set_id: &str) {
+            "UPDATE memberships SET role = ? WHERE user_id = ? AND set_id = ?"
         }
     }
 
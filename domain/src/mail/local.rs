@@ -0,0 +1,37 @@
+//! Provides a mailer which writes emails locally instead of sending them, for development and testing.
+
+use crate::mail::Mailer;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A mailer which appends emails to a file, or logs them if no file is given.
+pub struct LocalMailer {
+    /// The file to append emails to.
+    pub path: Option<PathBuf>,
+    /// The address emails are sent from.
+    pub from: String,
+}
+
+impl Mailer for LocalMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let email = format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n\n",
+            self.from, to, subject, body
+        );
+
+        match &self.path {
+            Some(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(email.as_bytes()))
+                .map_err(|_| "Could not send email".to_string()),
+            None => {
+                crate::log!("Email:\n{}", email.trim_end());
+                Ok(())
+            }
+        }
+    }
+}
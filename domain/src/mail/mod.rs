@@ -0,0 +1,65 @@
+//! Provides outbound email, which is used to send codes to users.
+
+pub mod local;
+pub mod smtp;
+
+use local::LocalMailer;
+use smtp::SmtpMailer;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The address emails are sent from, if not already specified in the `EQUION_MAIL_FROM` environment variable.
+static MAIL_FROM: &str = "noreply@equion.whenderson.dev";
+
+/// The port to connect to the SMTP server on, if not already specified in the `EQUION_SMTP_PORT` environment variable.
+const SMTP_PORT: u16 = 587;
+
+/// A way of sending emails to users.
+pub trait Mailer: Send + Sync {
+    /// Sends a plain text email with the given subject to the given address.
+    ///
+    /// Mailers which send emails in the background only return errors which are known before sending.
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String>;
+}
+
+/// Creates the mailer configured by the environment.
+///
+/// If the `EQUION_SMTP_HOST` environment variable is set, emails are sent through that SMTP server, authenticating with `EQUION_SMTP_USERNAME` and `EQUION_SMTP_PASSWORD` if given.
+/// Otherwise, emails are appended to the file at `EQUION_MAIL_FILE`, or logged if that is not set either, which is useful for local development.
+pub fn from_env() -> Arc<dyn Mailer> {
+    let from = std::env::var("EQUION_MAIL_FROM").unwrap_or_else(|_| String::from(MAIL_FROM));
+
+    match std::env::var("EQUION_SMTP_HOST") {
+        Ok(host) => {
+            let port = std::env::var("EQUION_SMTP_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(SMTP_PORT);
+
+            crate::log!("Sending emails through SMTP server at {}:{}", host, port);
+
+            Arc::new(SmtpMailer {
+                host,
+                port,
+                username: std::env::var("EQUION_SMTP_USERNAME").ok(),
+                password: std::env::var("EQUION_SMTP_PASSWORD").ok(),
+                from,
+            })
+        }
+        Err(_) => {
+            let path = std::env::var("EQUION_MAIL_FILE").ok().map(PathBuf::from);
+
+            match &path {
+                Some(path) => {
+                    crate::log!("Writing emails to {}", path.display());
+                }
+                None => {
+                    crate::log!(Warn, "No mail server configured, so emails will be logged");
+                }
+            }
+
+            Arc::new(LocalMailer { path, from })
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Provides a mailer which sends emails through an SMTP server.
+
+use crate::mail::Mailer;
+
+use native_tls::TlsConnector;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread::spawn;
+use std::time::Duration;
+
+/// The time to wait for the SMTP server to accept a connection or respond to a command before giving up.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A mailer which sends emails through an SMTP server, upgrading the connection with `STARTTLS`.
+///
+/// Emails are sent on a separate thread, so a slow or unresponsive mail server does not hold up requests.
+#[derive(Clone)]
+pub struct SmtpMailer {
+    /// The hostname of the SMTP server.
+    pub host: String,
+    /// The port of the SMTP server.
+    pub port: u16,
+    /// The username to authenticate with, if the server requires authentication.
+    pub username: Option<String>,
+    /// The password to authenticate with, if the server requires authentication.
+    pub password: Option<String>,
+    /// The address emails are sent from.
+    pub from: String,
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        // Addresses stored before they were validated could otherwise inject extra commands or headers.
+        if to.contains(['\r', '\n']) || subject.contains(['\r', '\n']) {
+            return Err("Invalid email header".to_string());
+        }
+
+        let mailer = self.clone();
+        let (to, subject, body) = (to.to_string(), subject.to_string(), body.to_string());
+
+        spawn(move || {
+            if let Err(e) = mailer.deliver(&to, &subject, &body) {
+                crate::log!(Error, "Could not send email: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl SmtpMailer {
+    /// Sends the email through the SMTP server, blocking until it has been accepted.
+    fn deliver(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| "Could not connect to mail server".to_string())?;
+
+        let stream = TcpStream::connect_timeout(&addr, SMTP_TIMEOUT)
+            .map_err(|_| "Could not connect to mail server".to_string())?;
+
+        stream
+            .set_read_timeout(Some(SMTP_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(SMTP_TIMEOUT)))
+            .map_err(|_| "Could not connect to mail server".to_string())?;
+
+        let mut stream = BufReader::new(stream);
+
+        command(&mut stream, None, 220)?;
+        command(&mut stream, Some("EHLO equion"), 250)?;
+        command(&mut stream, Some("STARTTLS"), 220)?;
+
+        let stream = TlsConnector::new()
+            .map_err(|_| "Could not secure connection to mail server".to_string())?
+            .connect(&self.host, stream.into_inner())
+            .map_err(|_| "Could not secure connection to mail server".to_string())?;
+        let mut stream = BufReader::new(stream);
+
+        command(&mut stream, Some("EHLO equion"), 250)?;
+
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            command(&mut stream, Some("AUTH LOGIN"), 334)?;
+            command(&mut stream, Some(&base64::encode(username)), 334)?;
+            command(&mut stream, Some(&base64::encode(password)), 235)?;
+        }
+
+        // Lines starting with a full stop are escaped with another, since a lone full stop ends the message.
+        let body = body
+            .lines()
+            .map(|line| {
+                if line.starts_with('.') {
+                    format!(".{}", line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n");
+
+        let message = format!(
+            "From: Equion <{}>\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n.",
+            self.from, to, subject, body
+        );
+
+        command(
+            &mut stream,
+            Some(&format!("MAIL FROM:<{}>", self.from)),
+            250,
+        )?;
+        command(&mut stream, Some(&format!("RCPT TO:<{}>", to)), 250)?;
+        command(&mut stream, Some("DATA"), 354)?;
+        command(&mut stream, Some(&message), 250)?;
+        command(&mut stream, Some("QUIT"), 221)?;
+
+        Ok(())
+    }
+}
+
+/// Sends the given command to the SMTP server, if any, and checks that the server replies with the expected status code.
+fn command<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    command: Option<&str>,
+    expected: u16,
+) -> Result<(), String> {
+    if let Some(command) = command {
+        stream
+            .get_mut()
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|_| "Could not send email".to_string())?;
+    }
+
+    // Multi-line replies have a hyphen after the status code on every line but the last.
+    loop {
+        let mut line = String::new();
+
+        stream
+            .read_line(&mut line)
+            .map_err(|_| "Could not send email".to_string())?;
+
+        let status: u16 = line
+            .get(0..3)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| "Could not send email".to_string())?;
+
+        if line.get(3..4) != Some("-") {
+            if status != expected {
+                return Err("Could not send email".to_string());
+            }
+
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Provides rate limiting with token buckets, used to protect endpoints from brute-force attacks and spam.
+//!
+//! Each bucket starts full and has one token taken from it by every request. Tokens are refilled at a constant rate, so short bursts of requests are allowed as long as the average rate stays within the limit.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Represents the rate limit of a bucket.
+#[derive(Clone, Copy)]
+pub struct Limit {
+    /// The maximum number of tokens in the bucket, which is the largest burst of requests allowed.
+    capacity: u32,
+    /// The time taken for a single token to be refilled.
+    interval: Duration,
+}
+
+/// Represents a single bucket of tokens.
+struct Bucket {
+    /// The rate limit of the bucket.
+    limit: Limit,
+    /// The number of tokens in the bucket when it was last updated.
+    tokens: f64,
+    /// The time at which the bucket was last updated.
+    updated: Instant,
+}
+
+/// Keeps track of the buckets of every client and account which has made rate-limited requests.
+#[derive(Default)]
+pub struct RateLimiter {
+    /// A hashmap of keys to their buckets.
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limit {
+    /// Creates a limit allowing bursts of `capacity` requests, with one more allowed every `interval` seconds.
+    pub const fn new(capacity: u32, interval: u64) -> Self {
+        Self {
+            capacity,
+            interval: Duration::from_secs(interval),
+        }
+    }
+}
+
+impl Bucket {
+    /// Refills the bucket with the tokens accumulated since it was last updated.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        let refilled = elapsed / self.limit.interval.as_secs_f64();
+
+        self.tokens = (self.tokens + refilled).min(self.limit.capacity as f64);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter with no buckets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token from the bucket with the given key, creating it with the given limit if it does not exist.
+    ///
+    /// If the bucket is empty, the number of seconds until a token will be available is returned as an error.
+    pub fn check(&self, key: &str, limit: Limit) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            limit,
+            tokens: limit.capacity as f64,
+            updated: now,
+        });
+
+        bucket.refill(now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let retry_after = (1.0 - bucket.tokens) * bucket.limit.interval.as_secs_f64();
+            Err((retry_after.ceil() as u64).max(1))
+        }
+    }
+
+    /// Removes every bucket which has been completely refilled, since these behave in the same way as new buckets.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.limit.capacity as f64
+        });
+    }
+}
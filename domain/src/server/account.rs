@@ -0,0 +1,349 @@
+//! Provides account deletion and data export.
+
+use crate::server::auth::verify_password;
+use crate::server::files::FileResponse;
+use crate::server::roles::Role;
+use crate::server::sets::delete_set_data;
+use crate::server::two_factor::check_second_factor;
+use crate::util::{parse_date, Token};
+use crate::State;
+
+use humphrey_json::prelude::*;
+use mysql::Value;
+
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+/// The ID of the placeholder user which the messages and files of deleted accounts are attributed to.
+pub const DELETED_USER_ID: &str = "00000000-0000-0000-0000-000000000000";
+
+/// The number of minutes for which a data export can be downloaded.
+const DATA_EXPORT_LIFETIME: usize = 60;
+
+/// The file name of data exports.
+const DATA_EXPORT_NAME: &str = "equion-data.json";
+
+/// Represents one of the user's set memberships in their data export.
+pub struct ExportedMembership {
+    /// The ID of the set.
+    pub set: String,
+    /// The name of the set.
+    pub name: String,
+    /// The name of the user's role in the set.
+    pub role: String,
+    /// The time at which the user joined the set.
+    pub joined: u64,
+}
+
+/// Represents a message sent by the user in a set, in their data export.
+pub struct ExportedMessage {
+    /// The ID of the message.
+    pub id: String,
+    /// The ID of the set the message was sent in.
+    pub set: String,
+    /// The ID of the subset the message was sent in.
+    pub subset: String,
+    /// The content of the message.
+    pub content: String,
+    /// The time at which the message was sent.
+    pub send_time: u64,
+}
+
+/// Represents a direct message sent by the user, in their data export.
+pub struct ExportedDmMessage {
+    /// The ID of the message.
+    pub id: String,
+    /// The ID of the DM the message was sent in.
+    pub dm: String,
+    /// The content of the message.
+    pub content: String,
+    /// The time at which the message was sent.
+    pub send_time: u64,
+}
+
+json_map! {
+    ExportedMembership,
+    set => "set",
+    name => "name",
+    role => "role",
+    joined => "joined"
+}
+
+json_map! {
+    ExportedMessage,
+    id => "id",
+    set => "set",
+    subset => "subset",
+    content => "content",
+    send_time => "sendTime"
+}
+
+json_map! {
+    ExportedDmMessage,
+    id => "id",
+    dm => "dm",
+    content => "content",
+    send_time => "sendTime"
+}
+
+impl ExportedMembership {
+    /// Converts a row of the database to an exported membership.
+    pub(crate) fn from_row(row: (String, String, String, Value)) -> Self {
+        Self {
+            set: row.0,
+            name: row.1,
+            role: row.2,
+            joined: parse_date(row.3),
+        }
+    }
+}
+
+impl ExportedMessage {
+    /// Converts a row of the database to an exported message.
+    pub(crate) fn from_row(row: (String, String, String, String, Value)) -> Self {
+        Self {
+            id: row.0,
+            set: row.1,
+            subset: row.2,
+            content: row.3,
+            send_time: parse_date(row.4),
+        }
+    }
+}
+
+impl ExportedDmMessage {
+    /// Converts a row of the database to an exported direct message.
+    pub(crate) fn from_row(row: (String, String, String, Value)) -> Self {
+        Self {
+            id: row.0,
+            dm: row.1,
+            content: row.2,
+            send_time: parse_date(row.3),
+        }
+    }
+}
+
+/// Represents what happened to a set owned by a deleted user.
+enum OwnedSet {
+    /// Ownership of the set was transferred to the given user, updating the given roles.
+    Transferred(String, Vec<Role>),
+    /// The set was deleted, since the user was its only member.
+    Deleted,
+}
+
+impl State {
+    /// Exports all of the authenticated user's data as a JSON file, returning the ID with which it can be downloaded from the file store.
+    ///
+    /// The export contains the user's profile, set memberships, authored messages and owned files, with file contents encoded in base64.
+    /// Since it contains the user's private details, it is kept apart from other files and can only be downloaded once, within an hour.
+    pub fn export_data(&self, token: impl AsRef<str>) -> Result<String, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let mut user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        user.resolve_presence(&self.voice);
+
+        let memberships = transaction.select_export_memberships(&user.uid)?;
+        let messages = transaction.select_export_messages(&user.uid)?;
+        let dm_messages = transaction.select_export_dm_messages(&user.uid)?;
+
+        let files = transaction
+            .select_files_by_owner(&user.uid)?
+            .into_iter()
+            .map(|file| {
+                json!({
+                    "id": (file.id),
+                    "name": (file.name),
+                    "data": (base64::encode(file.content))
+                })
+            })
+            .collect();
+
+        let export = json!({
+            "exportTime": (UNIX_EPOCH.elapsed().unwrap().as_secs()),
+            "user": (user.clone()),
+            "memberships": memberships,
+            "messages": messages,
+            "dmMessages": dm_messages,
+            "files": (humphrey_json::Value::Array(files))
+        });
+
+        let id = Uuid::new_v4().to_string();
+
+        transaction.insert_data_export(
+            Token(&id),
+            &user.uid,
+            export.serialize().into_bytes(),
+            DATA_EXPORT_LIFETIME,
+        )?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} exported their data", user.uid);
+
+        Ok(id)
+    }
+
+    /// Gets the data export with the given ID, deleting it so that it can only be downloaded once.
+    pub fn take_data_export(&self, id: impl AsRef<str>) -> Result<FileResponse, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (uid, content, expired) = transaction
+            .select_data_export(Token(id.as_ref()))?
+            .ok_or_else(|| "File not found".to_string())?;
+
+        transaction.delete_data_export(Token(id.as_ref()))?;
+
+        transaction.commit()?;
+
+        if expired {
+            return Err("File not found".to_string());
+        }
+
+        crate::log!("User {} downloaded their data export", uid);
+
+        Ok(FileResponse {
+            id: id.as_ref().to_string(),
+            name: DATA_EXPORT_NAME.to_string(),
+            content,
+            owner: uid,
+        })
+    }
+
+    /// Permanently deletes the authenticated user's account, given their password and, if enabled, a two-factor authentication code.
+    ///
+    /// The user's messages and files are kept for the other members of their sets and DMs, but are attributed to a placeholder
+    ///   "Deleted User" instead. Ownership of the user's sets is transferred to a moderator or otherwise the longest-standing member,
+    ///   and sets with no other members are deleted.
+    pub fn delete_account(
+        &self,
+        token: impl AsRef<str>,
+        password: impl AsRef<str>,
+        code: Option<String>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (uid, hash) = transaction
+            .select_id_and_password_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if !verify_password(password.as_ref(), &hash) {
+            return Err("Incorrect password".to_string());
+        }
+
+        if let Some(secret) = transaction.select_totp_secret(&uid)? {
+            let code = code.ok_or_else(|| "Two-factor authentication code required".to_string())?;
+
+            check_second_factor(&mut transaction, &uid, secret, &code)?;
+        }
+
+        let mut user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        user.resolve_presence(&self.voice);
+
+        let set_ids = transaction.select_user_set_ids(&uid)?;
+        let mut owned_sets: HashMap<String, OwnedSet> = HashMap::new();
+
+        for set in &set_ids {
+            if transaction.select_set_owner(set)?.as_ref() != Some(&uid) {
+                continue;
+            }
+
+            if let Some(successor) = transaction.select_set_successor(set, &uid)? {
+                let successor_role = transaction
+                    .select_role_by_user_and_set(&successor, set)?
+                    .ok_or_else(|| "Successor not in set".to_string())?;
+
+                let owner_role_id = transaction
+                    .select_role_id_by_kind(set, "owner")?
+                    .ok_or_else(|| "Set has no owner role".to_string())?;
+
+                transaction.update_set_owner(&successor, set)?;
+                transaction.update_membership_role(&owner_role_id, &successor, set)?;
+
+                let mut roles: Vec<Role> = Vec::new();
+
+                for role_id in [successor_role.id, owner_role_id] {
+                    if let Some(role) = transaction.select_role_by_id_and_set(&role_id, set)? {
+                        roles.push(role);
+                    }
+                }
+
+                owned_sets.insert(set.clone(), OwnedSet::Transferred(successor, roles));
+            } else {
+                delete_set_data(&mut transaction, set)?;
+
+                owned_sets.insert(set.clone(), OwnedSet::Deleted);
+            }
+        }
+
+        transaction.update_messages_sender(DELETED_USER_ID, &uid)?;
+        transaction.update_messages_deleted_by(DELETED_USER_ID, &uid)?;
+        transaction.update_pins_pinned_by(DELETED_USER_ID, &uid)?;
+        transaction.update_mentions_user(DELETED_USER_ID, &uid)?;
+        transaction.update_dm_messages_sender(DELETED_USER_ID, &uid)?;
+        transaction.update_files_owner(DELETED_USER_ID, &uid)?;
+
+        transaction.delete_user_reactions(&uid)?;
+        transaction.delete_user_read_markers(&uid)?;
+        transaction.delete_user_memberships(&uid)?;
+        transaction.delete_user_dm_participants(&uid)?;
+        transaction.delete_all_friends_for_user(&uid)?;
+        transaction.delete_all_blocks_for_user(&uid)?;
+        transaction.delete_user_sessions(&uid)?;
+        transaction.delete_user_login_challenges(&uid)?;
+        transaction.delete_recovery_codes(&uid)?;
+        transaction.delete_user_data_exports(&uid)?;
+        transaction.delete_password_reset(&uid)?;
+        transaction.delete_email_verification(&uid)?;
+        transaction.delete_user(&uid)?;
+
+        if let Some(image) = &user.image {
+            transaction.delete_file(image)?;
+        }
+
+        // Roles are only broadcast once the user has been removed, so that they are no longer listed as members.
+        for owned_set in owned_sets.values_mut() {
+            if let OwnedSet::Transferred(_, roles) = owned_set {
+                for role in roles.iter_mut() {
+                    role.members = transaction.select_user_ids_by_role(&role.id)?;
+                }
+            }
+        }
+
+        transaction.commit()?;
+
+        self.disconnect_user(&uid);
+
+        for set in set_ids {
+            match owned_sets.remove(&set) {
+                Some(OwnedSet::Deleted) => {
+                    self.broadcast_set(&set, None, None, None, None, true);
+                }
+                Some(OwnedSet::Transferred(successor, roles)) => {
+                    self.broadcast_left_user(&set, user.clone());
+
+                    for role in roles {
+                        self.broadcast_role(&set, role);
+                    }
+
+                    self.broadcast_set(&set, None, None, Some(successor), None, false);
+                }
+                None => self.broadcast_left_user(&set, user.clone()),
+            }
+        }
+
+        crate::log!("User {} deleted their account", uid);
+
+        Ok(())
+    }
+}
@@ -1,19 +1,79 @@
 //! Provides core authentication functionality.
 
+use crate::server::verification::{start_verification, validate_email};
+use crate::util::Token;
 use crate::State;
 
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
 use argon2::password_hash::rand_core::OsRng;
 use argon2::password_hash::SaltString;
 use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 
 use uuid::Uuid;
 
+use std::time::UNIX_EPOCH;
+
+/// The number of minutes for which an access token is valid.
+pub const ACCESS_TOKEN_LIFETIME: usize = 60;
+
+/// The number of minutes for which a refresh token is valid.
+pub const REFRESH_TOKEN_LIFETIME: usize = 60 * 24 * 30;
+
+/// The number of minutes for which a two-factor login challenge is valid.
+const LOGIN_CHALLENGE_LIFETIME: usize = 5;
+
 /// Represents an authentication response from the server.
 pub struct AuthResponse {
     /// The UID of the authenticated user.
     pub uid: String,
-    /// The authentication token of the authenticated user.
+    /// The short-lived access token of the authenticated user.
     pub token: String,
+    /// The long-lived token used to obtain a new access token once it expires.
+    pub refresh_token: String,
+    /// The time at which the access token expires.
+    pub expiry: u64,
+}
+
+/// Represents a login response from the server, which either signs the user in or requires a second factor.
+pub struct LoginResponse {
+    /// The UID of the user, if they have been signed in.
+    pub uid: Option<String>,
+    /// The short-lived access token, if the user has been signed in.
+    pub token: Option<String>,
+    /// The long-lived refresh token, if the user has been signed in.
+    pub refresh_token: Option<String>,
+    /// The time at which the access token expires, if the user has been signed in.
+    pub expiry: Option<u64>,
+    /// The challenge to complete with a two-factor authentication code, if the user has two-factor authentication enabled.
+    pub challenge: Option<String>,
+}
+
+impl From<AuthResponse> for LoginResponse {
+    fn from(response: AuthResponse) -> Self {
+        Self {
+            uid: Some(response.uid),
+            token: Some(response.token),
+            refresh_token: Some(response.refresh_token),
+            expiry: Some(response.expiry),
+            challenge: None,
+        }
+    }
+}
+
+impl AuthResponse {
+    /// Creates an authentication response with freshly-generated tokens for the given user.
+    fn new(uid: String) -> Self {
+        Self {
+            uid,
+            token: Uuid::new_v4().to_string(),
+            refresh_token: Uuid::new_v4().to_string(),
+            expiry: UNIX_EPOCH.elapsed().unwrap().as_secs() + ACCESS_TOKEN_LIFETIME as u64 * 60,
+        }
+    }
 }
 
 impl State {
@@ -24,31 +84,18 @@ impl State {
         password: impl AsRef<str>,
         display_name: impl AsRef<str>,
         email: impl AsRef<str>,
+        device: Option<String>,
+        ip: String,
     ) -> Result<AuthResponse, String> {
-        let valid_username = username
-            .as_ref()
-            .chars()
-            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
-
-        if username.as_ref().len() < 3 {
-            return Err("Username must be at least 3 characters long.".to_string());
-        }
-
-        if !valid_username {
-            return Err(
-                "Username can only contain ASCII letters, numbers, underscores and hyphens."
-                    .to_string(),
-            );
-        }
-
-        if password.as_ref().len() < 6 {
-            return Err("Password must be at least 6 characters long.".to_string());
-        }
+        validate_username(username.as_ref())?;
+        validate_password(password.as_ref())?;
 
         if display_name.as_ref().trim().is_empty() {
             return Err("You must enter a display name.".to_string());
         }
 
+        validate_email(email.as_ref())?;
+
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
@@ -61,15 +108,7 @@ impl State {
 
         // Generate a UUID for the user and hash the password
         let uid = Uuid::new_v4().to_string();
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-        let hash = argon2
-            .hash_password(password.as_ref().as_bytes(), &salt)
-            .map_err(|_| "Could not hash password".to_string())?
-            .to_string();
-
-        // Generate a token for the user
-        let token = Uuid::new_v4().to_string();
+        let hash = hash_password(password.as_ref())?;
 
         transaction.insert_user(
             &uid,
@@ -77,56 +116,121 @@ impl State {
             &hash,
             display_name.as_ref(),
             email.as_ref(),
-            &token,
         )?;
 
+        // Start a session for the user and verify their email address
+        let code = start_verification(&mut transaction, &uid)?;
+        let response = start_session(&mut transaction, uid, device, ip)?;
+
         transaction.commit()?;
 
+        // The account has already been created, so the user can request another code if this fails
+        if let Err(e) = self.send_verification(email.as_ref(), &code) {
+            crate::log!(Error, "Could not send verification email: {}", e);
+        }
+
         crate::log!("User signed up with username {}", username.as_ref());
 
-        Ok(AuthResponse { uid, token })
+        Ok(response)
     }
 
     /// Attempts to sign in a user with the given details.
+    ///
+    /// If the user has two-factor authentication enabled, a challenge is returned instead of tokens, which must be completed with `login_2fa`.
     pub fn login(
         &self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
-    ) -> Result<AuthResponse, String> {
+        device: Option<String>,
+        ip: String,
+    ) -> Result<LoginResponse, String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
         let user = transaction.select_id_and_password_by_username(username.as_ref())?;
 
         if let Some((uid, hash)) = user {
-            let argon2 = Argon2::default();
-            let password_hash = PasswordHash::new(&hash).unwrap();
-            let valid = argon2
-                .verify_password(password.as_ref().as_bytes(), &password_hash)
-                .is_ok();
-
-            if valid {
-                let token = Uuid::new_v4().to_string();
-
-                transaction.update_token_by_id(&token, &uid)?;
+            if verify_password(password.as_ref(), &hash) {
+                if transaction.select_totp_secret(&uid)?.is_some() {
+                    let challenge = Uuid::new_v4().to_string();
+
+                    transaction.insert_login_challenge(
+                        Token(&challenge),
+                        &uid,
+                        &device,
+                        &ip,
+                        LOGIN_CHALLENGE_LIFETIME,
+                    )?;
+
+                    transaction.commit()?;
+
+                    crate::log!(
+                        "User with username {} needs a second factor to log in",
+                        username.as_ref()
+                    );
+
+                    return Ok(LoginResponse {
+                        uid: None,
+                        token: None,
+                        refresh_token: None,
+                        expiry: None,
+                        challenge: Some(challenge),
+                    });
+                }
+
+                let response = start_session(&mut transaction, uid, device, ip)?;
 
                 transaction.commit()?;
 
                 crate::log!("User logged in with username {}", username.as_ref());
 
-                return Ok(AuthResponse { uid, token });
+                return Ok(response.into());
             }
         }
 
         Err("Invalid username or password".to_string())
     }
 
-    /// Logs out the user with the given token.
+    /// Exchanges the given refresh token for a new access token and refresh token.
+    ///
+    /// The refresh token can only be used once, since it is replaced along with the access token.
+    pub fn refresh(&self, refresh_token: impl AsRef<str>) -> Result<AuthResponse, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (id, uid, expired) = transaction
+            .select_session_by_refresh_token(Token(refresh_token.as_ref()))?
+            .ok_or_else(|| "Invalid refresh token".to_string())?;
+
+        if expired {
+            return Err("Refresh token expired".to_string());
+        }
+
+        let response = AuthResponse::new(uid);
+
+        transaction.update_session_tokens(
+            Token(&response.token),
+            Token(&response.refresh_token),
+            ACCESS_TOKEN_LIFETIME,
+            REFRESH_TOKEN_LIFETIME,
+            &id,
+        )?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} refreshed session {}", response.uid, id);
+
+        Ok(response)
+    }
+
+    /// Logs out the session with the given token, leaving the user's other sessions signed in.
     pub fn logout(&self, token: impl AsRef<str>) -> Result<(), String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        transaction.update_invalidate_token(token.as_ref())?;
+        let session = transaction.select_session_id_by_token(Token(token.as_ref()))?;
+
+        transaction.delete_session_by_token(Token(token.as_ref()))?;
 
         let affected_rows = transaction.inner.affected_rows();
 
@@ -135,6 +239,10 @@ impl State {
         if affected_rows == 0 {
             Err("Invalid token".to_string())
         } else {
+            if let Some(session) = session {
+                self.disconnect_session(session);
+            }
+
             Ok(())
         }
     }
@@ -144,10 +252,123 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let uid = transaction.select_id_by_token(token.as_ref())?;
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
+
+        if uid.is_some() {
+            transaction.update_session_last_used(Token(token.as_ref()))?;
+        } else if transaction.select_is_token_expired(Token(token.as_ref()))? {
+            return Err("Token expired".to_string());
+        }
 
         transaction.commit()?;
 
         uid.ok_or_else(|| "Invalid token".to_string())
     }
+
+    /// Checks whether the given access token has expired, returning an error if so.
+    ///
+    /// Expired tokens are otherwise indistinguishable from invalid ones, so this is checked before handling any request with a token to let clients know to refresh it.
+    pub fn check_token_expiry(&self, token: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let expired = transaction.select_is_token_expired(Token(token.as_ref()))?;
+
+        transaction.commit()?;
+
+        if expired {
+            Err("Token expired".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Gets the UID of the user the given token belongs to, if it is valid.
+    ///
+    /// Unlike `validate_token`, this does not count as a use of the session.
+    pub fn uid_by_token(&self, token: impl AsRef<str>) -> Result<Option<String>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
+
+        transaction.commit()?;
+
+        Ok(uid)
+    }
+}
+
+/// Checks that the given password meets the requirements for a new password.
+pub(crate) fn validate_password(password: &str) -> Result<(), String> {
+    if password.len() < 6 {
+        return Err("Password must be at least 6 characters long.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Hashes the given password with Argon2 and a random salt.
+pub(crate) fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let argon2 = Argon2::default();
+
+    argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| "Could not hash password".to_string())
+}
+
+/// Checks that the given username is long enough and only contains permitted characters.
+pub(crate) fn validate_username(username: &str) -> Result<(), String> {
+    let valid_username = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if username.len() < 3 {
+        return Err("Username must be at least 3 characters long.".to_string());
+    }
+
+    if !valid_username {
+        return Err(
+            "Username can only contain ASCII letters, numbers, underscores and hyphens."
+                .to_string(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks whether the given password matches the given Argon2 hash.
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    let argon2 = Argon2::default();
+
+    // The placeholder user for deleted accounts has no password hash, so can never be logged in as.
+    PasswordHash::new(hash).is_ok_and(|password_hash| {
+        argon2
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok()
+    })
+}
+
+/// Starts a new session for the given user, returning its freshly-generated tokens.
+pub(crate) fn start_session(
+    transaction: &mut Transaction,
+    uid: String,
+    device: Option<String>,
+    ip: String,
+) -> Result<AuthResponse, String> {
+    let response = AuthResponse::new(uid);
+
+    transaction.insert_session(
+        &Uuid::new_v4().to_string(),
+        Token(&response.token),
+        Token(&response.refresh_token),
+        &response.uid,
+        &device,
+        &ip,
+        ACCESS_TOKEN_LIFETIME,
+        REFRESH_TOKEN_LIFETIME,
+    )?;
+
+    Ok(response)
 }
@@ -0,0 +1,299 @@
+//! Provides direct messages between users outside of sets.
+
+use crate::server::account::DELETED_USER_ID;
+use crate::server::messages::Message;
+use crate::server::user::{viewer_of, User};
+use crate::util::{parse_date, Token};
+use crate::State;
+
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
+use humphrey_json::prelude::*;
+
+use mysql::Value;
+use uuid::Uuid;
+
+use std::time::UNIX_EPOCH;
+
+/// The maximum number of participants in a direct conversation, including its creator.
+const MAX_DM_PARTICIPANTS: usize = 10;
+
+/// Represents a direct conversation response from the server.
+pub struct Dm {
+    /// The ID of the conversation.
+    pub id: String,
+    /// The participants of the conversation.
+    pub participants: Vec<User>,
+    /// The time at which the latest message in the conversation was sent, if any.
+    pub last_message_time: Option<u64>,
+}
+
+json_map! {
+    Dm,
+    id => "id",
+    participants => "participants",
+    last_message_time => "lastMessageTime"
+}
+
+impl Dm {
+    /// Converts a row of the database to a direct conversation.
+    pub(crate) fn from_row(row: (String, Option<Value>)) -> Self {
+        Self {
+            id: row.0,
+            participants: Vec::new(),
+            last_message_time: row.1.map(parse_date),
+        }
+    }
+}
+
+impl State {
+    /// Gets the direct conversations of the authenticated user, most recently active first.
+    pub fn dms(&self, token: impl AsRef<str>) -> Result<Vec<Dm>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let mut dms = transaction.select_dms_by_user(&uid)?;
+
+        for dm in dms.iter_mut() {
+            dm.participants = self.dm_participants(&mut transaction, &dm.id, &uid)?;
+        }
+
+        transaction.commit()?;
+
+        crate::log!("User {} retrieved direct conversations", uid);
+
+        Ok(dms)
+    }
+
+    /// Creates a direct conversation between the authenticated user and the given users, returning its ID.
+    ///
+    /// If a conversation with exactly the same participants already exists, its ID is returned instead.
+    pub fn create_dm(&self, token: impl AsRef<str>, users: Vec<String>) -> Result<String, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let mut participants = vec![uid.clone()];
+
+        for user in users {
+            if !participants.contains(&user) {
+                participants.push(user);
+            }
+        }
+
+        if participants.len() < 2 {
+            return Err("A conversation needs at least one other user".to_string());
+        }
+
+        if participants.len() > MAX_DM_PARTICIPANTS {
+            return Err("Too many participants".to_string());
+        }
+
+        for participant in participants.iter().skip(1) {
+            if participant == DELETED_USER_ID
+                || transaction.select_user_by_uid(participant)?.is_none()
+            {
+                return Err("User not found".to_string());
+            }
+
+            let knows = transaction.select_shares_set(&uid, participant)?
+                || transaction.select_friend_request(&uid, participant)? == Some(true)
+                || transaction.select_friend_request(participant, &uid)? == Some(true);
+
+            if !knows
+                || transaction.select_is_blocked(&uid, participant)?
+                || transaction.select_is_blocked(participant, &uid)?
+            {
+                return Err("Cannot start a conversation with this user".to_string());
+            }
+        }
+
+        for dm in transaction.select_dm_ids_by_user(&uid)? {
+            let existing = transaction.select_dm_participant_ids(&dm)?;
+
+            if existing.len() == participants.len()
+                && existing.iter().all(|id| participants.contains(id))
+            {
+                transaction.commit()?;
+                return Ok(dm);
+            }
+        }
+
+        let id = Uuid::new_v4().to_string();
+
+        transaction.insert_dm(&id)?;
+
+        for participant in &participants {
+            transaction.insert_dm_participant(&id, participant)?;
+        }
+
+        // Each participant is sent the conversation as they see its other participants.
+        let mut dms = Vec::new();
+
+        for participant in &participants {
+            dms.push(Dm {
+                participants: self.dm_participants(&mut transaction, &id, participant)?,
+                id: id.clone(),
+                last_message_time: None,
+            });
+        }
+
+        transaction.commit()?;
+
+        for (participant, dm) in participants.iter().zip(dms) {
+            self.alert_dm(std::slice::from_ref(participant), dm);
+        }
+
+        crate::log!("User {} created direct conversation {}", uid, id);
+
+        Ok(id)
+    }
+
+    /// Gets the messages of the given direct conversation.
+    pub fn dm_messages(
+        &self,
+        token: impl AsRef<str>,
+        dm: impl AsRef<str>,
+        before: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Message>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if !transaction.select_is_dm_participant(dm.as_ref(), &uid)? {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let mut messages = if let Some(before) = before {
+            transaction.select_dm_messages_before(dm.as_ref(), &before, limit.unwrap_or(25))?
+        } else {
+            transaction.select_dm_messages(dm.as_ref(), limit.unwrap_or(25))?
+        };
+
+        let blocked = transaction.select_blocked_ids(&uid)?;
+
+        for message in messages.iter_mut() {
+            message.blocked = blocked.contains(&message.author_id);
+        }
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} retrieved messages for direct conversation {}",
+            uid,
+            dm.as_ref()
+        );
+
+        Ok(messages)
+    }
+
+    /// Sends a message to the given direct conversation.
+    pub fn send_dm(
+        &self,
+        token: impl AsRef<str>,
+        dm: impl AsRef<str>,
+        content: impl AsRef<str>,
+        attachment_name: Option<String>,
+        attachment_content: Option<String>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if !transaction.select_is_dm_participant(dm.as_ref(), &user.uid)? {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let attachment = self.store_attachment(
+            attachment_name,
+            attachment_content,
+            &user.uid,
+            &mut transaction,
+        )?;
+        let attachment_id = attachment.as_ref().map(|attachment| attachment.id.clone());
+
+        let id = Uuid::new_v4().to_string();
+
+        transaction.insert_dm_message(
+            &id,
+            dm.as_ref(),
+            &user.uid,
+            content.as_ref(),
+            &attachment_id,
+        )?;
+
+        // Participants who have blocked the sender are not sent the message.
+        let blockers = transaction.select_blocker_ids(&user.uid)?;
+        let mut participants = transaction.select_dm_participant_ids(dm.as_ref())?;
+        participants.retain(|participant| !blockers.contains(participant));
+
+        transaction.commit()?;
+
+        let message = Message {
+            id: id.clone(),
+            content: content.as_ref().to_string(),
+            author_id: user.uid.clone(),
+            author_name: user.display_name,
+            author_image: user.image,
+            attachment,
+            reply_to: None,
+            thread: None,
+            thread_replies: 0,
+            last_reply_time: None,
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+            edited_at: None,
+            deleted_by: None,
+            delete_reason: None,
+            blocked: false,
+            send_time: UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        };
+
+        self.alert_dm_message(&participants, dm.as_ref(), message);
+
+        crate::log!(
+            "User {} sent message with ID {} to direct conversation {}",
+            user.uid,
+            id,
+            dm.as_ref()
+        );
+
+        Ok(())
+    }
+
+    /// Gets the participants of the given direct conversation as seen by the given user, along with their online status.
+    fn dm_participants(
+        &self,
+        transaction: &mut Transaction,
+        dm: &str,
+        viewer: &str,
+    ) -> Result<Vec<User>, String> {
+        let mut participants = transaction.select_dm_participants(dm)?;
+
+        for user in participants.iter_mut() {
+            user.resolve_presence(&self.voice);
+            *user = user
+                .clone()
+                .view_as(viewer_of(transaction, Some(viewer), &user.uid)?);
+        }
+
+        Ok(participants)
+    }
+}
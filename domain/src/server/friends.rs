@@ -0,0 +1,236 @@
+//! Provides friend requests and blocking between users.
+
+use crate::server::account::DELETED_USER_ID;
+use crate::server::user::{viewer_of, User};
+use crate::util::Token;
+use crate::State;
+
+use humphrey_json::prelude::*;
+
+/// Represents a relationship with another user, either a friendship or a pending friend request.
+pub struct Friend {
+    /// The other user.
+    pub user: User,
+    /// The status of the relationship, either "friend", "incoming" or "outgoing".
+    pub status: String,
+}
+
+json_map! {
+    Friend,
+    user => "user",
+    status => "status"
+}
+
+/// A row of the database describing a friend, with the same columns as a user row followed by the status of the relationship.
+pub(crate) type FriendRow = (
+    String,         // 0. User ID
+    String,         // 1. Username
+    String,         // 2. Display name
+    String,         // 3. Email address
+    Option<String>, // 4. Image
+    Option<String>, // 5. Bio
+    String,         // 6. Presence
+    Option<String>, // 7. Custom status
+    String,         // 8. Email visibility
+    String,         // 9. Bio visibility
+    String,         // 10. Relationship status
+);
+
+impl Friend {
+    /// Converts a row of the database to a friend.
+    pub(crate) fn from_row(row: FriendRow) -> Self {
+        Self {
+            user: User::from_row((
+                row.0, row.1, row.2, row.3, row.4, row.5, row.6, row.7, row.8, row.9,
+            )),
+            status: row.10,
+        }
+    }
+}
+
+impl State {
+    /// Gets the friends and pending friend requests of the authenticated user, along with their online status.
+    pub fn friends(&self, token: impl AsRef<str>) -> Result<Vec<Friend>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let friends = transaction
+            .select_friends(&uid, &uid)?
+            .into_iter()
+            .map(|mut friend| {
+                let viewer = viewer_of(&mut transaction, Some(&uid), &friend.user.uid)?;
+
+                friend.user = friend.user.view_as(viewer);
+                friend.user.resolve_presence(&self.voice);
+
+                Ok(friend)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} retrieved friends", uid);
+
+        Ok(friends)
+    }
+
+    /// Sends a friend request from the authenticated user to the given user.
+    ///
+    /// If the given user has already sent a friend request to the authenticated user, it is accepted instead.
+    pub fn friend_request(
+        &self,
+        token: impl AsRef<str>,
+        uid: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let mut user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+        user.resolve_presence(&self.voice);
+
+        if user.uid == uid.as_ref() {
+            return Err("You cannot befriend yourself".to_string());
+        }
+
+        if uid.as_ref() == DELETED_USER_ID
+            || transaction.select_user_by_uid(uid.as_ref())?.is_none()
+        {
+            return Err("User not found".to_string());
+        }
+
+        if transaction.select_is_blocked(&user.uid, uid.as_ref())?
+            || transaction.select_is_blocked(uid.as_ref(), &user.uid)?
+        {
+            return Err("Cannot send friend request to this user".to_string());
+        }
+
+        let status = match (
+            transaction.select_friend_request(&user.uid, uid.as_ref())?,
+            transaction.select_friend_request(uid.as_ref(), &user.uid)?,
+        ) {
+            (Some(true), _) | (_, Some(true)) => return Err("Already friends".to_string()),
+            (Some(false), _) => return Err("Friend request already sent".to_string()),
+            (None, Some(false)) => {
+                transaction.update_accept_friend_request(uid.as_ref(), &user.uid)?;
+                "friend"
+            }
+            (None, None) => {
+                transaction.insert_friend_request(&user.uid, uid.as_ref())?;
+                "incoming"
+            }
+        };
+
+        let viewer = viewer_of(&mut transaction, Some(uid.as_ref()), &user.uid)?;
+
+        transaction.commit()?;
+
+        self.alert_friend(uid.as_ref(), user.clone().view_as(viewer), status);
+
+        crate::log!("User {} sent friend request to {}", user.uid, uid.as_ref());
+
+        Ok(())
+    }
+
+    /// Accepts or declines the friend request sent to the authenticated user by the given user.
+    pub fn respond_friend_request(
+        &self,
+        token: impl AsRef<str>,
+        uid: impl AsRef<str>,
+        accept: bool,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let mut user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+        user.resolve_presence(&self.voice);
+
+        if transaction.select_friend_request(uid.as_ref(), &user.uid)? != Some(false) {
+            return Err("Friend request not found".to_string());
+        }
+
+        if accept {
+            transaction.update_accept_friend_request(uid.as_ref(), &user.uid)?;
+        } else {
+            transaction.delete_friend_request(uid.as_ref(), &user.uid)?;
+        }
+
+        let viewer = viewer_of(&mut transaction, Some(uid.as_ref()), &user.uid)?;
+
+        transaction.commit()?;
+
+        if accept {
+            self.alert_friend(uid.as_ref(), user.clone().view_as(viewer), "friend");
+        }
+
+        crate::log!(
+            "User {} {} friend request from {}",
+            user.uid,
+            if accept { "accepted" } else { "declined" },
+            uid.as_ref()
+        );
+
+        Ok(())
+    }
+
+    /// Blocks the given user for the authenticated user, removing any friendship or friend requests between them.
+    pub fn block(&self, token: impl AsRef<str>, uid: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let blocker = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if blocker == uid.as_ref() {
+            return Err("You cannot block yourself".to_string());
+        }
+
+        if transaction.select_user_by_uid(uid.as_ref())?.is_none() {
+            return Err("User not found".to_string());
+        }
+
+        if transaction.select_is_blocked(&blocker, uid.as_ref())? {
+            return Err("User already blocked".to_string());
+        }
+
+        transaction.insert_block(&blocker, uid.as_ref())?;
+        transaction.delete_friend_request(&blocker, uid.as_ref())?;
+        transaction.delete_friend_request(uid.as_ref(), &blocker)?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} blocked {}", blocker, uid.as_ref());
+
+        Ok(())
+    }
+
+    /// Unblocks the given user for the authenticated user.
+    pub fn unblock(&self, token: impl AsRef<str>, uid: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let blocker = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if !transaction.select_is_blocked(&blocker, uid.as_ref())? {
+            return Err("User not blocked".to_string());
+        }
+
+        transaction.delete_block(&blocker, uid.as_ref())?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} unblocked {}", blocker, uid.as_ref());
+
+        Ok(())
+    }
+}
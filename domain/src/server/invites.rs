@@ -1,6 +1,7 @@
 //! Provides set invitation functionality.
 
-use crate::util::parse_date;
+use crate::server::roles::Permission;
+use crate::util::{parse_date, Token};
 use crate::State;
 
 use humphrey_json::prelude::*;
@@ -77,7 +78,7 @@ impl State {
         let mut transaction = conn.transaction()?;
 
         let uid = transaction
-            .select_id_by_token(token.as_ref())?
+            .select_id_by_token(Token(token.as_ref()))?
             .ok_or_else(|| "Invalid token".to_string())?;
 
         if !transaction.select_user_has_membership(&uid, set.as_ref())? {
@@ -122,12 +123,12 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let (admin, uid) = transaction
-            .select_membership(token.as_ref(), set.as_ref())?
+        let (permissions, uid) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
             .ok_or_else(|| "Invalid token or set".to_string())?;
 
-        if !admin {
-            return Err("User is not an admin of the set".to_string());
+        if !Permission::ManageInvites.granted_by(permissions) {
+            return Err("Insufficient permissions".to_string());
         }
 
         let id = Uuid::new_v4().to_string();
@@ -161,12 +162,12 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let (admin, uid) = transaction
-            .select_membership(token.as_ref(), set.as_ref())?
+        let (permissions, uid) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
             .ok_or_else(|| "Invalid token or set".to_string())?;
 
-        if !admin {
-            return Err("User is not an admin of the set".to_string());
+        if !Permission::ManageInvites.granted_by(permissions) {
+            return Err("Insufficient permissions".to_string());
         }
 
         let invite = transaction.select_invite_by_id(invite.as_ref())?;
@@ -0,0 +1,125 @@
+//! Provides parsing and resolution of mentions in messages.
+
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
+use humphrey_json::prelude::*;
+
+/// Represents a mention in a message.
+#[derive(Clone)]
+pub struct Mention {
+    /// The type of the mention, either "user", "here" or "everyone".
+    pub kind: String,
+    /// The ID of the mentioned user, if the mention is of a single user.
+    pub uid: Option<String>,
+}
+
+json_map! {
+    Mention,
+    kind => "type",
+    uid => "uid"
+}
+
+impl Mention {
+    /// Converts a row of the database to a mention.
+    pub(crate) fn from_row(row: (String, Option<String>)) -> Self {
+        Self {
+            kind: row.0,
+            uid: row.1,
+        }
+    }
+}
+
+/// Finds the mentions in the given content and resolves them against the users who can view the subset.
+///
+/// Mentions of unknown users, or of users who cannot view the subset, are ignored. Each user is only
+/// mentioned once, in the order they first appear.
+pub(crate) fn resolve_mentions(
+    transaction: &mut Transaction,
+    viewers: &[String],
+    content: &str,
+) -> Result<Vec<Mention>, String> {
+    let mut mentions: Vec<Mention> = Vec::new();
+
+    for name in mention_names(content) {
+        let mention = match name.as_str() {
+            "here" | "everyone" => Mention {
+                kind: name,
+                uid: None,
+            },
+            _ => match transaction.select_id_by_username(&name)? {
+                Some(uid) if viewers.contains(&uid) => Mention {
+                    kind: "user".to_string(),
+                    uid: Some(uid),
+                },
+                _ => continue,
+            },
+        };
+
+        if !mentions
+            .iter()
+            .any(|m| m.kind == mention.kind && m.uid == mention.uid)
+        {
+            mentions.push(mention);
+        }
+    }
+
+    Ok(mentions)
+}
+
+/// Returns the IDs of the users to notify of the given mentions, excluding the author.
+///
+/// Both "here" and "everyone" mentions notify every user who can view the subset, since only users
+/// who are online receive notifications.
+pub(crate) fn mention_recipients(
+    mentions: &[Mention],
+    viewers: &[String],
+    author: &str,
+) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+
+    for mention in mentions {
+        let uids = match mention.uid.as_ref() {
+            Some(uid) => std::slice::from_ref(uid),
+            None => viewers,
+        };
+
+        for uid in uids {
+            if uid != author && !recipients.contains(uid) {
+                recipients.push(uid.clone());
+            }
+        }
+    }
+
+    recipients
+}
+
+/// Extracts the names following each `@` in the content which is not part of a word, such as an email address.
+fn mention_names(content: &str) -> Vec<String> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut names = Vec::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        if *c != '@' || (i > 0 && is_username_char(chars[i - 1])) {
+            continue;
+        }
+
+        let name: String = chars[i + 1..]
+            .iter()
+            .take_while(|c| is_username_char(**c))
+            .collect();
+
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+/// Returns whether the character is allowed in usernames.
+fn is_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
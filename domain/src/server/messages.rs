@@ -1,17 +1,30 @@
 //! Provides the core functionality for message management.
 
-use crate::util::parse_date;
+use crate::server::mentions::{mention_recipients, resolve_mentions, Mention};
+use crate::server::reactions::Reaction;
+use crate::server::roles::Permission;
+use crate::util::{parse_date, Token};
 use crate::State;
 
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
 use humphrey::http::mime::MimeType;
 use humphrey_json::prelude::*;
 
 use mysql::Value;
 use uuid::Uuid;
 
+use std::collections::HashMap;
 use std::time::UNIX_EPOCH;
 
+/// The maximum number of characters of the referenced message included in a reply preview.
+const REPLY_PREVIEW_LENGTH: usize = 100;
+
 /// Represents a message response from the server.
+#[derive(Clone)]
 pub struct Message {
     /// The ID of the message.
     pub id: String,
@@ -25,11 +38,53 @@ pub struct Message {
     pub author_image: Option<String>,
     /// The optional attachment of the message.
     pub attachment: Option<Attachment>,
+    /// The optional preview of the message this message replies to.
+    pub reply_to: Option<Reply>,
+    /// The ID of the root message of the thread this message belongs to, if any.
+    pub thread: Option<String>,
+    /// The number of replies in this message's thread.
+    pub thread_replies: u64,
+    /// The time at which the latest reply in this message's thread was sent, if any.
+    pub last_reply_time: Option<u64>,
+    /// The reactions on the message, in the order they were first added.
+    pub reactions: Vec<Reaction>,
+    /// The users mentioned in the message, in the order they are first mentioned.
+    pub mentions: Vec<Mention>,
+    /// The time at which the message was last edited, if it has been edited.
+    pub edited_at: Option<u64>,
+    /// The ID of the user who deleted the message, if it has been deleted.
+    pub deleted_by: Option<String>,
+    /// The reason given for deleting the message, if any.
+    pub delete_reason: Option<String>,
+    /// Whether the author of the message has been blocked by the user viewing it.
+    pub blocked: bool,
     /// The time at which the message was sent.
     pub send_time: u64,
 }
 
+/// Represents a previous version of an edited message.
+pub struct Revision {
+    /// The content of the message before the edit.
+    pub content: String,
+    /// The time at which the content was replaced.
+    pub edit_time: u64,
+}
+
+/// Represents a preview of the message being replied to.
+#[derive(Clone)]
+pub struct Reply {
+    /// The ID of the referenced message.
+    pub id: String,
+    /// The name of the author of the referenced message, if it has not been deleted.
+    pub author_name: Option<String>,
+    /// The truncated content of the referenced message, if it has not been deleted.
+    pub content: Option<String>,
+    /// Whether the referenced message has been deleted.
+    pub deleted: bool,
+}
+
 /// Represents an attachment response from the server.
+#[derive(Clone)]
 pub struct Attachment {
     /// The ID of the file.
     pub id: String,
@@ -47,9 +102,33 @@ json_map! {
     author_name => "authorName",
     author_image => "authorImage",
     attachment => "attachment",
+    reply_to => "replyTo",
+    thread => "thread",
+    thread_replies => "threadReplies",
+    last_reply_time => "lastReplyTime",
+    reactions => "reactions",
+    mentions => "mentions",
+    edited_at => "editedAt",
+    deleted_by => "deletedBy",
+    delete_reason => "deleteReason",
+    blocked => "blocked",
     send_time => "sendTime"
 }
 
+json_map! {
+    Revision,
+    content => "content",
+    edit_time => "editTime"
+}
+
+json_map! {
+    Reply,
+    id => "id",
+    author_name => "authorName",
+    content => "content",
+    deleted => "deleted"
+}
+
 json_map! {
     Attachment,
     id => "id",
@@ -70,6 +149,10 @@ impl Message {
             Value,          // 5. Message send time
             Option<String>, // 6. Attachment ID
             Option<String>, // 7. Attachment name
+            Option<String>, // 8. Replied message ID
+            Option<String>, // 9. Thread root message ID
+            Option<Value>,  // 10. Message edit time
+            Option<String>, // 11. Deleting user ID
         ),
     ) -> Self {
         Message {
@@ -78,20 +161,160 @@ impl Message {
             author_id: row.2,
             author_name: row.3,
             author_image: row.4,
-            attachment: row.6.map(|id| {
-                let name = row.7.unwrap();
-                Attachment {
-                    id,
-                    name: name.clone(),
-                    type_: MimeType::from_extension(name.split('.').last().unwrap_or(""))
-                        .to_string(),
-                }
-            }),
+            attachment: row.6.map(|id| Attachment::new(id, row.7.unwrap())),
+            reply_to: row.8.map(Reply::deleted),
+            thread: row.9,
+            thread_replies: 0,
+            last_reply_time: None,
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+            edited_at: row.10.map(parse_date),
+            deleted_by: row.11,
+            delete_reason: None,
+            blocked: false,
             send_time: parse_date(row.5),
         }
     }
 }
 
+impl Attachment {
+    /// Creates an attachment response for the file with the given ID and name.
+    pub(crate) fn new(id: String, name: String) -> Self {
+        Attachment {
+            type_: MimeType::from_extension(name.split('.').next_back().unwrap_or("")).to_string(),
+            id,
+            name,
+        }
+    }
+}
+
+impl Revision {
+    /// Converts a row of the database to a revision.
+    pub(crate) fn from_row(row: (String, Value)) -> Self {
+        Revision {
+            content: row.0,
+            edit_time: parse_date(row.1),
+        }
+    }
+}
+
+impl Reply {
+    /// Converts a row of the database to a reply preview.
+    pub(crate) fn from_row(row: (String, String, String)) -> Self {
+        Reply {
+            id: row.0,
+            author_name: Some(row.1),
+            content: Some(row.2.chars().take(REPLY_PREVIEW_LENGTH).collect()),
+            deleted: false,
+        }
+    }
+
+    /// Creates a preview of a referenced message which no longer exists.
+    ///
+    /// Messages are loaded with this placeholder, which is replaced by `resolve_details` if the
+    /// referenced message still exists.
+    fn deleted(id: String) -> Self {
+        Reply {
+            id,
+            author_name: None,
+            content: None,
+            deleted: true,
+        }
+    }
+}
+
+/// Fills in the reply previews, thread summaries, reactions, mentions and deletion reasons of the given messages,
+/// and flags the messages whose authors the viewer has blocked.
+///
+/// Reactions are marked as the viewer's own if the viewer is given.
+pub(crate) fn resolve_details(
+    transaction: &mut Transaction,
+    viewer: Option<&str>,
+    messages: &mut [Message],
+) -> Result<(), String> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let blocked = match viewer {
+        Some(viewer) => transaction.select_blocked_ids(viewer)?,
+        None => Vec::new(),
+    };
+
+    // The details of every message are loaded at once, rather than with a set of queries per message
+    let ids = id_list(messages.iter().map(|message| message.id.as_str()));
+    let reply_ids = id_list(
+        messages
+            .iter()
+            .filter_map(|message| message.reply_to.as_ref())
+            .map(|reply| reply.id.as_str()),
+    );
+
+    let mut reactions: HashMap<String, Vec<Reaction>> = HashMap::new();
+    let mut mentions: HashMap<String, Vec<Mention>> = HashMap::new();
+
+    for (message, reaction) in
+        transaction.select_reactions_by_messages(viewer.unwrap_or(""), &ids)?
+    {
+        reactions.entry(message).or_default().push(reaction);
+    }
+
+    for (message, mention) in transaction.select_mentions_by_messages(&ids)? {
+        mentions.entry(message).or_default().push(mention);
+    }
+
+    let previews: HashMap<String, Reply> = transaction
+        .select_reply_previews(&reply_ids)?
+        .into_iter()
+        .map(|preview| (preview.id.clone(), preview))
+        .collect();
+
+    let mut delete_reasons: HashMap<String, String> = transaction
+        .select_delete_reasons(&ids)?
+        .into_iter()
+        .collect();
+
+    let mut thread_summaries: HashMap<String, (u64, Value)> = transaction
+        .select_thread_summaries(&ids)?
+        .into_iter()
+        .map(|(message, replies, last_reply_time)| (message, (replies, last_reply_time)))
+        .collect();
+
+    for message in messages.iter_mut() {
+        message.blocked = blocked.contains(&message.author_id);
+        message.reactions = reactions.remove(&message.id).unwrap_or_default();
+        message.mentions = mentions.remove(&message.id).unwrap_or_default();
+
+        if let Some(reply) = message.reply_to.as_mut() {
+            if let Some(preview) = previews.get(&reply.id) {
+                *reply = preview.clone();
+            }
+        }
+
+        if message.deleted_by.is_some() {
+            message.delete_reason = delete_reasons.remove(&message.id);
+        }
+
+        if message.thread.is_none() {
+            if let Some((replies, last_reply_time)) = thread_summaries.remove(&message.id) {
+                message.thread_replies = replies;
+                message.last_reply_time = Some(parse_date(last_reply_time));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats the given IDs as a JSON array, which queries expand into a table to match many rows at once.
+fn id_list<'a>(ids: impl Iterator<Item = &'a str>) -> String {
+    humphrey_json::Value::Array(
+        ids.map(|id| humphrey_json::Value::String(id.to_string()))
+            .collect(),
+    )
+    .serialize()
+}
+
 impl State {
     /// Gets the messages for the given subset.
     pub fn messages(
@@ -104,19 +327,26 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let user = transaction
-            .select_username_by_subset_membership_token(token.as_ref(), subset.as_ref())?;
+        let user = transaction.select_username_by_subset_membership_token(
+            Token(token.as_ref()),
+            subset.as_ref(),
+            Permission::ManageSubsets as u64,
+        )?;
 
         if user.is_none() {
             return Err("Insufficient permissions".to_string());
         }
 
-        let messages = if let Some(before) = before {
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
+
+        let mut messages = if let Some(before) = before {
             transaction.select_messages_before(subset.as_ref(), &before, limit.unwrap_or(25))?
         } else {
             transaction.select_messages(subset.as_ref(), limit.unwrap_or(25))?
         };
 
+        resolve_details(&mut transaction, uid.as_deref(), &mut messages)?;
+
         transaction.commit()?;
 
         crate::log!(
@@ -128,7 +358,58 @@ impl State {
         Ok(messages)
     }
 
+    /// Gets the replies in the thread under the given message.
+    pub fn thread(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+        before: Option<String>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Message>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (_, subset) = transaction
+            .select_message_set_and_subset(message.as_ref())?
+            .ok_or_else(|| "Message not found".to_string())?;
+
+        let user = transaction.select_username_by_subset_membership_token(
+            Token(token.as_ref()),
+            &subset,
+            Permission::ManageSubsets as u64,
+        )?;
+
+        if user.is_none() {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
+
+        let mut messages = if let Some(before) = before {
+            transaction.select_thread_messages_before(
+                message.as_ref(),
+                &before,
+                limit.unwrap_or(25),
+            )?
+        } else {
+            transaction.select_thread_messages(message.as_ref(), limit.unwrap_or(25))?
+        };
+
+        resolve_details(&mut transaction, uid.as_deref(), &mut messages)?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} retrieved thread for message {}",
+            user.unwrap(),
+            message.as_ref()
+        );
+
+        Ok(messages)
+    }
+
     /// Sends a message to the given subset.
+    #[allow(clippy::too_many_arguments)]
     pub fn send_message(
         &self,
         token: impl AsRef<str>,
@@ -136,11 +417,17 @@ impl State {
         content: impl AsRef<str>,
         attachment_name: Option<String>,
         attachment_content: Option<String>,
+        reply_to: Option<String>,
+        thread: Option<String>,
     ) -> Result<(), String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let meta = transaction.select_subset_metadata(token.as_ref(), subset.as_ref())?;
+        let meta = transaction.select_subset_metadata(
+            Token(token.as_ref()),
+            subset.as_ref(),
+            Permission::ManageSubsets as u64,
+        )?;
 
         if meta.is_none() {
             return Err("Insufficient permissions".to_string());
@@ -148,30 +435,48 @@ impl State {
 
         let (set_id, user_id, author_name, author_image) = meta.unwrap();
 
-        let attachment = if let Some(attachment_name) = attachment_name {
-            Some((
-                attachment_name,
-                attachment_content.ok_or_else(|| "No attachment content provided".to_string())?,
-            ))
-        } else {
-            None
-        };
+        let reply = if let Some(reply_to) = reply_to.as_ref() {
+            let same_subset = transaction
+                .select_message_set_and_subset(reply_to)?
+                .map(|(_, reply_subset)| reply_subset == subset.as_ref())
+                .unwrap_or(false);
 
-        let attachment_id = if let Some((attachment_name, attachment_content)) = attachment.as_ref()
-        {
-            let attachment_content = base64::decode(attachment_content)
-                .map_err(|_| "Could not decode attachment".to_string())?;
-
-            Some(self.set_file(
-                attachment_name,
-                attachment_content,
-                &user_id,
-                &mut transaction,
-            )?)
+            if !same_subset {
+                return Err("Replied message not found".to_string());
+            }
+
+            Some(
+                transaction
+                    .select_reply_preview(reply_to)?
+                    .ok_or_else(|| "Replied message not found".to_string())?,
+            )
         } else {
             None
         };
 
+        if let Some(thread) = thread.as_ref() {
+            let is_root = transaction
+                .select_message_subset_and_thread(thread)?
+                .map(|(thread_subset, parent)| thread_subset == subset.as_ref() && parent.is_none())
+                .unwrap_or(false);
+
+            if !is_root {
+                return Err("Thread not found".to_string());
+            }
+        }
+
+        let attachment = self.store_attachment(
+            attachment_name,
+            attachment_content,
+            &user_id,
+            &mut transaction,
+        )?;
+        let attachment_id = attachment.as_ref().map(|attachment| attachment.id.clone());
+
+        let viewers = transaction
+            .select_user_ids_by_subset_access(subset.as_ref(), Permission::ManageSubsets as u64)?;
+        let mentions = resolve_mentions(&mut transaction, &viewers, content.as_ref())?;
+
         let new_message_id = Uuid::new_v4().to_string();
 
         transaction.insert_message(
@@ -180,10 +485,21 @@ impl State {
             subset.as_ref(),
             &user_id,
             &attachment_id,
+            &reply_to,
+            &thread,
         )?;
 
+        for (position, mention) in mentions.iter().enumerate() {
+            transaction.insert_mention(&new_message_id, position, &mention.kind, &mention.uid)?;
+        }
+
+        let blockers = transaction.select_blocker_ids(&user_id)?;
+
         transaction.commit()?;
 
+        let mut recipients = mention_recipients(&mentions, &viewers, &user_id);
+        recipients.retain(|uid| !blockers.contains(uid));
+
         let send_time = UNIX_EPOCH.elapsed().unwrap().as_secs();
 
         let message = Message {
@@ -193,14 +509,26 @@ impl State {
             author_name,
             author_image,
             send_time,
-            attachment: attachment.map(|(name, _)| Attachment {
-                id: attachment_id.unwrap(),
-                name: name.clone(),
-                type_: MimeType::from_extension(name.split('.').last().unwrap_or("")).to_string(),
-            }),
+            attachment,
+            reply_to: reply,
+            thread: thread.clone(),
+            thread_replies: 0,
+            last_reply_time: None,
+            reactions: Vec::new(),
+            mentions,
+            edited_at: None,
+            deleted_by: None,
+            delete_reason: None,
+            blocked: false,
         };
 
-        self.broadcast_message(set_id, subset.as_ref(), message, false);
+        if let Some(thread) = thread {
+            self.broadcast_thread_message(&set_id, subset.as_ref(), thread, message.clone(), false);
+        } else {
+            self.broadcast_message(&set_id, subset.as_ref(), message.clone(), false);
+        }
+
+        self.alert_mentioned_users(set_id, subset.as_ref(), message, &recipients);
 
         crate::log!(
             "User {} sent message with ID {} to subset {}",
@@ -212,52 +540,170 @@ impl State {
         Ok(())
     }
 
+    /// Decodes the given base64 attachment and adds it to the file store, if one was given.
+    pub(crate) fn store_attachment(
+        &self,
+        name: Option<String>,
+        content: Option<String>,
+        owner: &str,
+        transaction: &mut Transaction,
+    ) -> Result<Option<Attachment>, String> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let content = content.ok_or_else(|| "No attachment content provided".to_string())?;
+        let content =
+            base64::decode(content).map_err(|_| "Could not decode attachment".to_string())?;
+
+        let id = self.set_file(&name, content, owner, transaction)?;
+
+        Ok(Some(Attachment::new(id, name)))
+    }
+
     /// Updates or deletes the given message.
+    ///
+    /// Only the author can edit a message, but members with the "delete messages" permission can
+    /// delete any message in the set. Deleted messages are kept as tombstones so that replies and
+    /// threads referencing them remain valid, and their edit history remains available to moderators.
     pub fn update_message(
         &self,
         token: impl AsRef<str>,
         message_id: impl AsRef<str>,
         content: Option<String>,
         delete: Option<bool>,
+        reason: Option<String>,
     ) -> Result<(), String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let message =
-            transaction.select_message_by_id_and_token(message_id.as_ref(), token.as_ref())?;
+        let (set, subset) = transaction
+            .select_message_set_and_subset(message_id.as_ref())?
+            .ok_or_else(|| "Insufficient permissions".to_string())?;
 
-        if message.is_none() {
-            return Err("Insufficient permissions".to_string());
-        }
+        let (permissions, user_id) = transaction
+            .select_membership(Token(token.as_ref()), &set)?
+            .ok_or_else(|| "Insufficient permissions".to_string())?;
 
-        let mut message = message.unwrap();
-        let user_id = message.author_id.clone();
+        let mut message = transaction
+            .select_message_by_id(message_id.as_ref())?
+            .ok_or_else(|| "Insufficient permissions".to_string())?;
 
-        let (set, subset) = transaction
-            .select_message_set_and_subset(&message.id)?
-            .ok_or_else(|| "Message not found".to_string())?;
+        let is_author = message.author_id == user_id;
+
+        resolve_details(&mut transaction, None, std::slice::from_mut(&mut message))?;
 
         if delete == Some(true) {
-            transaction.delete_message(&message.id)?;
+            if !is_author && !Permission::DeleteMessages.granted_by(permissions) {
+                return Err("Insufficient permissions".to_string());
+            }
+
+            transaction.delete_message_reactions(&message.id)?;
+            transaction.delete_pin(&message.id)?;
+            transaction.delete_message_mentions(&message.id)?;
+            transaction.soft_delete_message(&user_id, &reason, &message.id)?;
+
+            if let Some(attachment) = message.attachment.take() {
+                transaction.delete_file(&attachment.id)?;
+            }
+
             transaction.commit()?;
 
-            self.broadcast_message(set, subset, message, true);
+            message.content = String::new();
+            message.reactions = Vec::new();
+            message.mentions = Vec::new();
+            message.deleted_by = Some(user_id.clone());
+            message.delete_reason = reason;
+
+            self.broadcast_message_update(set, subset, message, true);
 
             crate::log!("User {} deleted message {}", user_id, message_id.as_ref());
         } else if let Some(content) = content {
+            if !is_author {
+                return Err("Insufficient permissions".to_string());
+            }
+
+            transaction.insert_message_revision(
+                &Uuid::new_v4().to_string(),
+                &message.id,
+                &message.content,
+            )?;
             transaction.update_message(&content, message_id.as_ref())?;
+
+            let viewers = transaction
+                .select_user_ids_by_subset_access(&subset, Permission::ManageSubsets as u64)?;
+            let mentions = resolve_mentions(&mut transaction, &viewers, &content)?;
+
+            transaction.delete_message_mentions(&message.id)?;
+
+            for (position, mention) in mentions.iter().enumerate() {
+                transaction.insert_mention(&message.id, position, &mention.kind, &mention.uid)?;
+            }
+
             transaction.commit()?;
 
             message.content = content;
+            message.mentions = mentions;
+            message.edited_at = Some(UNIX_EPOCH.elapsed().unwrap().as_secs());
 
-            self.broadcast_message(set, subset, message, false);
+            self.broadcast_message_update(set, subset, message, false);
 
-            crate::log!("User {} updated subset {}", user_id, message_id.as_ref());
+            crate::log!("User {} updated message {}", user_id, message_id.as_ref());
         }
 
         Ok(())
     }
 
+    /// Gets the previous versions of the given message, oldest first.
+    pub fn message_history(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+    ) -> Result<Vec<Revision>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (set, _) = transaction
+            .select_message_set_and_subset(message.as_ref())?
+            .ok_or_else(|| "Message not found".to_string())?;
+
+        let (permissions, uid) = transaction
+            .select_membership(Token(token.as_ref()), &set)?
+            .ok_or_else(|| "Insufficient permissions".to_string())?;
+
+        if !Permission::DeleteMessages.granted_by(permissions) {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let revisions = transaction.select_message_revisions(message.as_ref())?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} retrieved the edit history of message {}",
+            uid,
+            message.as_ref()
+        );
+
+        Ok(revisions)
+    }
+
+    /// Broadcasts an update to the given message, using the thread event if the message belongs to a thread.
+    fn broadcast_message_update(
+        &self,
+        set: String,
+        subset: String,
+        message: Message,
+        deleted: bool,
+    ) {
+        if let Some(thread) = message.thread.clone() {
+            self.broadcast_thread_message(set, subset, thread, message, deleted);
+        } else {
+            self.broadcast_message(set, subset, message, deleted);
+        }
+    }
+
     /// Updates the user's typing status.
     pub fn set_typing(
         &self,
@@ -267,19 +713,26 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let uid = transaction.select_id_by_token(token.as_ref())?;
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
         let set = transaction.select_set_by_subset(subset.as_ref())?;
-        let in_set = uid
+        let can_post = uid
             .as_ref()
-            .and_then(|uid| set.as_ref().map(|set| (uid, set)))
-            .and_then(|(uid, set)| transaction.select_user_has_membership(uid, set).ok())
+            .and_then(|uid| {
+                transaction
+                    .select_user_can_post_in_subset(
+                        uid,
+                        subset.as_ref(),
+                        Permission::ManageSubsets as u64,
+                    )
+                    .ok()
+            })
             .unwrap_or(false);
 
         transaction.commit()?;
 
         if let Some(uid) = uid {
             if let Some(set) = set {
-                if in_set {
+                if can_post {
                     self.broadcast_typing(set, subset, uid);
 
                     Ok(())
@@ -1,9 +1,22 @@
 //! Provides the core functionality of the server.
 
+pub mod account;
 pub mod auth;
+pub mod dms;
 pub mod files;
+pub mod friends;
 pub mod invites;
+pub mod mentions;
 pub mod messages;
+pub mod passwords;
+pub mod pins;
+pub mod reactions;
+pub mod read_markers;
+pub mod roles;
+pub mod search;
+pub mod sessions;
 pub mod sets;
 pub mod subscriptions;
+pub mod two_factor;
 pub mod user;
+pub mod verification;
@@ -0,0 +1,119 @@
+//! Provides password changes and resets.
+
+use crate::server::auth::{
+    hash_password, start_session, validate_password, verify_password, AuthResponse,
+};
+use crate::util::Token;
+use crate::State;
+
+use uuid::Uuid;
+
+/// The number of minutes for which a password reset code is valid.
+const RESET_CODE_LIFETIME: usize = 15;
+
+impl State {
+    /// Changes the password of the authenticated user, given their current password.
+    ///
+    /// All of the user's sessions are signed out, and a new session is started to replace the current one.
+    pub fn change_password(
+        &self,
+        token: impl AsRef<str>,
+        password: impl AsRef<str>,
+        new_password: impl AsRef<str>,
+        device: Option<String>,
+        ip: String,
+    ) -> Result<AuthResponse, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (uid, hash) = transaction
+            .select_id_and_password_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if !verify_password(password.as_ref(), &hash) {
+            return Err("Incorrect password".to_string());
+        }
+
+        validate_password(new_password.as_ref())?;
+
+        transaction.update_user_password(&hash_password(new_password.as_ref())?, &uid)?;
+        transaction.delete_user_sessions(&uid)?;
+
+        let response = start_session(&mut transaction, uid, device, ip)?;
+
+        transaction.commit()?;
+
+        self.disconnect_user(&response.uid);
+
+        crate::log!("User {} changed their password", response.uid);
+
+        Ok(response)
+    }
+
+    /// Emails a single-use code to the given user which can be used to reset their password.
+    ///
+    /// This succeeds even if the user does not exist, so that it cannot be used to find out which usernames are taken.
+    pub fn request_password_reset(&self, username: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction.select_id_and_email_by_username(username.as_ref())?;
+        let code = Uuid::new_v4().to_string();
+
+        if let Some((uid, _)) = &user {
+            transaction.insert_password_reset(uid, Token(&code), RESET_CODE_LIFETIME)?;
+        }
+
+        transaction.commit()?;
+
+        // Failures are only logged, since returning them would reveal that the user exists.
+        if let Some((uid, email)) = user {
+            if let Err(e) = self.mailer.send(
+                &email,
+                "Reset your Equion password",
+                &format!(
+                    "Your password reset code is {}.\n\nIt expires in {} minutes. If you did not request a password reset, you can ignore this email.",
+                    code, RESET_CODE_LIFETIME
+                ),
+            ) {
+                crate::log!(Error, "Could not send password reset email to user {}: {}", uid, e);
+            }
+
+            crate::log!("User {} requested a password reset", uid);
+        }
+
+        Ok(())
+    }
+
+    /// Resets the password of the user the given code was sent to, signing out all of their sessions.
+    pub fn reset_password(
+        &self,
+        code: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (uid, expired) = transaction
+            .select_password_reset(Token(code.as_ref()))?
+            .ok_or_else(|| "Invalid reset code".to_string())?;
+
+        if expired {
+            return Err("Reset code expired".to_string());
+        }
+
+        validate_password(password.as_ref())?;
+
+        transaction.update_user_password(&hash_password(password.as_ref())?, &uid)?;
+        transaction.delete_password_reset(&uid)?;
+        transaction.delete_user_sessions(&uid)?;
+
+        transaction.commit()?;
+
+        self.disconnect_user(&uid);
+
+        crate::log!("User {} reset their password", uid);
+
+        Ok(())
+    }
+}
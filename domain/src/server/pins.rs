@@ -0,0 +1,121 @@
+//! Provides pinned messages in subsets.
+
+use crate::server::messages::{resolve_details, Message};
+use crate::server::roles::Permission;
+use crate::util::Token;
+use crate::State;
+
+impl State {
+    /// Gets the pinned messages of the given subset, in the order they were pinned.
+    pub fn pins(
+        &self,
+        token: impl AsRef<str>,
+        subset: impl AsRef<str>,
+    ) -> Result<Vec<Message>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction.select_username_by_subset_membership_token(
+            Token(token.as_ref()),
+            subset.as_ref(),
+            Permission::ManageSubsets as u64,
+        )?;
+
+        if user.is_none() {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let uid = transaction.select_id_by_token(Token(token.as_ref()))?;
+
+        let mut messages = transaction.select_pinned_messages(subset.as_ref())?;
+
+        resolve_details(&mut transaction, uid.as_deref(), &mut messages)?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} retrieved pinned messages for subset {}",
+            user.unwrap(),
+            subset.as_ref()
+        );
+
+        Ok(messages)
+    }
+
+    /// Pins the given message in its subset.
+    pub fn pin(&self, token: impl AsRef<str>, message: impl AsRef<str>) -> Result<(), String> {
+        self.set_pin(token, message, true)
+    }
+
+    /// Unpins the given message from its subset.
+    pub fn unpin(&self, token: impl AsRef<str>, message: impl AsRef<str>) -> Result<(), String> {
+        self.set_pin(token, message, false)
+    }
+
+    /// Pins or unpins a message, then broadcasts the change to the set.
+    fn set_pin(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+        pinned: bool,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (set, subset) = transaction
+            .select_message_set_and_subset(message.as_ref())?
+            .ok_or_else(|| "Message not found".to_string())?;
+
+        let (permissions, uid) = transaction
+            .select_membership(Token(token.as_ref()), &set)?
+            .ok_or_else(|| "Insufficient permissions".to_string())?;
+
+        if !Permission::DeleteMessages.granted_by(permissions)
+            || transaction
+                .select_username_by_subset_membership_token(
+                    Token(token.as_ref()),
+                    &subset,
+                    Permission::ManageSubsets as u64,
+                )?
+                .is_none()
+        {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let mut pinned_message = transaction
+            .select_message_by_id(message.as_ref())?
+            .ok_or_else(|| "Message not found".to_string())?;
+
+        let exists = transaction.select_pin_exists(message.as_ref())?;
+
+        if pinned {
+            if exists {
+                return Err("Message already pinned".to_string());
+            }
+
+            transaction.insert_pin(message.as_ref(), &uid)?;
+        } else {
+            if !exists {
+                return Err("Message not pinned".to_string());
+            }
+
+            transaction.delete_pin(message.as_ref())?;
+        }
+
+        resolve_details(
+            &mut transaction,
+            None,
+            std::slice::from_mut(&mut pinned_message),
+        )?;
+
+        transaction.commit()?;
+
+        self.broadcast_pin(set, subset, pinned_message, &uid, !pinned);
+
+        let action = if pinned { "pinned" } else { "unpinned" };
+
+        crate::log!("User {} {} message {}", uid, action, message.as_ref());
+
+        Ok(())
+    }
+}
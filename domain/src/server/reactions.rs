@@ -0,0 +1,142 @@
+//! Provides emoji reactions on messages.
+
+use crate::server::roles::Permission;
+use crate::util::Token;
+use crate::State;
+
+use humphrey_json::prelude::*;
+
+/// The maximum length of an emoji in bytes.
+const MAX_EMOJI_LENGTH: usize = 64;
+
+/// Represents the aggregated reactions with a single emoji on a message.
+#[derive(Clone)]
+pub struct Reaction {
+    /// The emoji of the reaction.
+    pub emoji: String,
+    /// The number of users who reacted with the emoji.
+    pub count: u64,
+    /// Whether the requesting user reacted with the emoji.
+    pub me: bool,
+}
+
+json_map! {
+    Reaction,
+    emoji => "emoji",
+    count => "count",
+    me => "me"
+}
+
+impl Reaction {
+    /// Converts a row of the database to a reaction.
+    pub(crate) fn from_row(row: (String, u64, bool)) -> Self {
+        Self {
+            emoji: row.0,
+            count: row.1,
+            me: row.2,
+        }
+    }
+}
+
+impl State {
+    /// Reacts to the given message with the given emoji.
+    pub fn react(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+        emoji: impl AsRef<str>,
+    ) -> Result<(), String> {
+        self.set_reaction(token, message, emoji, true)
+    }
+
+    /// Removes the user's reaction with the given emoji from the given message.
+    pub fn unreact(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+        emoji: impl AsRef<str>,
+    ) -> Result<(), String> {
+        self.set_reaction(token, message, emoji, false)
+    }
+
+    /// Adds or removes a reaction, then broadcasts the new count to the set.
+    fn set_reaction(
+        &self,
+        token: impl AsRef<str>,
+        message: impl AsRef<str>,
+        emoji: impl AsRef<str>,
+        reacted: bool,
+    ) -> Result<(), String> {
+        if emoji.as_ref().is_empty() || emoji.as_ref().len() > MAX_EMOJI_LENGTH {
+            return Err("Invalid emoji".to_string());
+        }
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (set, subset) = transaction
+            .select_message_set_and_subset(message.as_ref())?
+            .ok_or_else(|| "Message not found".to_string())?;
+
+        if transaction.select_message_is_deleted(message.as_ref())? {
+            return Err("Message not found".to_string());
+        }
+
+        if transaction
+            .select_username_by_subset_membership_token(
+                Token(token.as_ref()),
+                &subset,
+                Permission::ManageSubsets as u64,
+            )?
+            .is_none()
+        {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let exists = transaction.select_reaction_exists(message.as_ref(), &uid, emoji.as_ref())?;
+
+        if reacted {
+            if exists {
+                return Err("Already reacted with this emoji".to_string());
+            }
+
+            transaction.insert_reaction(message.as_ref(), &uid, emoji.as_ref())?;
+        } else {
+            if !exists {
+                return Err("Not reacted with this emoji".to_string());
+            }
+
+            transaction.delete_reaction(message.as_ref(), &uid, emoji.as_ref())?;
+        }
+
+        let count = transaction.select_reaction_count(message.as_ref(), emoji.as_ref())?;
+
+        transaction.commit()?;
+
+        self.broadcast_reaction(
+            set,
+            subset,
+            message.as_ref(),
+            emoji.as_ref(),
+            &uid,
+            count,
+            !reacted,
+        );
+
+        let action = if reacted { "added" } else { "removed" };
+
+        crate::log!(
+            "User {} {} reaction {} on message {}",
+            uid,
+            action,
+            emoji.as_ref(),
+            message.as_ref()
+        );
+
+        Ok(())
+    }
+}
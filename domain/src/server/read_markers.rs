@@ -0,0 +1,96 @@
+//! Provides tracking of which messages each user has read.
+
+use crate::server::roles::Permission;
+use crate::server::sets::Subset;
+use crate::util::Token;
+use crate::State;
+
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
+/// Fills in the unread message and mention counts of the given subsets of the given set for the given user.
+pub(crate) fn resolve_unread_counts(
+    transaction: &mut Transaction,
+    uid: &str,
+    set: &str,
+    subsets: &mut [Subset],
+) -> Result<(), String> {
+    let counts = transaction.select_unread_counts(uid, set)?;
+
+    for subset in subsets.iter_mut() {
+        (subset.unread, subset.mentions) = unread_counts(&counts, &subset.id);
+    }
+
+    Ok(())
+}
+
+/// Finds the unread message and mention counts of the given subset, which are only present if it has unread messages.
+fn unread_counts(counts: &[(String, u64, u64)], subset: &str) -> (u64, u64) {
+    counts
+        .iter()
+        .find(|(id, _, _)| id == subset)
+        .map(|&(_, unread, mentions)| (unread, mentions))
+        .unwrap_or((0, 0))
+}
+
+impl State {
+    /// Marks the messages of the given subset as read, up to and including the given message.
+    ///
+    /// If no message is given, every message currently in the subset is marked as read.
+    pub fn mark_read(
+        &self,
+        token: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        message: Option<String>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        if transaction
+            .select_username_by_subset_membership_token(
+                Token(token.as_ref()),
+                subset.as_ref(),
+                Permission::ManageSubsets as u64,
+            )?
+            .is_none()
+        {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let set = transaction
+            .select_set_by_subset(subset.as_ref())?
+            .ok_or_else(|| "Subset not found".to_string())?;
+
+        if let Some(message) = message.as_ref() {
+            let in_subset = transaction
+                .select_message_set_and_subset(message)?
+                .map(|(_, message_subset)| message_subset == subset.as_ref())
+                .unwrap_or(false);
+
+            if !in_subset {
+                return Err("Message not found".to_string());
+            }
+
+            transaction.update_read_marker(&uid, subset.as_ref(), message)?;
+        } else {
+            transaction.update_read_marker_to_latest(&uid, subset.as_ref(), subset.as_ref())?;
+        }
+
+        let counts = transaction.select_unread_counts(&uid, &set)?;
+        let (unread, mentions) = unread_counts(&counts, subset.as_ref());
+
+        transaction.commit()?;
+
+        self.alert_read(&uid, set, subset.as_ref(), unread, mentions);
+
+        crate::log!("User {} marked subset {} as read", uid, subset.as_ref());
+
+        Ok(())
+    }
+}
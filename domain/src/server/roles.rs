@@ -0,0 +1,242 @@
+//! Provides role and permission management for sets.
+
+use crate::util::Token;
+use crate::State;
+
+use humphrey_json::prelude::*;
+use uuid::Uuid;
+
+/// Represents a permission which can be granted to a role.
+///
+/// Each permission occupies a single bit of a role's permission bitmask.
+#[repr(u64)]
+#[derive(Clone, Copy)]
+pub enum Permission {
+    /// Permission to rename the set or change its icon.
+    ManageSet = 1,
+    /// Permission to create, rename and delete subsets.
+    ManageSubsets = 2,
+    /// Permission to create and revoke invites.
+    ManageInvites = 4,
+    /// Permission to kick members from the set.
+    Kick = 8,
+    /// Permission to delete messages sent by other members.
+    DeleteMessages = 16,
+    /// Permission to create roles and assign them to members.
+    ManageRoles = 32,
+}
+
+impl Permission {
+    /// The bitmask containing every permission.
+    pub const ALL: u64 = 63;
+
+    /// The bitmask given to the default moderator role of a new set.
+    pub const MODERATOR: u64 = Permission::ManageSubsets as u64
+        | Permission::ManageInvites as u64
+        | Permission::Kick as u64
+        | Permission::DeleteMessages as u64;
+
+    /// Returns whether the given permission bitmask includes this permission.
+    pub fn granted_by(self, permissions: u64) -> bool {
+        permissions & self as u64 != 0
+    }
+}
+
+/// Represents a role response from the server.
+pub struct Role {
+    /// The ID of the role.
+    pub id: String,
+    /// The name of the role.
+    pub name: String,
+    /// The kind of the role, one of `owner`, `moderator`, `member` or `custom`.
+    pub kind: String,
+    /// The permission bitmask of the role.
+    pub permissions: u64,
+    /// The IDs of the members who hold the role.
+    pub members: Vec<String>,
+}
+
+json_map! {
+    Role,
+    id => "id",
+    name => "name",
+    kind => "kind",
+    permissions => "permissions",
+    members => "members"
+}
+
+impl Role {
+    /// Converts a row of the database to a role.
+    pub(crate) fn from_row(row: (String, String, String, u64)) -> Self {
+        Self {
+            id: row.0,
+            name: row.1,
+            kind: row.2,
+            permissions: row.3,
+            members: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    /// Gets the roles of the given set, along with the members holding each role.
+    pub fn get_roles(
+        &self,
+        token: impl AsRef<str>,
+        set: impl AsRef<str>,
+    ) -> Result<Vec<Role>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (_, uid) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
+            .ok_or_else(|| "Invalid token or set".to_string())?;
+
+        let roles: Result<Vec<Role>, String> = transaction
+            .select_roles_by_set(set.as_ref())?
+            .into_iter()
+            .map(|mut role| {
+                role.members = transaction.select_user_ids_by_role(&role.id)?;
+                Ok(role)
+            })
+            .collect();
+
+        let roles = roles?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} retrieved roles for set {}", uid, set.as_ref());
+
+        Ok(roles)
+    }
+
+    /// Creates a custom role with the given name and permissions in the given set.
+    pub fn create_role(
+        &self,
+        token: impl AsRef<str>,
+        set: impl AsRef<str>,
+        name: impl AsRef<str>,
+        permissions: usize,
+    ) -> Result<String, String> {
+        let permissions = u64::try_from(permissions)
+            .ok()
+            .filter(|permissions| permissions & !Permission::ALL == 0)
+            .ok_or_else(|| "Invalid permissions".to_string())?;
+
+        if name.as_ref().trim().is_empty() {
+            return Err("Role name cannot be empty".to_string());
+        }
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (own_permissions, uid) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
+            .ok_or_else(|| "Invalid token or set".to_string())?;
+
+        if !Permission::ManageRoles.granted_by(own_permissions) {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        if permissions & !own_permissions != 0 {
+            return Err("Cannot grant permissions you do not have".to_string());
+        }
+
+        let new_role_id = Uuid::new_v4().to_string();
+
+        transaction.insert_role(
+            &new_role_id,
+            set.as_ref(),
+            name.as_ref(),
+            "custom",
+            permissions,
+        )?;
+        transaction.commit()?;
+
+        self.broadcast_role(
+            set.as_ref(),
+            Role {
+                id: new_role_id.clone(),
+                name: name.as_ref().to_string(),
+                kind: "custom".to_string(),
+                permissions,
+                members: Vec::new(),
+            },
+        );
+
+        crate::log!(
+            "User {} created role {} in set {}",
+            uid,
+            new_role_id,
+            set.as_ref()
+        );
+
+        Ok(new_role_id)
+    }
+
+    /// Assigns the given role to the given member of the set.
+    pub fn assign_role(
+        &self,
+        token: impl AsRef<str>,
+        set: impl AsRef<str>,
+        uid: impl AsRef<str>,
+        role: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (own_permissions, assigner_id) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
+            .ok_or_else(|| "Invalid token or set".to_string())?;
+
+        if !Permission::ManageRoles.granted_by(own_permissions) {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let mut new_role = transaction
+            .select_role_by_id_and_set(role.as_ref(), set.as_ref())?
+            .ok_or_else(|| "Role not found".to_string())?;
+
+        let mut old_role = transaction
+            .select_role_by_user_and_set(uid.as_ref(), set.as_ref())?
+            .ok_or_else(|| "Target user not in set".to_string())?;
+
+        if new_role.kind == "owner" {
+            return Err("The owner role cannot be assigned".to_string());
+        }
+
+        if old_role.kind == "owner" {
+            return Err("Cannot change the role of the owner".to_string());
+        }
+
+        if (new_role.permissions | old_role.permissions) & !own_permissions != 0 {
+            return Err("Cannot grant permissions you do not have".to_string());
+        }
+
+        if old_role.id == new_role.id {
+            return Ok(());
+        }
+
+        transaction.update_membership_role(&new_role.id, uid.as_ref(), set.as_ref())?;
+
+        old_role.members = transaction.select_user_ids_by_role(&old_role.id)?;
+        new_role.members = transaction.select_user_ids_by_role(&new_role.id)?;
+
+        transaction.commit()?;
+
+        let new_role_id = new_role.id.clone();
+
+        self.broadcast_role(set.as_ref(), old_role);
+        self.broadcast_role(set.as_ref(), new_role);
+
+        crate::log!(
+            "User {} assigned role {} to user {} in set {}",
+            assigner_id,
+            new_role_id,
+            uid.as_ref(),
+            set.as_ref()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Provides full-text search of messages across the user's sets.
+
+use crate::server::messages::{resolve_details, Message};
+use crate::server::roles::Permission;
+use crate::util::Token;
+use crate::State;
+
+use humphrey_json::prelude::*;
+
+/// The maximum number of results which can be returned in a single page.
+const MAX_SEARCH_LIMIT: usize = 100;
+
+/// Represents a search result response from the server.
+pub struct SearchResult {
+    /// The ID of the set containing the message.
+    pub set: String,
+    /// The ID of the subset containing the message.
+    pub subset: String,
+    /// The matching message.
+    pub message: Message,
+    /// The locations of the search terms in the content of the message.
+    pub highlights: Vec<Highlight>,
+}
+
+/// Represents the location of a search term in the content of a message.
+pub struct Highlight {
+    /// The offset of the match, in characters.
+    pub start: usize,
+    /// The length of the match, in characters.
+    pub length: usize,
+}
+
+json_map! {
+    SearchResult,
+    set => "set",
+    subset => "subset",
+    message => "message",
+    highlights => "highlights"
+}
+
+json_map! {
+    Highlight,
+    start => "start",
+    length => "length"
+}
+
+impl State {
+    /// Searches the messages of the subsets the user can view.
+    #[allow(clippy::too_many_arguments)]
+    pub fn search(
+        &self,
+        token: impl AsRef<str>,
+        query: impl AsRef<str>,
+        set: Option<String>,
+        subset: Option<String>,
+        author: Option<String>,
+        has_attachment: Option<bool>,
+        after: Option<usize>,
+        before: Option<usize>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<Vec<SearchResult>, String> {
+        let terms = search_terms(query.as_ref());
+
+        if terms.is_empty() {
+            return Err("Search query cannot be empty".to_string());
+        }
+
+        let boolean_query = terms
+            .iter()
+            .map(|term| format!("+{}*", term))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let rows = transaction.select_search_messages(
+            &uid,
+            &boolean_query,
+            Permission::ManageSubsets as u64,
+            &set,
+            &subset,
+            &author,
+            has_attachment.unwrap_or(false),
+            &after.map(|after| after as u64),
+            &before.map(|before| before as u64),
+            limit.unwrap_or(25).min(MAX_SEARCH_LIMIT),
+            offset.unwrap_or(0),
+        )?;
+
+        let (mut messages, locations): (Vec<Message>, Vec<(String, String)>) = rows
+            .into_iter()
+            .map(|(message, set, subset)| (message, (set, subset)))
+            .unzip();
+
+        resolve_details(&mut transaction, Some(&uid), &mut messages)?;
+
+        transaction.commit()?;
+
+        let results = messages
+            .into_iter()
+            .zip(locations)
+            .map(|(message, (set, subset))| SearchResult {
+                set,
+                subset,
+                highlights: highlights(&message.content, &terms),
+                message,
+            })
+            .collect::<Vec<_>>();
+
+        crate::log!(
+            "User {} searched for messages and found {} results",
+            uid,
+            results.len()
+        );
+
+        Ok(results)
+    }
+}
+
+/// Splits a search query into terms with their case folded, removing characters with special meaning to the database.
+fn search_terms(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .map(|term| {
+            term.chars()
+                .filter(|c| !"+-<>()~*\"@".contains(*c))
+                .map(fold_case)
+                .collect::<String>()
+        })
+        .filter(|term| !term.is_empty())
+        .collect()
+}
+
+/// Finds the locations of the given case-folded terms at the start of words in the content, as matched by the database.
+fn highlights(content: &str, terms: &[String]) -> Vec<Highlight> {
+    let content: Vec<char> = content.chars().map(fold_case).collect();
+
+    let mut highlights: Vec<Highlight> = Vec::new();
+
+    for term in terms {
+        let term: Vec<char> = term.chars().collect();
+
+        for start in 0..content.len() {
+            let word_start = start == 0 || !is_word_char(content[start - 1]);
+
+            if word_start && content[start..].starts_with(&term) {
+                highlights.push(Highlight {
+                    start,
+                    length: term.len(),
+                });
+            }
+        }
+    }
+
+    highlights.sort_by_key(|highlight| highlight.start);
+
+    highlights
+}
+
+/// Folds the case of a character, keeping a single character so that offsets in the folded content match the original.
+fn fold_case(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// Checks whether the character can be part of a word, as defined by the database's full-text index.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
@@ -0,0 +1,136 @@
+//! Provides management of the signed-in sessions of each user.
+
+use crate::util::{parse_date, Token};
+use crate::State;
+
+use humphrey_json::prelude::*;
+
+use mysql::Value;
+
+/// Represents a signed-in session response from the server.
+pub struct Session {
+    /// The ID of the session, which is distinct from its token.
+    pub id: String,
+    /// The name of the device the session was started on, if given.
+    pub device: Option<String>,
+    /// The IP address the session was started from.
+    pub ip: String,
+    /// The time at which the session was started.
+    pub created: u64,
+    /// The time at which the session was last used.
+    pub last_used: u64,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+json_map! {
+    Session,
+    id => "id",
+    device => "device",
+    ip => "ip",
+    created => "created",
+    last_used => "lastUsed",
+    current => "current"
+}
+
+impl Session {
+    /// Converts a row of the database to a session.
+    pub(crate) fn from_row(row: (String, Option<String>, String, Value, Value, bool)) -> Self {
+        Self {
+            id: row.0,
+            device: row.1,
+            ip: row.2,
+            created: parse_date(row.3),
+            last_used: parse_date(row.4),
+            current: row.5,
+        }
+    }
+}
+
+impl State {
+    /// Gets the signed-in sessions of the authenticated user, most recently used first.
+    pub fn sessions(&self, token: impl AsRef<str>) -> Result<Vec<Session>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let sessions = transaction.select_sessions_by_user(Token(token.as_ref()), &uid)?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} retrieved sessions", uid);
+
+        Ok(sessions)
+    }
+
+    /// Revokes the given session of the authenticated user, signing it out.
+    pub fn revoke_session(
+        &self,
+        token: impl AsRef<str>,
+        session: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if transaction.select_session_owner(session.as_ref())? != Some(uid.clone()) {
+            return Err("Session not found".to_string());
+        }
+
+        transaction.delete_session(session.as_ref())?;
+
+        transaction.commit()?;
+
+        self.disconnect_session(session.as_ref());
+
+        crate::log!("User {} revoked session {}", uid, session.as_ref());
+
+        Ok(())
+    }
+
+    /// Revokes every session of the authenticated user, including the one making the request.
+    pub fn logout_everywhere(&self, token: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        transaction.delete_user_sessions(&uid)?;
+
+        transaction.commit()?;
+
+        self.disconnect_user(&uid);
+
+        crate::log!("User {} logged out everywhere", uid);
+
+        Ok(())
+    }
+
+    /// Deletes every session whose refresh token has expired, since such sessions can never be used again.
+    ///
+    /// Expired two-factor login challenges and data exports are deleted at the same time.
+    pub fn delete_expired_sessions(&self) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        transaction.delete_expired_login_challenges()?;
+        transaction.delete_expired_data_exports()?;
+        transaction.delete_expired_sessions()?;
+
+        let affected_rows = transaction.inner.affected_rows();
+
+        transaction.commit()?;
+
+        crate::log!(Debug, "Deleted {} expired sessions", affected_rows);
+
+        Ok(())
+    }
+}
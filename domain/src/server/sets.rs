@@ -2,11 +2,18 @@
 
 use std::time::UNIX_EPOCH;
 
-use crate::server::user::User;
-use crate::util::get_greek_letter;
+use crate::server::read_markers::resolve_unread_counts;
+use crate::server::roles::{Permission, Role};
+use crate::server::user::{User, Viewer};
+use crate::util::{get_greek_letter, Token};
 use crate::voice::user::WrappedVoiceUser;
 use crate::State;
 
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
 use humphrey_json::prelude::*;
 use uuid::Uuid;
 
@@ -18,8 +25,16 @@ pub struct Set {
     pub name: String,
     /// The icon of the set.
     pub icon: String,
-    /// Whether the requesting user has administrative privileges for the set.
+    /// The ID of the owner of the set.
+    ///
+    /// This is only `None` for sets which had no members when ownership was introduced.
+    pub owner: Option<String>,
+    /// Whether the requesting user holds every permission in the set.
     pub admin: bool,
+    /// The ID of the requesting user's role in the set.
+    pub role: String,
+    /// The permission bitmask of the requesting user's role in the set.
+    pub permissions: u64,
     /// The subsets of the set.
     pub subsets: Vec<Subset>,
     /// The members of the set.
@@ -34,16 +49,37 @@ pub struct Subset {
     pub id: String,
     /// The name of the subset.
     pub name: String,
+    /// Whether the requesting user can send messages in the subset.
+    pub can_post: bool,
+    /// The number of messages the requesting user has not read in the subset.
+    pub unread: u64,
+    /// The number of unread messages in the subset which mention the requesting user.
+    pub mentions: u64,
+}
+
+/// Represents a permission override for a role in a subset.
+///
+/// Roles without an override can view and post in the subset.
+pub struct SubsetPermission {
+    /// The ID of the role the override applies to.
+    pub role: String,
+    /// Whether members with the role can view the subset.
+    pub view: bool,
+    /// Whether members with the role can send messages in the subset.
+    pub post: bool,
 }
 
 impl Set {
     /// Converts a row of the database to a set.
-    pub(crate) fn from_row(row: (String, String, String, bool)) -> Self {
+    pub(crate) fn from_row(row: (String, String, String, Option<String>, String, u64)) -> Self {
         Self {
             id: row.0,
             name: row.1,
             icon: row.2,
-            admin: row.3,
+            owner: row.3,
+            admin: row.5 & Permission::ALL == Permission::ALL,
+            role: row.4,
+            permissions: row.5,
             subsets: Vec::new(),
             members: Vec::new(),
             voice_members: Vec::new(),
@@ -53,10 +89,24 @@ impl Set {
 
 impl Subset {
     /// Converts a row of the database to a subset.
-    pub(crate) fn from_row(row: (String, String)) -> Self {
+    pub(crate) fn from_row(row: (String, String, bool)) -> Self {
         Self {
             id: row.0,
             name: row.1,
+            can_post: row.2,
+            unread: 0,
+            mentions: 0,
+        }
+    }
+}
+
+impl SubsetPermission {
+    /// Converts a row of the database to a subset permission override.
+    pub(crate) fn from_row(row: (String, bool, bool)) -> Self {
+        Self {
+            role: row.0,
+            view: row.1,
+            post: row.2,
         }
     }
 }
@@ -66,7 +116,10 @@ json_map! {
     id => "id",
     name => "name",
     icon => "icon",
+    owner => "owner",
     admin => "admin",
+    role => "role",
+    permissions => "permissions",
     subsets => "subsets",
     members => "members",
     voice_members => "voiceMembers"
@@ -75,7 +128,17 @@ json_map! {
 json_map! {
     Subset,
     id => "id",
-    name => "name"
+    name => "name",
+    can_post => "canPost",
+    unread => "unread",
+    mentions => "mentions"
+}
+
+json_map! {
+    SubsetPermission,
+    role => "role",
+    view => "view",
+    post => "post"
 }
 
 impl State {
@@ -84,24 +147,33 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let user = transaction.select_username_by_token(token.as_ref())?;
+        let user = transaction.select_username_by_token(Token(token.as_ref()))?;
 
         if user.is_none() {
             return Err("Invalid token".to_string());
         }
 
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .unwrap_or_default();
+
         let sets: Result<Vec<Set>, String> = transaction
-            .select_sets_by_token(token.as_ref())?
+            .select_sets_by_token(Token(token.as_ref()))?
             .into_iter()
             .map(|mut set| {
-                set.subsets = transaction.select_subsets_by_set(&set.id)?;
+                set.subsets = transaction.select_subsets_by_set_and_role(
+                    Permission::ManageSubsets as u64,
+                    &set.role,
+                    &set.id,
+                )?;
+                resolve_unread_counts(&mut transaction, &uid, &set.id, &mut set.subsets)?;
 
                 set.members = transaction
                     .select_users_by_set(&set.id)?
                     .into_iter()
                     .map(|mut user| {
-                        user.online = self.voice.is_user_online(&user.uid);
-                        user
+                        user.resolve_presence(&self.voice);
+                        user.view_as(Viewer::Contact)
                     })
                     .collect();
 
@@ -136,17 +208,26 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .unwrap_or_default();
+
         let set: Option<Result<Set, String>> = transaction
-            .select_set_by_id_and_token(token.as_ref(), id.as_ref())?
+            .select_set_by_id_and_token(Token(token.as_ref()), id.as_ref())?
             .map(|mut set| {
-                set.subsets = transaction.select_subsets_by_set(&set.id)?;
+                set.subsets = transaction.select_subsets_by_set_and_role(
+                    Permission::ManageSubsets as u64,
+                    &set.role,
+                    &set.id,
+                )?;
+                resolve_unread_counts(&mut transaction, &uid, &set.id, &mut set.subsets)?;
 
                 set.members = transaction
                     .select_users_by_set(&set.id)?
                     .into_iter()
                     .map(|mut user| {
-                        user.online = self.voice.is_user_online(&user.uid);
-                        user
+                        user.resolve_presence(&self.voice);
+                        user.view_as(Viewer::Contact)
                     })
                     .collect();
 
@@ -171,7 +252,7 @@ impl State {
 
             transaction.commit()?;
 
-            crate::log!("User {} retrieved set {}", token.as_ref(), id.as_ref());
+            crate::log!("User {} retrieved set {}", uid, id.as_ref());
 
             Ok(set)
         } else {
@@ -179,7 +260,8 @@ impl State {
         }
     }
 
-    /// Creates a set with the given details. Automatically gives the creator admin rights.
+    /// Creates a set with the given details, along with its default owner, moderator and member roles.
+    /// Automatically gives the creator the owner role.
     pub fn create_set(
         &self,
         token: impl AsRef<str>,
@@ -189,7 +271,7 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let user_id = transaction.select_id_by_token(token.as_ref())?;
+        let user_id = transaction.select_id_by_token(Token(token.as_ref()))?;
 
         if user_id.is_none() {
             return Err("Invalid token".to_string());
@@ -197,9 +279,12 @@ impl State {
 
         let user_id = user_id.unwrap();
 
+        self.require_verified_email(&mut transaction, &user_id)?;
+
         let new_set_id = Uuid::new_v4().to_string();
         let new_membership_id = Uuid::new_v4().to_string();
         let new_subset_id = Uuid::new_v4().to_string();
+        let owner_role_id = Uuid::new_v4().to_string();
 
         let icon = icon
             .unwrap_or_else(|| {
@@ -210,8 +295,29 @@ impl State {
             .unwrap_or('α')
             .to_string();
 
-        transaction.insert_set(&new_set_id, name.as_ref(), &icon)?;
-        transaction.insert_membership(&new_membership_id, &user_id, &new_set_id, true)?;
+        transaction.insert_set(&new_set_id, name.as_ref(), &icon, &user_id)?;
+        transaction.insert_role(
+            &owner_role_id,
+            &new_set_id,
+            "Owner",
+            "owner",
+            Permission::ALL,
+        )?;
+        transaction.insert_role(
+            &Uuid::new_v4().to_string(),
+            &new_set_id,
+            "Moderator",
+            "moderator",
+            Permission::MODERATOR,
+        )?;
+        transaction.insert_role(
+            &Uuid::new_v4().to_string(),
+            &new_set_id,
+            "Member",
+            "member",
+            0,
+        )?;
+        transaction.insert_membership(&new_membership_id, &user_id, &new_set_id, &owner_role_id)?;
         transaction.insert_subset(&new_subset_id, "General", &new_set_id)?;
 
         transaction.commit()?;
@@ -231,15 +337,15 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let membership = transaction.select_membership(token.as_ref(), set.as_ref())?;
+        let membership = transaction.select_membership(Token(token.as_ref()), set.as_ref())?;
 
         if membership.is_none() {
             return Err("Invalid token or set".to_string());
         }
 
-        let (is_admin, user_id) = membership.unwrap();
+        let (permissions, user_id) = membership.unwrap();
 
-        if !is_admin {
+        if !Permission::ManageSubsets.granted_by(permissions) {
             return Err("Insufficient permissions".to_string());
         }
 
@@ -268,24 +374,24 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let (admin, user_id) = transaction
-            .select_membership(token.as_ref(), set.as_ref())?
+        let (permissions, user_id) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
             .ok_or_else(|| "Invalid token or set".to_string())?;
 
-        if !admin {
+        if !Permission::ManageSet.granted_by(permissions) {
             return Err("Insufficient permissions".to_string());
         }
 
         if delete == Some(true) {
-            transaction.delete_set_messages(set.as_ref())?;
-            transaction.delete_set_subsets(set.as_ref())?;
-            transaction.delete_set_invites(set.as_ref())?;
-            transaction.delete_set_memberships(set.as_ref())?;
-            transaction.delete_set(set.as_ref())?;
+            if transaction.select_set_owner(set.as_ref())?.as_ref() != Some(&user_id) {
+                return Err("Only the owner can delete the set".to_string());
+            }
+
+            delete_set_data(&mut transaction, set.as_ref())?;
 
             transaction.commit()?;
 
-            self.broadcast_set(set.as_ref(), None, None, None, true);
+            self.broadcast_set(set.as_ref(), None, None, None, None, true);
 
             crate::log!("User {} deleted set {}", user_id, set.as_ref());
 
@@ -302,7 +408,7 @@ impl State {
 
         transaction.commit()?;
 
-        self.broadcast_set(set.as_ref(), name, icon, None, false);
+        self.broadcast_set(set.as_ref(), name, icon, None, None, false);
 
         crate::log!("User {} updated set {}", user_id, set.as_ref());
 
@@ -320,16 +426,22 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let (admin, set_id, subset_name, user_id) = transaction
-            .select_subset_metadata_for_update(token.as_ref(), subset.as_ref())?
+        let (permissions, set_id, subset_name, user_id) = transaction
+            .select_subset_metadata_for_update(Token(token.as_ref()), subset.as_ref())?
             .ok_or_else(|| "Invalid token or subset".to_string())?;
 
-        if !admin {
+        if !Permission::ManageSubsets.granted_by(permissions) {
             return Err("Insufficient permissions".to_string());
         }
 
         if delete == Some(true) {
+            transaction.delete_subset_reactions(subset.as_ref())?;
+            transaction.delete_subset_pins(subset.as_ref())?;
+            transaction.delete_subset_mentions(subset.as_ref())?;
+            transaction.delete_subset_read_markers(subset.as_ref())?;
+            transaction.delete_subset_revisions(subset.as_ref())?;
             transaction.delete_subset_messages(subset.as_ref())?;
+            transaction.delete_subset_permissions(subset.as_ref())?;
             transaction.delete_subset(subset.as_ref())?;
             transaction.commit()?;
 
@@ -348,6 +460,89 @@ impl State {
         Ok(())
     }
 
+    /// Gets the permission overrides of the given subset.
+    pub fn get_subset_permissions(
+        &self,
+        token: impl AsRef<str>,
+        subset: impl AsRef<str>,
+    ) -> Result<Vec<SubsetPermission>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (permissions, _, _, user_id) = transaction
+            .select_subset_metadata_for_update(Token(token.as_ref()), subset.as_ref())?
+            .ok_or_else(|| "Invalid token or subset".to_string())?;
+
+        if !Permission::ManageSubsets.granted_by(permissions) {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        let overrides = transaction.select_subset_permissions(subset.as_ref())?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} retrieved permissions for subset {}",
+            user_id,
+            subset.as_ref()
+        );
+
+        Ok(overrides)
+    }
+
+    /// Sets whether members with the given role can view and post in the given subset.
+    ///
+    /// Roles with the "manage subsets" permission are unaffected by overrides.
+    pub fn update_subset_permissions(
+        &self,
+        token: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        role: impl AsRef<str>,
+        view: bool,
+        post: bool,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (permissions, set_id, _, user_id) = transaction
+            .select_subset_metadata_for_update(Token(token.as_ref()), subset.as_ref())?
+            .ok_or_else(|| "Invalid token or subset".to_string())?;
+
+        if !Permission::ManageSubsets.granted_by(permissions) {
+            return Err("Insufficient permissions".to_string());
+        }
+
+        if transaction
+            .select_role_by_id_and_set(role.as_ref(), &set_id)?
+            .is_none()
+        {
+            return Err("Role not found".to_string());
+        }
+
+        transaction.delete_subset_permission(subset.as_ref(), role.as_ref())?;
+
+        if !view || !post {
+            transaction.insert_subset_permission(
+                &Uuid::new_v4().to_string(),
+                subset.as_ref(),
+                role.as_ref(),
+                view,
+                view && post,
+            )?;
+        }
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} updated permissions of role {} in subset {}",
+            user_id,
+            role.as_ref(),
+            subset.as_ref()
+        );
+
+        Ok(())
+    }
+
     /// Adds the authenticated user to the set with the given invite code.
     pub fn join_set(
         &self,
@@ -358,13 +553,15 @@ impl State {
         let mut transaction = conn.transaction()?;
 
         let user = transaction
-            .select_user_by_token(token.as_ref())?
+            .select_user_by_token(Token(token.as_ref()))?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             })
             .ok_or_else(|| "Invalid token".to_string())?;
 
+        self.require_verified_email(&mut transaction, &user.uid)?;
+
         let invite = transaction
             .select_invite_by_code(invite.as_ref())?
             .ok_or_else(|| "Invalid invite code".to_string())?;
@@ -381,8 +578,17 @@ impl State {
             return Err("Already a member of this set".to_string());
         }
 
+        let member_role_id = transaction
+            .select_role_id_by_kind(&invite.set_id, "member")?
+            .ok_or_else(|| "Set has no member role".to_string())?;
+
         let new_membership_id = Uuid::new_v4().to_string();
-        transaction.insert_membership(&new_membership_id, &user.uid, &invite.set_id, false)?;
+        transaction.insert_membership(
+            &new_membership_id,
+            &user.uid,
+            &invite.set_id,
+            &member_role_id,
+        )?;
         transaction.increment_invite_uses(&invite.id)?;
         transaction.commit()?;
 
@@ -401,9 +607,9 @@ impl State {
         let mut transaction = conn.transaction()?;
 
         let user = transaction
-            .select_user_by_token(token.as_ref())?
+            .select_user_by_token(Token(token.as_ref()))?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             });
 
@@ -420,6 +626,13 @@ impl State {
             return Err("Not a member of this set".to_string());
         }
 
+        if transaction.select_set_owner(set.as_ref())?.as_ref() == Some(&user_id) {
+            return Err(
+                "The owner cannot leave the set without transferring ownership".to_string(),
+            );
+        }
+
+        transaction.delete_membership_read_markers(&user_id, set.as_ref())?;
         transaction.delete_membership(&user_id, set.as_ref())?;
         transaction.commit()?;
 
@@ -440,26 +653,35 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let (admin, admin_user_id) = transaction
-            .select_membership(token.as_ref(), set.as_ref())?
+        let (permissions, admin_user_id) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
             .ok_or_else(|| "Invalid token or set".to_string())?;
 
-        if !admin {
+        if !Permission::Kick.granted_by(permissions) {
             return Err("Insufficient permissions".to_string());
         }
 
         let user = transaction
             .select_user_by_uid(uid.as_ref())?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             })
             .ok_or_else(|| "Target user does not exist".to_string())?;
 
-        if !transaction.select_user_has_membership(&user.uid, set.as_ref())? {
-            return Err("Target user not in set".to_string());
+        let target_role = transaction
+            .select_role_by_user_and_set(&user.uid, set.as_ref())?
+            .ok_or_else(|| "Target user not in set".to_string())?;
+
+        if transaction.select_set_owner(set.as_ref())?.as_ref() == Some(&user.uid) {
+            return Err("Cannot kick the owner of the set".to_string());
+        }
+
+        if target_role.permissions & !permissions != 0 {
+            return Err("Cannot kick a member with permissions you do not have".to_string());
         }
 
+        transaction.delete_membership_read_markers(uid.as_ref(), set.as_ref())?;
         transaction.delete_membership(uid.as_ref(), set.as_ref())?;
         transaction.commit()?;
 
@@ -475,4 +697,100 @@ impl State {
 
         Ok(())
     }
+
+    /// Transfers ownership of the given set to the given member.
+    ///
+    /// The new owner is given the owner role, and the previous owner is given the moderator role.
+    pub fn transfer_ownership(
+        &self,
+        token: impl AsRef<str>,
+        set: impl AsRef<str>,
+        uid: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (_, owner_id) = transaction
+            .select_membership(Token(token.as_ref()), set.as_ref())?
+            .ok_or_else(|| "Invalid token or set".to_string())?;
+
+        if transaction.select_set_owner(set.as_ref())?.as_ref() != Some(&owner_id) {
+            return Err("Only the owner can transfer ownership".to_string());
+        }
+
+        if owner_id == uid.as_ref() {
+            return Err("Already the owner of the set".to_string());
+        }
+
+        let target_role = transaction
+            .select_role_by_user_and_set(uid.as_ref(), set.as_ref())?
+            .ok_or_else(|| "Target user not in set".to_string())?;
+
+        let owner_role_id = transaction
+            .select_role_id_by_kind(set.as_ref(), "owner")?
+            .ok_or_else(|| "Set has no owner role".to_string())?;
+
+        let moderator_role_id = transaction
+            .select_role_id_by_kind(set.as_ref(), "moderator")?
+            .ok_or_else(|| "Set has no moderator role".to_string())?;
+
+        transaction.update_set_owner(uid.as_ref(), set.as_ref())?;
+        transaction.update_membership_role(&owner_role_id, uid.as_ref(), set.as_ref())?;
+        transaction.update_membership_role(&moderator_role_id, &owner_id, set.as_ref())?;
+
+        let mut roles: Vec<Role> = Vec::new();
+
+        for role_id in [target_role.id, owner_role_id, moderator_role_id] {
+            if roles.iter().any(|role| role.id == role_id) {
+                continue;
+            }
+
+            if let Some(mut role) = transaction.select_role_by_id_and_set(&role_id, set.as_ref())? {
+                role.members = transaction.select_user_ids_by_role(&role.id)?;
+                roles.push(role);
+            }
+        }
+
+        transaction.commit()?;
+
+        for role in roles {
+            self.broadcast_role(set.as_ref(), role);
+        }
+
+        self.broadcast_set(
+            set.as_ref(),
+            None,
+            None,
+            Some(uid.as_ref().to_string()),
+            None,
+            false,
+        );
+
+        crate::log!(
+            "User {} transferred ownership of set {} to user {}",
+            owner_id,
+            set.as_ref(),
+            uid.as_ref()
+        );
+
+        Ok(())
+    }
+}
+
+/// Deletes the given set along with all of its subsets, messages, roles, invites and memberships.
+pub(crate) fn delete_set_data(transaction: &mut Transaction, set: &str) -> Result<(), String> {
+    transaction.delete_set_reactions(set)?;
+    transaction.delete_set_pins(set)?;
+    transaction.delete_set_mentions(set)?;
+    transaction.delete_set_read_markers(set)?;
+    transaction.delete_set_revisions(set)?;
+    transaction.delete_set_messages(set)?;
+    transaction.delete_set_subset_permissions(set)?;
+    transaction.delete_set_subsets(set)?;
+    transaction.delete_set_invites(set)?;
+    transaction.delete_set_memberships(set)?;
+    transaction.delete_set_roles(set)?;
+    transaction.delete_set(set)?;
+
+    Ok(())
 }
@@ -1,18 +1,60 @@
 //! Provides event subscription management.
 
+use crate::server::dms::Dm;
 use crate::server::messages;
-use crate::server::user::User;
+use crate::server::roles::{Permission, Role};
+use crate::server::user::{User, Viewer};
+use crate::util::Token;
 use crate::voice::user::WrappedVoiceUser;
 use crate::State;
 
 use humphrey_ws::Message;
 
 use humphrey_json::prelude::*;
+use humphrey_json::Value;
 
 use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 
+/// Represents an authenticated WebSocket connection.
+pub struct Connection {
+    /// The ID of the authenticated user.
+    pub uid: String,
+    /// The ID of the session the connection was authenticated with.
+    pub session: String,
+}
+
 impl State {
+    /// Authenticates the connection as the user, so that it receives the events sent directly to them without subscribing to any set.
+    pub fn authenticate(&self, token: impl AsRef<str>, addr: SocketAddr) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let session = transaction
+            .select_session_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        transaction.update_session_last_used(Token(token.as_ref()))?;
+        transaction.commit()?;
+
+        self.connections.write().unwrap().insert(
+            addr,
+            Connection {
+                uid: uid.clone(),
+                session,
+            },
+        );
+
+        crate::log!(Debug, "User {} authenticated a connection", uid);
+
+        Ok(())
+    }
+
     /// Subscribes the authenticated user to events for the given set.
     pub fn subscribe(
         &self,
@@ -23,13 +65,23 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let membership = transaction.select_membership(token.as_ref(), set.as_ref())?;
+        let membership = transaction.select_membership(Token(token.as_ref()), set.as_ref())?;
 
-        if membership.is_none() {
-            return Err("Not a member of this set".to_string());
-        }
+        let uid = match membership {
+            Some((_, uid)) => uid,
+            None => return Err("Not a member of this set".to_string()),
+        };
+
+        let session = transaction
+            .select_session_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
 
+        transaction.update_session_last_used(Token(token.as_ref()))?;
+        transaction.commit()?;
+
+        // Both maps are locked together so that a subscribed connection is never missing its authentication
         let mut subscriptions = self.subscriptions.write().unwrap();
+        let mut connections = self.connections.write().unwrap();
 
         match subscriptions.entry(set.as_ref().to_string()) {
             Entry::Vacant(entry) => {
@@ -44,15 +96,16 @@ impl State {
             }
         }
 
-        transaction.commit()?;
-
-        crate::log!(
-            Debug,
-            "User {} subscribed to set {}",
-            membership.unwrap().1,
-            set.as_ref()
+        connections.insert(
+            addr,
+            Connection {
+                uid: uid.clone(),
+                session,
+            },
         );
 
+        crate::log!(Debug, "User {} subscribed to set {}", uid, set.as_ref());
+
         Ok(())
     }
 
@@ -66,12 +119,14 @@ impl State {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
-        let membership = transaction.select_membership(token.as_ref(), set.as_ref())?;
+        let membership = transaction.select_membership(Token(token.as_ref()), set.as_ref())?;
 
         if membership.is_none() {
             return Err("Not a member of this set".to_string());
         }
 
+        transaction.commit()?;
+
         let mut subscriptions = self.subscriptions.write().unwrap();
 
         match subscriptions.entry(set.as_ref().to_string()) {
@@ -87,8 +142,6 @@ impl State {
             }
         }
 
-        transaction.commit()?;
-
         crate::log!(
             Debug,
             "User {} unsubscribed from set {}",
@@ -105,6 +158,7 @@ impl State {
         set: impl AsRef<str>,
         name: Option<String>,
         icon: Option<String>,
+        owner: Option<String>,
         kicked: Option<bool>,
         deleted: bool,
     ) {
@@ -118,6 +172,7 @@ impl State {
                 "data": {
                     "name": name,
                     "icon": icon,
+                    "owner": owner,
                     "kicked": kicked
                 }
             })
@@ -167,6 +222,29 @@ impl State {
         }
     }
 
+    /// Broadcasts the "role" event to all subscribers of the set.
+    pub fn broadcast_role(&self, set: impl AsRef<str>, role: Role) {
+        let subscriptions = self.subscriptions.read().unwrap();
+
+        let message = Message::new(
+            json!({
+                "event": "v1/role",
+                "set": (set.as_ref()),
+                "role": role
+            })
+            .serialize(),
+        );
+
+        if let Some(subscriptions) = subscriptions.get(set.as_ref()) {
+            let locked_sender = self.global_sender.lock().unwrap();
+            let sender = locked_sender.as_ref().unwrap();
+
+            for subscriber in subscriptions {
+                sender.send(*subscriber, message.clone());
+            }
+        }
+    }
+
     /// Broadcasts the "message" event to all subscribers of the set.
     pub fn broadcast_message(
         &self,
@@ -175,31 +253,144 @@ impl State {
         message: messages::Message,
         deleted: bool,
     ) {
+        self.broadcast_message_event(set.as_ref(), subset.as_ref(), message, |message| {
+            json!({
+                "event": "v1/message",
+                "set": (set.as_ref()),
+                "subset": (subset.as_ref()),
+                "message": message,
+                "deleted": deleted
+            })
+        });
+    }
+
+    /// Broadcasts the "thread message" event to all subscribers of the set.
+    pub fn broadcast_thread_message(
+        &self,
+        set: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        thread: impl AsRef<str>,
+        message: messages::Message,
+        deleted: bool,
+    ) {
+        self.broadcast_message_event(set.as_ref(), subset.as_ref(), message, |message| {
+            json!({
+                "event": "v1/threadMessage",
+                "set": (set.as_ref()),
+                "subset": (subset.as_ref()),
+                "thread": (thread.as_ref()),
+                "message": message,
+                "deleted": deleted
+            })
+        });
+    }
+
+    /// Broadcasts the "reaction" event to all subscribers of the set.
+    #[allow(clippy::too_many_arguments)]
+    pub fn broadcast_reaction(
+        &self,
+        set: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        message: impl AsRef<str>,
+        emoji: impl AsRef<str>,
+        uid: impl AsRef<str>,
+        count: u64,
+        deleted: bool,
+    ) {
+        let viewers = self.subset_viewers(subset.as_ref());
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
             json!({
-                "event": "v1/message",
+                "event": "v1/reaction",
                 "set": (set.as_ref()),
                 "subset": (subset.as_ref()),
-                "message": message,
+                "message": (message.as_ref()),
+                "emoji": (emoji.as_ref()),
+                "uid": (uid.as_ref()),
+                "count": count,
                 "deleted": deleted
             })
             .serialize(),
         );
 
         if let Some(subscriptions) = subscriptions.get(set.as_ref()) {
+            let connections = self.connections.read().unwrap();
             let locked_sender = self.global_sender.lock().unwrap();
             let sender = locked_sender.as_ref().unwrap();
 
             for subscriber in subscriptions {
-                sender.send(*subscriber, message.clone());
+                if can_view(&viewers, &connections, subscriber) {
+                    sender.send(*subscriber, message.clone());
+                }
+            }
+        }
+    }
+
+    /// Broadcasts the "pin" event to all subscribers of the set.
+    pub fn broadcast_pin(
+        &self,
+        set: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        message: messages::Message,
+        uid: impl AsRef<str>,
+        deleted: bool,
+    ) {
+        self.broadcast_message_event(set.as_ref(), subset.as_ref(), message, |message| {
+            json!({
+                "event": "v1/pin",
+                "set": (set.as_ref()),
+                "subset": (subset.as_ref()),
+                "message": message,
+                "uid": (uid.as_ref()),
+                "deleted": deleted
+            })
+        });
+    }
+
+    /// Sends an event containing the given message to the subscribers of the set who can view the subset.
+    ///
+    /// The message is marked as blocked in the event sent to users who have blocked its author.
+    fn broadcast_message_event(
+        &self,
+        set: &str,
+        subset: &str,
+        mut message: messages::Message,
+        event: impl Fn(messages::Message) -> Value,
+    ) {
+        let viewers = self.subset_viewers(subset);
+        let blockers = self.blocker_ids(&message.author_id).unwrap_or_default();
+        let subscriptions = self.subscriptions.read().unwrap();
+
+        message.blocked = false;
+        let unblocked = Message::new(event(message.clone()).serialize());
+
+        message.blocked = true;
+        let blocked = Message::new(event(message).serialize());
+
+        if let Some(subscriptions) = subscriptions.get(set) {
+            let connections = self.connections.read().unwrap();
+            let locked_sender = self.global_sender.lock().unwrap();
+            let sender = locked_sender.as_ref().unwrap();
+
+            for subscriber in subscriptions {
+                if !can_view(&viewers, &connections, subscriber) {
+                    continue;
+                }
+
+                if is_blocker(&blockers, &connections, subscriber) {
+                    sender.send(*subscriber, blocked.clone());
+                } else {
+                    sender.send(*subscriber, unblocked.clone());
+                }
             }
         }
     }
 
     /// Broadcasts the "new user" event to all subscribers of the set.
     pub fn broadcast_new_user(&self, set: impl AsRef<str>, user: User) {
+        let user = user.view_as(Viewer::Contact);
+        let blockers = self.blocker_ids(&user.uid).unwrap_or_default();
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
@@ -213,21 +404,27 @@ impl State {
         );
 
         if let Some(subscriptions) = subscriptions.get(set.as_ref()) {
+            let connections = self.connections.read().unwrap();
             let locked_sender = self.global_sender.lock().unwrap();
             let sender = locked_sender.as_ref().unwrap();
 
             for subscriber in subscriptions {
-                sender.send(*subscriber, message.clone());
+                if !is_blocker(&blockers, &connections, subscriber) {
+                    sender.send(*subscriber, message.clone());
+                }
             }
         }
     }
 
     /// Broadcasts the "update user" event to all subscribers of the set.
     pub fn broadcast_update_user(&self, user: User) -> Option<()> {
+        let user = user.view_as(Viewer::Contact);
+
         let mut conn = self.db.connection().ok()?;
         let mut transaction = conn.transaction().ok()?;
 
         let set_ids = transaction.select_user_set_ids(&user.uid).ok()?;
+        let blockers = transaction.select_blocker_ids(&user.uid).ok()?;
         transaction.commit().ok()?;
 
         let subscriptions = self.subscriptions.read().unwrap();
@@ -244,11 +441,14 @@ impl State {
             );
 
             if let Some(subscriptions) = subscriptions.get(&set) {
+                let connections = self.connections.read().unwrap();
                 let locked_sender = self.global_sender.lock().unwrap();
                 let sender = locked_sender.as_ref().unwrap();
 
                 for subscriber in subscriptions {
-                    sender.send(*subscriber, message.clone());
+                    if !is_blocker(&blockers, &connections, subscriber) {
+                        sender.send(*subscriber, message.clone());
+                    }
                 }
             }
         }
@@ -256,26 +456,55 @@ impl State {
         Some(())
     }
 
-    /// Broadcasts the "update user" event when a user comes online.
+    /// Broadcasts the "presence" event to all subscribers of the user's sets.
     ///
-    /// In the future, this will become a separate event for optimisation.
-    pub fn broadcast_user_online(&self, uid: impl AsRef<str>) -> Option<()> {
-        let user = self.get_user(uid).ok()?;
+    /// This is sent instead of the full "update user" event when only the user's presence changes,
+    /// such as when they come online, go offline or set a custom status.
+    pub fn broadcast_presence(&self, uid: impl AsRef<str>) -> Option<()> {
+        let user = self.get_user_as(uid, Viewer::Contact).ok()?;
 
-        self.broadcast_update_user(user)
-    }
+        let mut conn = self.db.connection().ok()?;
+        let mut transaction = conn.transaction().ok()?;
 
-    /// Broadcasts the "update user" event when a user goes offline.
-    ///
-    /// In the future, this will become a separate event for optimisation.
-    pub fn broadcast_user_offline(&self, uid: impl AsRef<str>) -> Option<()> {
-        let user = self.get_user(uid).ok()?;
+        let set_ids = transaction.select_user_set_ids(&user.uid).ok()?;
+        let blockers = transaction.select_blocker_ids(&user.uid).ok()?;
+        transaction.commit().ok()?;
 
-        self.broadcast_update_user(user)
+        let subscriptions = self.subscriptions.read().unwrap();
+
+        for set in set_ids {
+            let message = Message::new(
+                json!({
+                    "event": "v1/presence",
+                    "set": (&set),
+                    "uid": (&user.uid),
+                    "online": (user.online),
+                    "presence": (&user.presence),
+                    "status": (user.status.clone())
+                })
+                .serialize(),
+            );
+
+            if let Some(subscriptions) = subscriptions.get(&set) {
+                let connections = self.connections.read().unwrap();
+                let locked_sender = self.global_sender.lock().unwrap();
+                let sender = locked_sender.as_ref().unwrap();
+
+                for subscriber in subscriptions {
+                    if !is_blocker(&blockers, &connections, subscriber) {
+                        sender.send(*subscriber, message.clone());
+                    }
+                }
+            }
+        }
+
+        Some(())
     }
 
     /// Broadcasts the "user left" event to all subscribers of the set.
     pub fn broadcast_left_user(&self, set: impl AsRef<str>, user: User) {
+        let user = user.view_as(Viewer::Contact);
+        let blockers = self.blocker_ids(&user.uid).unwrap_or_default();
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
@@ -289,17 +518,24 @@ impl State {
         );
 
         if let Some(subscriptions) = subscriptions.get(set.as_ref()) {
+            let connections = self.connections.read().unwrap();
             let locked_sender = self.global_sender.lock().unwrap();
             let sender = locked_sender.as_ref().unwrap();
 
             for subscriber in subscriptions {
-                sender.send(*subscriber, message.clone());
+                if !is_blocker(&blockers, &connections, subscriber) {
+                    sender.send(*subscriber, message.clone());
+                }
             }
         }
     }
 
     /// Broadcasts the "user joined voice chat" event to all subscribers of the set.
     pub fn broadcast_joined_vc(&self, set: impl AsRef<str>, user: WrappedVoiceUser) {
+        let user = WrappedVoiceUser {
+            user: user.user.view_as(Viewer::Contact),
+            ..user
+        };
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
@@ -324,6 +560,10 @@ impl State {
 
     /// Broadcasts the "user left voice chat" event to all subscribers of the set.
     pub fn broadcast_left_vc(&self, set: impl AsRef<str>, user: WrappedVoiceUser) {
+        let user = WrappedVoiceUser {
+            user: user.user.view_as(Viewer::Contact),
+            ..user
+        };
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
@@ -353,6 +593,8 @@ impl State {
         subset: impl AsRef<str>,
         uid: impl AsRef<str>,
     ) {
+        let viewers = self.subset_viewers(subset.as_ref());
+        let blockers = self.blocker_ids(uid.as_ref()).unwrap_or_default();
         let subscriptions = self.subscriptions.read().unwrap();
 
         let message = Message::new(
@@ -365,15 +607,44 @@ impl State {
         );
 
         if let Some(subscriptions) = subscriptions.get(set.as_ref()) {
+            let connections = self.connections.read().unwrap();
             let locked_sender = self.global_sender.lock().unwrap();
             let sender = locked_sender.as_ref().unwrap();
 
             for subscriber in subscriptions {
-                sender.send(*subscriber, message.clone());
+                if can_view(&viewers, &connections, subscriber)
+                    && !is_blocker(&blockers, &connections, subscriber)
+                {
+                    sender.send(*subscriber, message.clone());
+                }
             }
         }
     }
 
+    /// Gets the IDs of the users who can view the given subset, or `None` if the database could not be queried.
+    fn subset_viewers(&self, subset: impl AsRef<str>) -> Option<Vec<String>> {
+        let mut conn = self.db.connection().ok()?;
+        let mut transaction = conn.transaction().ok()?;
+
+        let viewers = transaction
+            .select_user_ids_by_subset_access(subset.as_ref(), Permission::ManageSubsets as u64)
+            .ok()?;
+        transaction.commit().ok()?;
+
+        Some(viewers)
+    }
+
+    /// Gets the IDs of the users who have blocked the given user, or `None` if the database could not be queried.
+    fn blocker_ids(&self, uid: impl AsRef<str>) -> Option<Vec<String>> {
+        let mut conn = self.db.connection().ok()?;
+        let mut transaction = conn.transaction().ok()?;
+
+        let blockers = transaction.select_blocker_ids(uid.as_ref()).ok()?;
+        transaction.commit().ok()?;
+
+        Some(blockers)
+    }
+
     /// Alerts a user that they have been kicked from the set.
     pub fn alert_kicked_user(&self, set: impl AsRef<str>, uid: impl AsRef<str>) {
         if let Some(user) = self.voice.get_user(uid) {
@@ -395,4 +666,220 @@ impl State {
             sender.send(user.socket_addr, message);
         }
     }
+
+    /// Alerts the given users that they have been mentioned in a message.
+    ///
+    /// The event is sent directly to each user who is online, whether or not they are subscribed to the set.
+    pub fn alert_mentioned_users(
+        &self,
+        set: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        message: messages::Message,
+        uids: &[String],
+    ) {
+        let message = Message::new(
+            json!({
+                "event": "v1/mention",
+                "set": (set.as_ref()),
+                "subset": (subset.as_ref()),
+                "message": message
+            })
+            .serialize(),
+        );
+
+        self.send_to_users(uids, message);
+    }
+
+    /// Alerts the participants of a direct conversation that it has been created.
+    pub fn alert_dm(&self, uids: &[String], dm: Dm) {
+        let message = Message::new(
+            json!({
+                "event": "v1/dm",
+                "dm": dm
+            })
+            .serialize(),
+        );
+
+        self.send_to_users(uids, message);
+    }
+
+    /// Sends a message in a direct conversation to its participants.
+    pub fn alert_dm_message(
+        &self,
+        uids: &[String],
+        dm: impl AsRef<str>,
+        message: messages::Message,
+    ) {
+        let message = Message::new(
+            json!({
+                "event": "v1/dmMessage",
+                "dm": (dm.as_ref()),
+                "message": message
+            })
+            .serialize(),
+        );
+
+        self.send_to_users(uids, message);
+    }
+
+    /// Alerts a user of a change to their relationship with the given user.
+    pub fn alert_friend(&self, uid: impl AsRef<str>, user: User, status: impl AsRef<str>) {
+        let message = Message::new(
+            json!({
+                "event": "v1/friend",
+                "user": user,
+                "status": (status.as_ref())
+            })
+            .serialize(),
+        );
+
+        self.send_to_users(&[uid.as_ref().to_string()], message);
+    }
+
+    /// Sends the given message directly to every connection of each of the given users who is online.
+    fn send_to_users(&self, uids: &[String], message: Message) {
+        let connections = self.connections.read().unwrap();
+        let mut addrs: Vec<SocketAddr> = Vec::new();
+
+        for uid in uids {
+            let mut user_addrs: Vec<SocketAddr> = connections
+                .iter()
+                .filter(|(_, connection)| connection.uid == *uid)
+                .map(|(addr, _)| *addr)
+                .collect();
+
+            user_addrs.sort();
+
+            for addr in user_addrs {
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+
+        drop(connections);
+
+        let locked_sender = self.global_sender.lock().unwrap();
+        let sender = locked_sender.as_ref().unwrap();
+
+        for addr in addrs {
+            sender.send(addr, message.clone());
+        }
+    }
+
+    /// Unsubscribes every connection authenticated as the given user from all events.
+    pub(crate) fn disconnect_user(&self, uid: impl AsRef<str>) {
+        self.disconnect(|connection| connection.uid == uid.as_ref());
+    }
+
+    /// Unsubscribes every connection authenticated with the given session from all events.
+    pub(crate) fn disconnect_session(&self, session: impl AsRef<str>) {
+        self.disconnect(|connection| connection.session == session.as_ref());
+    }
+
+    /// Unsubscribes every connection matching the predicate from all events, since it can no longer be authenticated.
+    fn disconnect(&self, predicate: impl Fn(&Connection) -> bool) {
+        let mut connections = self.connections.write().unwrap();
+
+        let addrs = connections
+            .iter()
+            .filter(|(_, connection)| predicate(connection))
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        connections.retain(|_, connection| !predicate(connection));
+        drop(connections);
+
+        for subscriptions in self.subscriptions.write().unwrap().values_mut() {
+            subscriptions.retain(|addr| !addrs.contains(addr));
+        }
+
+        for addr in addrs {
+            self.disconnect_voice(addr);
+        }
+    }
+
+    /// Disconnects the user connected to voice chat on the given connection, if any, removing them from their voice channel.
+    pub(crate) fn disconnect_voice(&self, addr: SocketAddr) {
+        let voice_user = {
+            let online_users = self.voice.online_users.read().unwrap();
+            online_users
+                .values()
+                .find(|u| u.socket_addr == addr)
+                .cloned()
+        };
+
+        if let Some(voice_user) = voice_user {
+            if let Some(channel_id) = voice_user.channel_id {
+                if let Ok(user) = self.get_user_as(&voice_user.uid, Viewer::Contact) {
+                    self.voice.leave_voice_channel(&voice_user.uid).ok();
+                    self.broadcast_left_vc(
+                        channel_id,
+                        WrappedVoiceUser {
+                            user,
+                            peer_id: voice_user.peer_id,
+                        },
+                    );
+                }
+            }
+
+            self.voice.disconnect_user_voice(&voice_user.uid);
+            self.broadcast_presence(&voice_user.uid);
+        }
+    }
+
+    /// Informs each of the user's connections of their new read position in the subset.
+    pub fn alert_read(
+        &self,
+        uid: impl AsRef<str>,
+        set: impl AsRef<str>,
+        subset: impl AsRef<str>,
+        unread: u64,
+        mentions: u64,
+    ) {
+        let message = Message::new(
+            json!({
+                "event": "v1/read",
+                "set": (set.as_ref()),
+                "subset": (subset.as_ref()),
+                "unread": unread,
+                "mentions": mentions
+            })
+            .serialize(),
+        );
+
+        let connections = self.connections.read().unwrap();
+        let locked_sender = self.global_sender.lock().unwrap();
+        let sender = locked_sender.as_ref().unwrap();
+
+        for (addr, connection) in connections.iter() {
+            if connection.uid == uid.as_ref() {
+                sender.send(*addr, message.clone());
+            }
+        }
+    }
+}
+
+/// Returns whether the user authenticated on the given connection is one of the given subset viewers.
+fn can_view(
+    viewers: &Option<Vec<String>>,
+    connections: &HashMap<SocketAddr, Connection>,
+    addr: &SocketAddr,
+) -> bool {
+    match (viewers, connections.get(addr)) {
+        (Some(viewers), Some(connection)) => viewers.contains(&connection.uid),
+        _ => false,
+    }
+}
+
+/// Returns whether the user authenticated on the given connection is one of the given blockers.
+fn is_blocker(
+    blockers: &[String],
+    connections: &HashMap<SocketAddr, Connection>,
+    addr: &SocketAddr,
+) -> bool {
+    connections
+        .get(addr)
+        .map(|connection| blockers.contains(&connection.uid))
+        .unwrap_or(false)
 }
@@ -0,0 +1,205 @@
+//! Provides two-factor authentication with time-based one-time passwords and recovery codes.
+
+use crate::server::auth::{start_session, AuthResponse};
+use crate::totp;
+use crate::util::Token;
+use crate::State;
+
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+/// The number of recovery codes generated when two-factor authentication is enabled.
+const RECOVERY_CODES: usize = 10;
+
+/// Represents the details needed to add two-factor authentication to an authenticator app.
+pub struct TwoFactorSetup {
+    /// The base 32 secret, for entering into the app manually.
+    pub secret: String,
+    /// The provisioning URI of the secret, for displaying as a QR code.
+    pub uri: String,
+}
+
+impl State {
+    /// Starts enabling two-factor authentication for the authenticated user, returning the secret to add to their authenticator app.
+    ///
+    /// Two-factor authentication is not enabled until a code generated from the secret is passed to `confirm_2fa`.
+    pub fn enable_2fa(&self, token: impl AsRef<str>) -> Result<TwoFactorSetup, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if transaction.select_totp_secret(&user.uid)?.is_some() {
+            return Err("Two-factor authentication already enabled".to_string());
+        }
+
+        let secret = totp::generate_secret();
+
+        transaction.update_user_totp(&Some(totp::encrypt_secret(&secret)), false, &user.uid)?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} started enabling two-factor authentication",
+            user.uid
+        );
+
+        Ok(TwoFactorSetup {
+            uri: totp::provisioning_uri(&secret, &user.username),
+            secret,
+        })
+    }
+
+    /// Enables two-factor authentication for the authenticated user once they have entered a code from their authenticator app.
+    ///
+    /// Returns the user's recovery codes, each of which can be used once instead of a code if they lose access to the app.
+    pub fn confirm_2fa(
+        &self,
+        token: impl AsRef<str>,
+        code: impl AsRef<str>,
+    ) -> Result<Vec<String>, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let encrypted_secret = transaction
+            .select_pending_totp_secret(&uid)?
+            .ok_or_else(|| "Two-factor authentication not being enabled".to_string())?;
+
+        let secret = totp::decrypt_secret(&encrypted_secret)
+            .ok_or_else(|| "Could not decrypt secret".to_string())?;
+
+        let counter =
+            totp::verify(&secret, code.as_ref(), None).ok_or_else(|| "Invalid code".to_string())?;
+
+        transaction.update_user_totp(&Some(encrypted_secret), true, &uid)?;
+        transaction.update_totp_counter(counter, &uid)?;
+        transaction.delete_recovery_codes(&uid)?;
+
+        let recovery_codes = (0..RECOVERY_CODES)
+            .map(|_| generate_recovery_code())
+            .collect::<Vec<_>>();
+
+        for recovery_code in &recovery_codes {
+            transaction.insert_recovery_code(&uid, Token(recovery_code))?;
+        }
+
+        transaction.commit()?;
+
+        crate::log!("User {} enabled two-factor authentication", uid);
+
+        Ok(recovery_codes)
+    }
+
+    /// Disables two-factor authentication for the authenticated user, given a code from their authenticator app or a recovery code.
+    pub fn disable_2fa(&self, token: impl AsRef<str>, code: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let secret = transaction
+            .select_totp_secret(&uid)?
+            .ok_or_else(|| "Two-factor authentication not enabled".to_string())?;
+
+        check_second_factor(&mut transaction, &uid, secret, code.as_ref())?;
+
+        transaction.update_user_totp(&None, false, &uid)?;
+        transaction.delete_recovery_codes(&uid)?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} disabled two-factor authentication", uid);
+
+        Ok(())
+    }
+
+    /// Completes the given login challenge with a code from the user's authenticator app or a recovery code, signing them in.
+    pub fn login_2fa(
+        &self,
+        challenge: impl AsRef<str>,
+        code: impl AsRef<str>,
+    ) -> Result<AuthResponse, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let (uid, device, ip, expired) = transaction
+            .select_login_challenge(Token(challenge.as_ref()))?
+            .ok_or_else(|| "Invalid challenge".to_string())?;
+
+        if expired {
+            return Err("Challenge expired".to_string());
+        }
+
+        let secret = transaction
+            .select_totp_secret(&uid)?
+            .ok_or_else(|| "Invalid challenge".to_string())?;
+
+        check_second_factor(&mut transaction, &uid, secret, code.as_ref())?;
+
+        transaction.delete_login_challenge(Token(challenge.as_ref()))?;
+
+        let response = start_session(&mut transaction, uid, device, ip)?;
+
+        transaction.commit()?;
+
+        crate::log!(
+            "User {} logged in with two-factor authentication",
+            response.uid
+        );
+
+        Ok(response)
+    }
+}
+
+/// Checks the given code against the user's encrypted authenticator app secret and last accepted counter, or otherwise their recovery codes.
+///
+/// The counter of an accepted code is recorded and recovery codes are deleted once used, so each can only be used once.
+pub(crate) fn check_second_factor(
+    transaction: &mut Transaction,
+    uid: &str,
+    (encrypted_secret, last_counter): (String, Option<u64>),
+    code: &str,
+) -> Result<(), String> {
+    // If the secret cannot be decrypted because the server's secret has changed, recovery codes still work
+    let counter = totp::decrypt_secret(&encrypted_secret)
+        .and_then(|secret| totp::verify(&secret, code, last_counter));
+
+    if let Some(counter) = counter {
+        transaction.update_totp_counter(counter, uid)?;
+        return Ok(());
+    }
+
+    let recovery_code = code.trim().to_ascii_lowercase();
+
+    if transaction.select_recovery_code(uid, Token(&recovery_code))? {
+        transaction.delete_recovery_code(uid, Token(&recovery_code))?;
+        return Ok(());
+    }
+
+    Err("Invalid code".to_string())
+}
+
+/// Generates a random recovery code, made up of two groups of five hexadecimal digits.
+fn generate_recovery_code() -> String {
+    let mut bytes = [0; 5];
+    OsRng.fill_bytes(&mut bytes);
+
+    let digits = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("{}-{}", &digits[..5], &digits[5..])
+}
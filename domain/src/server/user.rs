@@ -1,9 +1,31 @@
 //! Provides core functionality for user management.
 
+use crate::server::account::DELETED_USER_ID;
+use crate::server::auth::validate_username;
+use crate::server::verification::{start_verification, validate_email};
+use crate::util::Token;
+use crate::voice::VoiceServer;
 use crate::State;
 
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
 use humphrey_json::prelude::*;
 
+/// The presence states a user can choose.
+const PRESENCES: [&str; 4] = ["online", "idle", "dnd", "invisible"];
+
+/// The maximum number of characters in a custom status.
+const MAX_STATUS_LENGTH: usize = 128;
+
+/// The maximum number of users which can be returned by a user search.
+const MAX_USER_SEARCH_LIMIT: usize = 50;
+
+/// The audiences a user can choose to show their email address and bio to.
+const VISIBILITIES: [&str; 3] = ["everyone", "contacts", "nobody"];
+
 /// Represents a user response from the server.
 #[derive(Clone)]
 pub struct User {
@@ -13,14 +35,46 @@ pub struct User {
     pub username: String,
     /// The display name of the user.
     pub display_name: String,
-    /// The email address of the user.
-    pub email: String,
+    /// The email address of the user, or `None` if it is hidden from the viewer.
+    pub email: Option<String>,
     /// The user's profile picture, or `None` if they have not set one.
     pub image: Option<String>,
-    /// The user's bio, or `None` if they have not set one.
+    /// The user's bio, or `None` if they have not set one or it is hidden from the viewer.
     pub bio: Option<String>,
     /// Whether the user is currently online.
     pub online: bool,
+    /// The user's presence as seen by others, either "online", "idle", "dnd" or "offline".
+    pub presence: String,
+    /// The user's custom status, or `None` if they have not set one or it has expired.
+    pub status: Option<String>,
+    /// Who the user has chosen to show their email address and bio to, which is only visible to the user themselves.
+    pub privacy: Option<Privacy>,
+}
+
+/// Represents a user's privacy settings.
+#[derive(Clone)]
+pub struct Privacy {
+    /// Who can see the user's email address, either "everyone", "contacts" or "nobody".
+    pub email: String,
+    /// Who can see the user's bio, either "everyone", "contacts" or "nobody".
+    pub bio: String,
+}
+
+/// Represents who is viewing a user's details, which determines which of them are visible.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Viewer {
+    /// The user themselves, who can see all of their details.
+    Themselves,
+    /// A user who shares a set with the user.
+    Contact,
+    /// Any other user, or an unauthenticated client.
+    Anyone,
+}
+
+json_map! {
+    Privacy,
+    email => "email",
+    bio => "bio"
 }
 
 json_map! {
@@ -31,44 +85,145 @@ json_map! {
     email => "email",
     image => "image",
     bio => "bio",
-    online => "online"
+    online => "online",
+    presence => "presence",
+    status => "status",
+    privacy => "privacy"
 }
 
+/// A row of the database describing a user, as selected by the user queries.
+pub(crate) type UserRow = (
+    String,         // 0. User ID
+    String,         // 1. Username
+    String,         // 2. Display name
+    String,         // 3. Email address
+    Option<String>, // 4. Image
+    Option<String>, // 5. Bio
+    String,         // 6. Presence
+    Option<String>, // 7. Custom status
+    String,         // 8. Email visibility
+    String,         // 9. Bio visibility
+);
+
 impl User {
     /// Converts a row of the database to a user.
-    pub(crate) fn from_row(
-        row: (
-            String,
-            String,
-            String,
-            String,
-            Option<String>,
-            Option<String>,
-        ),
-    ) -> Self {
+    pub(crate) fn from_row(row: UserRow) -> Self {
         Self {
             uid: row.0,
             username: row.1,
             display_name: row.2,
-            email: row.3,
+            email: Some(row.3),
             image: row.4,
             bio: row.5,
             online: false,
+            presence: row.6,
+            status: row.7,
+            privacy: Some(Privacy {
+                email: row.8,
+                bio: row.9,
+            }),
+        }
+    }
+
+    /// Hides the details which the user has not chosen to show to the given viewer.
+    pub(crate) fn view_as(mut self, viewer: Viewer) -> Self {
+        let is_visible = |visibility: &str| match viewer {
+            Viewer::Themselves => true,
+            Viewer::Contact => visibility != "nobody",
+            Viewer::Anyone => visibility == "everyone",
+        };
+
+        // Users which have already been viewed by someone else have no privacy settings left to apply
+        if let Some(privacy) = self.privacy.take() {
+            if !is_visible(&privacy.email) {
+                self.email = None;
+            }
+
+            if !is_visible(&privacy.bio) {
+                self.bio = None;
+            }
+
+            if viewer == Viewer::Themselves {
+                self.privacy = Some(privacy);
+            }
+        }
+
+        self
+    }
+
+    /// Combines the user's chosen presence with whether they are connected to the voice server.
+    ///
+    /// Users who are not connected, or who have chosen to be invisible, appear offline.
+    pub(crate) fn resolve_presence(&mut self, voice: &VoiceServer) {
+        self.online = voice.is_user_online(&self.uid) && self.presence != "invisible";
+
+        if !self.online {
+            if self.presence == "invisible" {
+                self.status = None;
+            }
+
+            self.presence = "offline".to_string();
         }
     }
 }
 
 impl State {
-    /// Gets the user with the given ID from the database.
-    pub fn get_user(&self, uid: impl AsRef<str>) -> Result<User, String> {
+    /// Gets the user with the given ID from the database, as seen by the user with the given token if one is given.
+    ///
+    /// The placeholder user which deleted accounts are replaced with cannot be looked up, here or by username.
+    pub fn get_user(&self, uid: impl AsRef<str>, token: Option<String>) -> Result<User, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction
+            .select_user_by_uid(uid.as_ref())?
+            .filter(|user| user.uid != DELETED_USER_ID)
+            .ok_or_else(|| "User not found".to_string())?;
+
+        let viewer = viewer_by_token(&mut transaction, token, &user.uid)?;
+
+        transaction.commit()?;
+
+        let mut user = user.view_as(viewer);
+        user.resolve_presence(&self.voice);
+
+        Ok(user)
+    }
+
+    /// Gets the user with the given username from the database, as seen by the user with the given token if one is given.
+    pub fn get_user_by_username(
+        &self,
+        username: impl AsRef<str>,
+        token: Option<String>,
+    ) -> Result<User, String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction
+            .select_user_by_username(username.as_ref())?
+            .filter(|user| user.uid != DELETED_USER_ID)
+            .ok_or_else(|| "User not found".to_string())?;
+
+        let viewer = viewer_by_token(&mut transaction, token, &user.uid)?;
+
+        transaction.commit()?;
+
+        let mut user = user.view_as(viewer);
+        user.resolve_presence(&self.voice);
+
+        Ok(user)
+    }
+
+    /// Gets the user with the given ID from the database, as seen by the given viewer.
+    pub(crate) fn get_user_as(&self, uid: impl AsRef<str>, viewer: Viewer) -> Result<User, String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
         let user = transaction
             .select_user_by_uid(uid.as_ref())?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
-                user
+                user.resolve_presence(&self.voice);
+                user.view_as(viewer)
             })
             .ok_or_else(|| "User not found".to_string());
 
@@ -77,15 +232,57 @@ impl State {
         user
     }
 
-    /// Gets the user with the given token from the database.
+    /// Searches for users sharing a set with the authenticated user whose username or display name starts with the query.
+    pub fn search_users(
+        &self,
+        token: impl AsRef<str>,
+        query: impl AsRef<str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<User>, String> {
+        let query = query.as_ref().trim();
+
+        if query.is_empty() {
+            return Err("Search query cannot be empty".to_string());
+        }
+
+        let prefix = escape_like(query);
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let users = transaction.select_search_users(
+            &uid,
+            &uid,
+            DELETED_USER_ID,
+            &prefix,
+            &prefix,
+            limit.unwrap_or(10).min(MAX_USER_SEARCH_LIMIT),
+        )?;
+
+        transaction.commit()?;
+
+        Ok(users
+            .into_iter()
+            .map(|mut user| {
+                user.resolve_presence(&self.voice);
+                user.view_as(Viewer::Contact)
+            })
+            .collect())
+    }
+
+    /// Gets the user with the given token from the database, including the details they have hidden from others.
     pub fn get_user_by_token(&self, token: impl AsRef<str>) -> Result<User, String> {
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
         let user = transaction
-            .select_user_by_token(token.as_ref())?
+            .select_user_by_token(Token(token.as_ref()))?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             })
             .ok_or_else(|| "User not found".to_string());
@@ -99,35 +296,68 @@ impl State {
     pub fn update_user(
         &self,
         token: impl AsRef<str>,
+        username: Option<String>,
         display_name: Option<String>,
         email: Option<String>,
         bio: Option<String>,
     ) -> Result<(), String> {
+        if let Some(username) = &username {
+            validate_username(username)?;
+        }
+
+        if let Some(email) = &email {
+            validate_email(email)?;
+        }
+
         let mut conn = self.db.connection()?;
         let mut transaction = conn.transaction()?;
 
+        if let Some(username) = username {
+            let current_username = transaction
+                .select_username_by_token(Token(token.as_ref()))?
+                .ok_or_else(|| "Invalid token".to_string())?;
+
+            if username != current_username {
+                if transaction.exists_user_by_username(&username)? {
+                    return Err("Username already exists".to_string());
+                }
+
+                transaction.update_user_username(&username, Token(token.as_ref()))?;
+            }
+        }
+
         if let Some(display_name) = display_name {
-            transaction.update_user_display_name(&display_name, token.as_ref())?;
+            transaction.update_user_display_name(&display_name, Token(token.as_ref()))?;
         }
 
-        if let Some(email) = email {
-            transaction.update_user_email(&email, token.as_ref())?;
+        if let Some(email) = &email {
+            transaction.update_user_email(email, Token(token.as_ref()))?;
         }
 
         if let Some(bio) = bio {
-            transaction.update_user_bio(&bio, token.as_ref())?;
+            transaction.update_user_bio(&bio, Token(token.as_ref()))?;
         }
 
         let user = transaction
-            .select_user_by_token(token.as_ref())?
+            .select_user_by_token(Token(token.as_ref()))?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             })
             .ok_or_else(|| "User not found".to_string())?;
 
+        // A changed email address must be verified again
+        let code = match email {
+            Some(email) => Some((email, start_verification(&mut transaction, &user.uid)?)),
+            None => None,
+        };
+
         transaction.commit()?;
 
+        if let Some((email, code)) = code {
+            self.send_verification(&email, &code)?;
+        }
+
         let uid = user.uid.clone();
 
         self.broadcast_update_user(user);
@@ -148,16 +378,16 @@ impl State {
         let mut transaction = conn.transaction()?;
 
         let mut user = transaction
-            .select_user_by_token(token.as_ref())?
+            .select_user_by_token(Token(token.as_ref()))?
             .map(|mut user| {
-                user.online = self.voice.is_user_online(&user.uid);
+                user.resolve_presence(&self.voice);
                 user
             })
             .ok_or_else(|| "User not found".to_string())?;
 
         let file_id = self.set_file(name, image, &user.uid, &mut transaction)?;
 
-        transaction.update_user_image(&file_id, token.as_ref())?;
+        transaction.update_user_image(&file_id, Token(token.as_ref()))?;
         transaction.commit()?;
 
         let uid = user.uid.clone();
@@ -170,4 +400,135 @@ impl State {
 
         Ok(())
     }
+
+    /// Sets the authenticated user's presence and custom status.
+    ///
+    /// If a duration in minutes is given, the custom status is cleared after that time.
+    pub fn set_presence(
+        &self,
+        token: impl AsRef<str>,
+        presence: impl AsRef<str>,
+        status: Option<String>,
+        duration: Option<usize>,
+    ) -> Result<(), String> {
+        if !PRESENCES.contains(&presence.as_ref()) {
+            return Err("Invalid presence".to_string());
+        }
+
+        let status = status.filter(|status| !status.is_empty());
+
+        if status
+            .as_ref()
+            .map(|status| status.chars().count() > MAX_STATUS_LENGTH)
+            .unwrap_or(false)
+        {
+            return Err("Status too long".to_string());
+        }
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if let (Some(duration), Some(_)) = (duration, status.as_ref()) {
+            transaction.update_user_presence_with_duration(
+                presence.as_ref(),
+                &status,
+                duration,
+                &uid,
+            )?;
+        } else {
+            transaction.update_user_presence(presence.as_ref(), &status, &uid)?;
+        }
+
+        transaction.commit()?;
+
+        self.broadcast_presence(&uid);
+
+        crate::log!("User {} set presence to {}", uid, presence.as_ref());
+
+        Ok(())
+    }
+
+    /// Sets who can see the authenticated user's email address and bio.
+    ///
+    /// Each setting must be "everyone", "contacts" (members of the user's sets) or "nobody",
+    ///   and settings which are not given are left unchanged.
+    pub fn set_privacy(
+        &self,
+        token: impl AsRef<str>,
+        email: Option<String>,
+        bio: Option<String>,
+    ) -> Result<(), String> {
+        if [&email, &bio]
+            .iter()
+            .any(|visibility| matches!(visibility, Some(v) if !VISIBILITIES.contains(&v.as_str())))
+        {
+            return Err("Invalid visibility".to_string());
+        }
+
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        transaction.update_user_privacy(&email, &bio, &uid)?;
+
+        let mut user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        transaction.commit()?;
+
+        user.resolve_presence(&self.voice);
+
+        self.broadcast_update_user(user);
+
+        crate::log!("User {} updated their privacy settings", uid);
+
+        Ok(())
+    }
+}
+
+/// Determines how the user with the given ID is seen by the user with the given token, or by anyone if no token is given.
+fn viewer_by_token(
+    transaction: &mut Transaction,
+    token: Option<String>,
+    uid: &str,
+) -> Result<Viewer, String> {
+    let viewer = match token {
+        Some(token) => Some(
+            transaction
+                .select_id_by_token(Token(&token))?
+                .ok_or_else(|| "Invalid token".to_string())?,
+        ),
+        None => None,
+    };
+
+    viewer_of(transaction, viewer.as_deref(), uid)
+}
+
+/// Determines how the user with the given ID is seen by the user with the given viewer ID, or by anyone if no viewer is given.
+pub(crate) fn viewer_of(
+    transaction: &mut Transaction,
+    viewer: Option<&str>,
+    uid: &str,
+) -> Result<Viewer, String> {
+    match viewer {
+        Some(viewer) if viewer == uid => Ok(Viewer::Themselves),
+        Some(viewer) if transaction.select_shares_set(viewer, uid)? => Ok(Viewer::Contact),
+        _ => Ok(Viewer::Anyone),
+    }
+}
+
+/// Escapes the wildcard characters of a `LIKE` pattern, so the given string is matched literally.
+fn escape_like(string: &str) -> String {
+    string
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
 }
@@ -0,0 +1,138 @@
+//! Provides email address validation and verification.
+
+use crate::util::Token;
+use crate::State;
+
+#[cfg(not(test))]
+use crate::db::Transaction;
+#[cfg(test)]
+use crate::tests::mock::MockTransaction as Transaction;
+
+use uuid::Uuid;
+
+/// The number of minutes for which an email verification code is valid.
+const VERIFICATION_CODE_LIFETIME: usize = 60 * 24;
+
+/// The maximum number of characters in an email address.
+const MAX_EMAIL_LENGTH: usize = 255;
+
+impl State {
+    /// Verifies the email address of the authenticated user with the code that was sent to it.
+    pub fn verify_email(
+        &self,
+        token: impl AsRef<str>,
+        code: impl AsRef<str>,
+    ) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let uid = transaction
+            .select_id_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        let (code_uid, expired) = transaction
+            .select_email_verification(Token(code.as_ref()))?
+            .ok_or_else(|| "Invalid verification code".to_string())?;
+
+        if code_uid != uid {
+            return Err("Invalid verification code".to_string());
+        }
+
+        if expired {
+            return Err("Verification code expired".to_string());
+        }
+
+        transaction.update_user_email_verified(&uid)?;
+        transaction.delete_email_verification(&uid)?;
+
+        transaction.commit()?;
+
+        crate::log!("User {} verified their email address", uid);
+
+        Ok(())
+    }
+
+    /// Sends a new verification code to the email address of the authenticated user, invalidating any previous code.
+    pub fn resend_verification(&self, token: impl AsRef<str>) -> Result<(), String> {
+        let mut conn = self.db.connection()?;
+        let mut transaction = conn.transaction()?;
+
+        let user = transaction
+            .select_user_by_token(Token(token.as_ref()))?
+            .ok_or_else(|| "Invalid token".to_string())?;
+
+        if transaction.select_email_verified(&user.uid)? {
+            return Err("Email address already verified".to_string());
+        }
+
+        let code = start_verification(&mut transaction, &user.uid)?;
+
+        transaction.commit()?;
+
+        // Users loaded from the database always have their email address, since it is only hidden when viewed by others
+        self.send_verification(user.email.as_deref().unwrap_or_default(), &code)?;
+
+        crate::log!("User {} requested a new verification code", user.uid);
+
+        Ok(())
+    }
+
+    /// Emails the given verification code to the given address.
+    pub(crate) fn send_verification(&self, email: &str, code: &str) -> Result<(), String> {
+        self.mailer.send(
+            email,
+            "Verify your Equion email address",
+            &format!(
+                "Your email verification code is {}.\n\nIt expires in 24 hours. If you did not create an Equion account, you can ignore this email.",
+                code
+            ),
+        )
+    }
+
+    /// Returns an error if the server requires verified email addresses and the given user's is not verified.
+    pub(crate) fn require_verified_email(
+        &self,
+        transaction: &mut Transaction,
+        uid: &str,
+    ) -> Result<(), String> {
+        if self.require_email_verification && !transaction.select_email_verified(uid)? {
+            return Err("You must verify your email address first".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates a new verification code for the given user, replacing any previous code, and returns it.
+pub(crate) fn start_verification(
+    transaction: &mut Transaction,
+    uid: &str,
+) -> Result<String, String> {
+    let code = Uuid::new_v4().to_string();
+
+    transaction.insert_email_verification(uid, Token(&code), VERIFICATION_CODE_LIFETIME)?;
+
+    Ok(code)
+}
+
+/// Checks that the given email address is syntactically valid.
+///
+/// This only rejects addresses which are clearly malformed, since whether an address actually exists is checked by verification.
+pub(crate) fn validate_email(email: &str) -> Result<(), String> {
+    let valid = email.len() <= MAX_EMAIL_LENGTH
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control())
+        && email.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain
+                    .split('.')
+                    .all(|label| !label.is_empty() && !label.starts_with('-'))
+        });
+
+    if !valid {
+        return Err("You must enter a valid email address.".to_string());
+    }
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use crate::db::Database;
-use crate::tests::mock::{MockEventSender, MockOutgoingMessage};
+use crate::ratelimit::RateLimiter;
+use crate::tests::mock::{MockEventSender, MockMailer, MockOutgoingMail, MockOutgoingMessage};
 use crate::voice::VoiceServer;
 use crate::State;
 
@@ -20,6 +21,9 @@ pub enum TestStage {
         data: Value,
         addr: SocketAddr,
     },
+    Mail {
+        data: Value,
+    },
     Import {
         stages: Vec<TestStage>,
     },
@@ -27,17 +31,24 @@ pub enum TestStage {
 
 pub(crate) fn harness(stages: impl Iterator<Item = TestStage>) {
     let (event_tx, event_rx) = channel();
+    let (mail_tx, mail_rx) = channel();
 
     let state = Arc::new(State {
         db: Arc::new(Database::new()),
         global_sender: Arc::new(Mutex::new(Some(MockEventSender::new(event_tx)))),
         subscriptions: Arc::new(RwLock::new(HashMap::new())),
+        connections: Arc::new(RwLock::new(HashMap::new())),
+        client_addrs: Arc::new(RwLock::new(HashMap::new())),
         voice: Arc::new(VoiceServer::new()),
+        mailer: Arc::new(MockMailer::new(mail_tx)),
+        require_email_verification: false,
+        rate_limiter: Arc::new(RateLimiter::new()),
     });
 
     let mut stages: VecDeque<TestStage> = stages.collect();
     let mut variables: HashMap<String, String> = HashMap::new();
     let mut events: VecDeque<MockOutgoingMessage> = VecDeque::new();
+    let mut mails: VecDeque<MockOutgoingMail> = VecDeque::new();
 
     while !stages.is_empty() {
         let stage = stages.pop_front().unwrap();
@@ -56,6 +67,15 @@ pub(crate) fn harness(stages: impl Iterator<Item = TestStage>) {
                     for (key, value) in variables.iter() {
                         input_string = input_string.replace(&format!("{{{{{}}}}}", key), value);
                         output_string = output_string.replace(&format!("{{{{{}}}}}", key), value);
+
+                        // `{{totp:key}}` is replaced with the current code for the secret in `key`,
+                        // and `{{totp+1:key}}` with the next one, since each code can only be used once
+                        for (offset, prefix) in [(0, "totp"), (1, "totp+1")] {
+                            if let Some(code) = crate::totp::code_at_offset(value, offset) {
+                                input_string = input_string
+                                    .replace(&format!("{{{{{}:{}}}}}", prefix, key), &code);
+                            }
+                        }
                     }
 
                     (
@@ -88,6 +108,11 @@ pub(crate) fn harness(stages: impl Iterator<Item = TestStage>) {
                 while let Ok(message) = event_rx.try_recv() {
                     events.push_back(message);
                 }
+
+                // Update mails if necessary
+                while let Ok(mail) = mail_rx.try_recv() {
+                    mails.push_back(mail);
+                }
             }
 
             TestStage::Event {
@@ -111,6 +136,26 @@ pub(crate) fn harness(stages: impl Iterator<Item = TestStage>) {
                 }
             }
 
+            TestStage::Mail { data: stage_data } => {
+                if let Some(mail) = mails.pop_front() {
+                    let data = Value::Object(vec![
+                        ("to".to_string(), Value::String(mail.to)),
+                        ("subject".to_string(), Value::String(mail.subject)),
+                        ("body".to_string(), Value::String(mail.body)),
+                    ]);
+
+                    println!("Mail:          {}", data.serialize());
+                    println!("Expected Mail: {}\n", stage_data.serialize());
+
+                    // Update variables if necessary and check for equality
+                    for (k, v) in update_variables(&data, &stage_data).unwrap() {
+                        variables.insert(k, v);
+                    }
+                } else {
+                    panic!("No mail sent");
+                }
+            }
+
             TestStage::Import {
                 stages: stage_stages,
             } => {
@@ -130,6 +175,16 @@ pub(crate) fn harness(stages: impl Iterator<Item = TestStage>) {
 
         panic!("Some events not handled");
     }
+
+    if !mails.is_empty() {
+        println!("-- UNHANDLED MAILS! --");
+
+        for mail in mails {
+            println!("Mail to {}: {}", mail.to, mail.subject);
+        }
+
+        panic!("Some mails not handled");
+    }
 }
 
 fn update_variables(v1: &Value, v2: &Value) -> Result<Vec<(String, String)>, String> {
@@ -189,17 +244,21 @@ fn update_variables(v1: &Value, v2: &Value) -> Result<Vec<(String, String)>, Str
 
     if let Value::String(v1_s) = v1 {
         if let Value::String(v2_s) = v2 {
-            if v2_s.starts_with("{{") && v2_s.ends_with("}}") {
-                variables.push((
-                    v2_s.strip_prefix("{{")
-                        .unwrap()
-                        .strip_suffix("}}")
-                        .unwrap()
-                        .to_string(),
-                    v1_s.to_string(),
-                ));
-
-                return Ok(variables);
+            // Variables can also be captured from within a larger string, such as the body of a mail
+            if let (Some(start), Some(end)) = (v2_s.find("{{"), v2_s.find("}}")) {
+                let (prefix, suffix) = (&v2_s[..start], &v2_s[end + 2..]);
+
+                if v1_s.len() >= prefix.len() + suffix.len()
+                    && v1_s.starts_with(prefix)
+                    && v1_s.ends_with(suffix)
+                {
+                    variables.push((
+                        v2_s[start + 2..end].to_string(),
+                        v1_s[prefix.len()..v1_s.len() - suffix.len()].to_string(),
+                    ));
+
+                    return Ok(variables);
+                }
             }
 
             if v1_s != v2_s {
@@ -3,6 +3,9 @@ use std::sync::{Arc, Mutex};
 use super::schema::*;
 use super::{MockDatabase, MockDatabaseInner};
 
+use crate::server::account::DELETED_USER_ID;
+use crate::util::hash_token;
+
 use mysql::Value;
 
 pub fn init() -> MockDatabase {
@@ -17,8 +20,15 @@ pub fn init() -> MockDatabase {
             bio: None,
             password: "$argon2id$v=19$m=16,t=2,p=1$SzVRWHZNcFNtVmdIM1BrRA$iyUXX6k0yz6gc6dn2s+TnA"
                 .into(),
-            token: None,
-            creation_date: Value::Date(2022, 1, 1, 0, 0, 0, 0),
+            presence: "online".into(),
+            status: None,
+            status_expiry: None,
+            email_verified: true,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_counter: None,
+            email_visibility: "nobody".into(),
+            bio_visibility: "everyone".into(),
         },
         User {
             id: "user_2".into(),
@@ -29,8 +39,15 @@ pub fn init() -> MockDatabase {
             bio: None,
             password: "$argon2id$v=19$m=16,t=2,p=1$SzVRWHZNcFNtVmdIM1BrRA$vAS2ALgTFaI8Pu5KxATLkw"
                 .into(),
-            token: None,
-            creation_date: Value::Date(2022, 2, 1, 0, 0, 0, 0),
+            presence: "online".into(),
+            status: None,
+            status_expiry: None,
+            email_verified: true,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_counter: None,
+            email_visibility: "nobody".into(),
+            bio_visibility: "everyone".into(),
         },
         User {
             id: "lonely".into(),
@@ -41,8 +58,33 @@ pub fn init() -> MockDatabase {
             bio: Some("Not in any sets".into()),
             password: "$argon2id$v=19$m=16,t=2,p=1$cUFFT2dDZExKMGdPQVAyaQ$Eu2/Jfw/uLDAvfFZMz3DfQ"
                 .into(),
-            token: None,
-            creation_date: Value::Date(2022, 3, 1, 0, 0, 0, 0),
+            presence: "online".into(),
+            status: None,
+            status_expiry: None,
+            email_verified: true,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_counter: None,
+            email_visibility: "nobody".into(),
+            bio_visibility: "everyone".into(),
+        },
+        User {
+            id: DELETED_USER_ID.into(),
+            username: "deleted".into(),
+            display_name: "Deleted User".into(),
+            email: "".into(),
+            image: None,
+            bio: None,
+            password: "".into(),
+            presence: "offline".into(),
+            status: None,
+            status_expiry: None,
+            email_verified: false,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_counter: None,
+            email_visibility: "nobody".into(),
+            bio_visibility: "everyone".into(),
         },
     ];
 
@@ -50,22 +92,44 @@ pub fn init() -> MockDatabase {
         id: "set_1".into(),
         name: "Set 1".into(),
         icon: "1".into(),
-        creation_date: Value::Date(2022, 3, 1, 0, 0, 0, 0),
+        owner: Some("user_1".into()),
     }];
 
+    let roles: Vec<Role> = vec![
+        Role {
+            id: "role_owner".into(),
+            set_id: "set_1".into(),
+            name: "Owner".into(),
+            kind: "owner".into(),
+            permissions: 63,
+        },
+        Role {
+            id: "role_moderator".into(),
+            set_id: "set_1".into(),
+            name: "Moderator".into(),
+            kind: "moderator".into(),
+            permissions: 30,
+        },
+        Role {
+            id: "role_member".into(),
+            set_id: "set_1".into(),
+            name: "Member".into(),
+            kind: "member".into(),
+            permissions: 0,
+        },
+    ];
+
     let memberships: Vec<Membership> = vec![
         Membership {
-            id: "membership_1".into(),
             user_id: "user_1".into(),
             set_id: "set_1".into(),
-            admin: true,
+            role: "role_owner".into(),
             creation_date: Value::Date(2022, 3, 1, 0, 0, 0, 0),
         },
         Membership {
-            id: "membership_2".into(),
             user_id: "user_2".into(),
             set_id: "set_1".into(),
-            admin: false,
+            role: "role_member".into(),
             creation_date: Value::Date(2022, 3, 2, 0, 0, 0, 0),
         },
     ];
@@ -83,9 +147,10 @@ pub fn init() -> MockDatabase {
         id: "subset_1".into(),
         name: "General".into(),
         set_id: "set_1".into(),
-        creation_date: Value::Date(2022, 3, 1, 0, 0, 0, 0),
     }];
 
+    let subset_permissions = Vec::new();
+
     let messages: Vec<Message> = vec![
         Message {
             id: "message_1".into(),
@@ -94,6 +159,11 @@ pub fn init() -> MockDatabase {
             sender: "user_1".into(),
             send_time: Value::Date(2022, 3, 1, 1, 0, 0, 0),
             attachment: None,
+            reply_to: None,
+            thread: None,
+            edited_at: None,
+            deleted_by: None,
+            delete_reason: None,
         },
         Message {
             id: "message_2".into(),
@@ -102,19 +172,100 @@ pub fn init() -> MockDatabase {
             sender: "user_2".into(),
             send_time: Value::Date(2022, 3, 1, 2, 0, 0, 0),
             attachment: None,
+            reply_to: None,
+            thread: None,
+            edited_at: None,
+            deleted_by: None,
+            delete_reason: None,
         },
     ];
 
+    let message_revisions = Vec::new();
+
+    let reactions = Vec::new();
+
+    let pins = Vec::new();
+
+    let mentions = Vec::new();
+
+    let read_markers = Vec::new();
+
+    let dms = Vec::new();
+
+    let dm_participants = Vec::new();
+
+    let dm_messages = Vec::new();
+
+    let friends = Vec::new();
+
+    let blocks = Vec::new();
+
+    // Sessions with an expired access token, one of which can still be refreshed
+    let sessions = vec![
+        Session {
+            id: "expired_session".into(),
+            token: hash_token("expired_token"),
+            user_id: "lonely".into(),
+            device: None,
+            ip: "127.0.0.1".into(),
+            creation_date: Value::Date(2022, 3, 1, 0, 0, 0, 0),
+            last_used: Value::Date(2022, 3, 1, 0, 0, 0, 0),
+            expiry: Value::Date(2022, 3, 1, 1, 0, 0, 0),
+            refresh_token: hash_token("expired_refresh_token"),
+            refresh_expiry: Value::Date(2022, 3, 31, 0, 0, 0, 0),
+        },
+        Session {
+            id: "refreshable_session".into(),
+            token: hash_token("refreshable_token"),
+            user_id: "lonely".into(),
+            device: None,
+            ip: "127.0.0.1".into(),
+            creation_date: now(),
+            last_used: now(),
+            expiry: now(),
+            refresh_token: hash_token("refresh_token"),
+            refresh_expiry: minutes_in_future(60),
+        },
+    ];
+
+    let password_resets = Vec::new();
+
+    let email_verifications = Vec::new();
+
+    let recovery_codes = Vec::new();
+
+    let login_challenges = Vec::new();
+
+    let data_exports = Vec::new();
+
     let files = Vec::new();
 
     MockDatabase {
         database: Arc::new(Mutex::new(MockDatabaseInner {
             users,
             sets,
+            roles,
             memberships,
             invites,
             subsets,
+            subset_permissions,
             messages,
+            message_revisions,
+            reactions,
+            pins,
+            mentions,
+            read_markers,
+            dms,
+            dm_participants,
+            dm_messages,
+            friends,
+            blocks,
+            sessions,
+            password_resets,
+            email_verifications,
+            recovery_codes,
+            login_challenges,
+            data_exports,
             files,
         })),
     }
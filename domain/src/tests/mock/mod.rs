@@ -4,6 +4,8 @@ mod sql;
 
 use schema::*;
 
+use crate::mail::Mailer;
+
 use std::net::SocketAddr;
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex, MutexGuard};
@@ -12,9 +14,27 @@ pub struct MockDatabaseInner {
     pub users: Vec<User>,
     pub memberships: Vec<Membership>,
     pub sets: Vec<Set>,
+    pub roles: Vec<Role>,
     pub invites: Vec<Invite>,
     pub subsets: Vec<Subset>,
+    pub subset_permissions: Vec<SubsetPermission>,
     pub messages: Vec<Message>,
+    pub message_revisions: Vec<MessageRevision>,
+    pub reactions: Vec<Reaction>,
+    pub pins: Vec<Pin>,
+    pub mentions: Vec<Mention>,
+    pub read_markers: Vec<ReadMarker>,
+    pub dms: Vec<Dm>,
+    pub dm_participants: Vec<DmParticipant>,
+    pub dm_messages: Vec<DmMessage>,
+    pub friends: Vec<Friend>,
+    pub blocks: Vec<Block>,
+    pub sessions: Vec<Session>,
+    pub password_resets: Vec<PasswordReset>,
+    pub email_verifications: Vec<EmailVerification>,
+    pub recovery_codes: Vec<RecoveryCode>,
+    pub login_challenges: Vec<LoginChallenge>,
+    pub data_exports: Vec<DataExport>,
     pub files: Vec<File>,
 }
 
@@ -42,6 +62,16 @@ pub struct MockOutgoingMessage {
     pub(crate) message: humphrey_ws::Message,
 }
 
+pub struct MockMailer {
+    inner: Mutex<Sender<MockOutgoingMail>>,
+}
+
+pub struct MockOutgoingMail {
+    pub(crate) to: String,
+    pub(crate) subject: String,
+    pub(crate) body: String,
+}
+
 impl MockDatabase {
     pub fn new() -> Self {
         data::init()
@@ -55,7 +85,7 @@ impl MockDatabase {
 }
 
 impl MockConnection {
-    pub fn transaction(&mut self) -> Result<MockTransaction, String> {
+    pub fn transaction(&mut self) -> Result<MockTransaction<'_>, String> {
         Ok(MockTransaction {
             database: self.database.lock().unwrap(),
             inner: MockTransactionInner,
@@ -83,3 +113,27 @@ impl MockEventSender {
             .ok();
     }
 }
+
+impl MockMailer {
+    pub fn new(inner: Sender<MockOutgoingMail>) -> Self {
+        MockMailer {
+            inner: Mutex::new(inner),
+        }
+    }
+}
+
+impl Mailer for MockMailer {
+    fn send(&self, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .send(MockOutgoingMail {
+                to: to.to_string(),
+                subject: subject.to_string(),
+                body: body.to_string(),
+            })
+            .ok();
+
+        Ok(())
+    }
+}
@@ -9,16 +9,22 @@ pub struct User {
     pub image: Option<String>,
     pub bio: Option<String>,
     pub password: String,
-    pub token: Option<String>,
-    pub creation_date: Value,
+    pub presence: String,
+    pub status: Option<String>,
+    pub status_expiry: Option<Value>,
+    pub email_verified: bool,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_counter: Option<u64>,
+    pub email_visibility: String,
+    pub bio_visibility: String,
 }
 
 #[derive(Clone)]
 pub struct Membership {
-    pub id: String,
     pub user_id: String,
     pub set_id: String,
-    pub admin: bool,
+    pub role: String,
     pub creation_date: Value,
 }
 
@@ -27,7 +33,16 @@ pub struct Set {
     pub id: String,
     pub name: String,
     pub icon: String,
-    pub creation_date: Value,
+    pub owner: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Role {
+    pub id: String,
+    pub set_id: String,
+    pub name: String,
+    pub kind: String,
+    pub permissions: u64,
 }
 
 #[derive(Clone)]
@@ -45,7 +60,14 @@ pub struct Subset {
     pub id: String,
     pub name: String,
     pub set_id: String,
-    pub creation_date: Value,
+}
+
+#[derive(Clone)]
+pub struct SubsetPermission {
+    pub subset_id: String,
+    pub role_id: String,
+    pub can_view: bool,
+    pub can_post: bool,
 }
 
 #[derive(Clone)]
@@ -56,6 +78,46 @@ pub struct Message {
     pub sender: String,
     pub send_time: Value,
     pub attachment: Option<String>,
+    pub reply_to: Option<String>,
+    pub thread: Option<String>,
+    pub edited_at: Option<Value>,
+    pub deleted_by: Option<String>,
+    pub delete_reason: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct MessageRevision {
+    pub message_id: String,
+    pub content: String,
+    pub edit_time: Value,
+}
+
+#[derive(Clone)]
+pub struct Reaction {
+    pub message_id: String,
+    pub user_id: String,
+    pub emoji: String,
+}
+
+#[derive(Clone)]
+pub struct Pin {
+    pub message_id: String,
+    pub pinned_by: String,
+}
+
+#[derive(Clone)]
+pub struct Mention {
+    pub message_id: String,
+    pub position: usize,
+    pub kind: String,
+    pub user_id: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct ReadMarker {
+    pub user_id: String,
+    pub subset_id: String,
+    pub read_time: Value,
 }
 
 #[derive(Clone)]
@@ -65,3 +127,89 @@ pub struct File {
     pub content: Vec<u8>,
     pub owner: String,
 }
+
+#[derive(Clone)]
+pub struct Dm {
+    pub id: String,
+    pub creation_date: Value,
+}
+
+#[derive(Clone)]
+pub struct DmParticipant {
+    pub dm_id: String,
+    pub user_id: String,
+}
+
+#[derive(Clone)]
+pub struct DmMessage {
+    pub id: String,
+    pub dm_id: String,
+    pub sender: String,
+    pub content: String,
+    pub attachment: Option<String>,
+    pub send_time: Value,
+}
+
+#[derive(Clone)]
+pub struct Friend {
+    pub user_id: String,
+    pub friend_id: String,
+    pub accepted: bool,
+}
+
+#[derive(Clone)]
+pub struct Block {
+    pub user_id: String,
+    pub blocked_id: String,
+}
+
+#[derive(Clone)]
+pub struct Session {
+    pub id: String,
+    pub token: String,
+    pub user_id: String,
+    pub device: Option<String>,
+    pub ip: String,
+    pub creation_date: Value,
+    pub last_used: Value,
+    pub expiry: Value,
+    pub refresh_token: String,
+    pub refresh_expiry: Value,
+}
+
+#[derive(Clone)]
+pub struct PasswordReset {
+    pub user_id: String,
+    pub token: String,
+    pub expiry: Value,
+}
+
+#[derive(Clone)]
+pub struct EmailVerification {
+    pub user_id: String,
+    pub token: String,
+    pub expiry: Value,
+}
+
+#[derive(Clone)]
+pub struct RecoveryCode {
+    pub user_id: String,
+    pub token: String,
+}
+
+#[derive(Clone)]
+pub struct DataExport {
+    pub token: String,
+    pub user_id: String,
+    pub content: Vec<u8>,
+    pub expiry: Value,
+}
+
+#[derive(Clone)]
+pub struct LoginChallenge {
+    pub token: String,
+    pub user_id: String,
+    pub device: Option<String>,
+    pub ip: String,
+    pub expiry: Value,
+}
@@ -1,14 +1,23 @@
 use crate::{
     server::{
+        account::{ExportedDmMessage, ExportedMembership, ExportedMessage},
+        dms::Dm,
         files::FileResponse,
+        friends::Friend,
         invites::Invite,
-        messages::Message,
-        sets::{Set, Subset},
+        mentions::Mention,
+        messages::{Message, Reply, Revision},
+        reactions::Reaction,
+        roles::Role,
+        sessions::Session,
+        sets::{Set, Subset, SubsetPermission},
         user::User,
     },
-    util::parse_date,
+    util::{parse_date, Token},
 };
 
+use mysql::Value;
+
 use super::data::{minutes_in_future, now, now_u64};
 use super::schema;
 use super::MockTransaction;
@@ -18,6 +27,174 @@ impl<'a> MockTransaction<'a> {
         Ok(())
     }
 
+    fn session_user(&self, token: Token) -> Option<String> {
+        self.database
+            .sessions
+            .iter()
+            .find(|s| s.token == token.hash() && parse_date(s.expiry.clone()) > now_u64())
+            .map(|s| s.user_id.clone())
+    }
+
+    fn is_session_of(&self, token: Token, uid: &str) -> bool {
+        self.database.sessions.iter().any(|s| {
+            s.token == token.hash() && s.user_id == uid && parse_date(s.expiry.clone()) > now_u64()
+        })
+    }
+
+    fn role_permissions(&self, role: &str) -> u64 {
+        self.database
+            .roles
+            .iter()
+            .find(|r| r.id == role)
+            .map(|r| r.permissions)
+            .unwrap_or(0)
+    }
+
+    fn role_subset_access(&self, role: &str, subset: &str, manage_subsets: u64) -> (bool, bool) {
+        if self.role_permissions(role) & manage_subsets != 0 {
+            return (true, true);
+        }
+
+        self.database
+            .subset_permissions
+            .iter()
+            .find(|p| p.subset_id == subset && p.role_id == role)
+            .map(|p| (p.can_view, p.can_post))
+            .unwrap_or((true, true))
+    }
+
+    fn subset_access(
+        &self,
+        user_id: &str,
+        subset: &str,
+        manage_subsets: u64,
+    ) -> Option<(bool, bool)> {
+        let set = self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .map(|s| s.set_id.clone())?;
+
+        self.database
+            .memberships
+            .iter()
+            .find(|m| m.user_id == user_id && m.set_id == set)
+            .map(|m| self.role_subset_access(&m.role, subset, manage_subsets))
+    }
+
+    fn dm_message_row(&self, m: &schema::DmMessage) -> Message {
+        let user = self
+            .database
+            .users
+            .iter()
+            .find(|u| u.id == m.sender)
+            .unwrap();
+
+        let file_name = m.attachment.as_ref().and_then(|attachment| {
+            self.database
+                .files
+                .iter()
+                .find(|f| &f.id == attachment)
+                .map(|f| f.name.clone())
+        });
+
+        Message::from_row((
+            m.id.clone(),
+            m.content.clone(),
+            m.sender.clone(),
+            user.display_name.clone(),
+            user.image.clone(),
+            m.send_time.clone(),
+            m.attachment.clone(),
+            file_name,
+            None,
+            None,
+            None,
+            None,
+        ))
+    }
+
+    fn unread_messages(&self, uid: &str, subset: &str) -> Vec<&schema::Message> {
+        let set = match self.database.subsets.iter().find(|s| s.id == subset) {
+            Some(s) => &s.set_id,
+            None => return Vec::new(),
+        };
+
+        let joined = match self
+            .database
+            .memberships
+            .iter()
+            .find(|m| m.user_id == uid && &m.set_id == set)
+        {
+            Some(membership) => membership.creation_date.clone(),
+            None => return Vec::new(),
+        };
+
+        let read_time = self
+            .database
+            .read_markers
+            .iter()
+            .find(|r| r.user_id == uid && r.subset_id == subset)
+            .map(|r| r.read_time.clone())
+            .unwrap_or(joined);
+
+        self.database
+            .messages
+            .iter()
+            .filter(|m| {
+                m.subset == subset
+                    && m.deleted_by.is_none()
+                    && m.sender != uid
+                    && parse_date(m.send_time.clone()) > parse_date(read_time.clone())
+            })
+            .collect()
+    }
+
+    fn set_read_marker(&mut self, uid: &str, subset: &str, read_time: Value) {
+        self.database
+            .read_markers
+            .retain(|r| !(r.user_id == uid && r.subset_id == subset));
+
+        self.database.read_markers.push(schema::ReadMarker {
+            user_id: uid.to_string(),
+            subset_id: subset.to_string(),
+            read_time,
+        });
+    }
+
+    fn message_row(&self, m: &schema::Message) -> Message {
+        let user = self
+            .database
+            .users
+            .iter()
+            .find(|u| u.id == m.sender)
+            .unwrap();
+
+        let file_name = m.attachment.clone().and_then(|id| {
+            self.database
+                .files
+                .iter()
+                .find(|f| f.id == id)
+                .map(|f| f.name.clone())
+        });
+
+        Message::from_row((
+            m.id.clone(),
+            m.content.clone(),
+            m.sender.clone(),
+            user.display_name.clone(),
+            user.image.clone(),
+            m.send_time.clone(),
+            m.attachment.clone(),
+            file_name,
+            m.reply_to.clone(),
+            m.thread.clone(),
+            m.edited_at.clone(),
+            m.deleted_by.clone(),
+        ))
+    }
+
     pub fn exists_user_by_username(&mut self, username: &str) -> Result<bool, String> {
         Ok(self
             .database
@@ -33,7 +210,6 @@ impl<'a> MockTransaction<'a> {
         password: &str,
         display_name: &str,
         email: &str,
-        token: &str,
     ) -> Result<(), String> {
         self.database.users.push(schema::User {
             id: id.to_string(),
@@ -41,14 +217,30 @@ impl<'a> MockTransaction<'a> {
             password: password.to_string(),
             display_name: display_name.to_string(),
             email: email.to_string(),
-            token: Some(token.to_string()),
-            creation_date: now(),
             image: None,
             bio: None,
+            presence: "online".to_string(),
+            status: None,
+            status_expiry: None,
+            email_verified: false,
+            totp_secret: None,
+            totp_enabled: false,
+            totp_counter: None,
+            email_visibility: "nobody".to_string(),
+            bio_visibility: "everyone".to_string(),
         });
         Ok(())
     }
 
+    pub fn select_id_by_username(&mut self, username: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .map(|user| user.id.clone()))
+    }
+
     pub fn select_id_and_password_by_username(
         &mut self,
         username: &str,
@@ -61,644 +253,2475 @@ impl<'a> MockTransaction<'a> {
             .map(|user| (user.id.clone(), user.password.clone())))
     }
 
-    pub fn select_id_by_token(&mut self, token: &str) -> Result<Option<String>, String> {
+    pub fn select_id_and_password_by_token(
+        &mut self,
+        token: Token,
+    ) -> Result<Option<(String, String)>, String> {
         Ok(self
             .database
             .users
             .iter()
-            .find(|user| user.token.as_ref().map(|t| t == token).unwrap_or(false))
-            .map(|user| user.id.clone()))
+            .find(|user| self.is_session_of(token, &user.id))
+            .map(|user| (user.id.clone(), user.password.clone())))
     }
 
-    pub fn select_username_by_token(&mut self, token: &str) -> Result<Option<String>, String> {
+    pub fn select_id_and_email_by_username(
+        &mut self,
+        username: &str,
+    ) -> Result<Option<(String, String)>, String> {
         Ok(self
             .database
             .users
             .iter()
-            .find(|user| user.token.as_ref().map(|t| t == token).unwrap_or(false))
-            .map(|user| user.username.clone()))
+            .find(|user| user.username == username)
+            .map(|user| (user.id.clone(), user.email.clone())))
     }
 
-    pub fn update_token_by_id(&mut self, token: &str, id: &str) -> Result<(), String> {
-        if let Some(user) = self.database.users.iter_mut().find(|user| user.id == id) {
-            user.token = Some(token.to_string());
+    pub fn update_user_password(&mut self, password: &str, uid: &str) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.password = password.to_string();
         }
         Ok(())
     }
 
-    pub fn update_invalidate_token(&mut self, token: &str) -> Result<(), String> {
-        if let Some(user) = self
+    pub fn insert_password_reset(
+        &mut self,
+        uid: &str,
+        token: Token,
+        lifetime: usize,
+    ) -> Result<(), String> {
+        self.database.password_resets.retain(|r| r.user_id != uid);
+        self.database.password_resets.push(schema::PasswordReset {
+            user_id: uid.to_string(),
+            token: token.hash(),
+            expiry: minutes_in_future(lifetime),
+        });
+        Ok(())
+    }
+
+    pub fn select_password_reset(
+        &mut self,
+        token: Token,
+    ) -> Result<Option<(String, bool)>, String> {
+        Ok(self
+            .database
+            .password_resets
+            .iter()
+            .find(|r| r.token == token.hash())
+            .map(|r| (r.user_id.clone(), parse_date(r.expiry.clone()) <= now_u64())))
+    }
+
+    pub fn delete_password_reset(&mut self, uid: &str) -> Result<(), String> {
+        self.database.password_resets.retain(|r| r.user_id != uid);
+        Ok(())
+    }
+
+    pub fn select_totp_secret(
+        &mut self,
+        uid: &str,
+    ) -> Result<Option<(String, Option<u64>)>, String> {
+        Ok(self
             .database
             .users
-            .iter_mut()
-            .find(|user| user.token.as_ref().map(|t| t == token).unwrap_or(false))
-        {
-            user.token = None;
+            .iter()
+            .find(|user| user.id == uid && user.totp_enabled)
+            .and_then(|user| {
+                user.totp_secret
+                    .clone()
+                    .map(|secret| (secret, user.totp_counter))
+            }))
+    }
+
+    pub fn select_pending_totp_secret(&mut self, uid: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|user| user.id == uid && !user.totp_enabled)
+            .and_then(|user| user.totp_secret.clone()))
+    }
+
+    pub fn update_user_totp(
+        &mut self,
+        secret: &Option<String>,
+        enabled: bool,
+        uid: &str,
+    ) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.totp_secret = secret.clone();
+            user.totp_enabled = enabled;
+            user.totp_counter = None;
         }
         Ok(())
     }
 
-    pub fn select_file_by_id(&mut self, id: &str) -> Result<Option<FileResponse>, String> {
+    pub fn update_totp_counter(&mut self, counter: u64, uid: &str) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.totp_counter = Some(counter);
+        }
+        Ok(())
+    }
+
+    pub fn insert_recovery_code(&mut self, uid: &str, token: Token) -> Result<(), String> {
+        self.database.recovery_codes.push(schema::RecoveryCode {
+            user_id: uid.to_string(),
+            token: token.hash(),
+        });
+        Ok(())
+    }
+
+    pub fn select_recovery_code(&mut self, uid: &str, token: Token) -> Result<bool, String> {
         Ok(self
             .database
-            .files
+            .recovery_codes
             .iter()
-            .find(|file| file.id == id)
-            .map(|file| {
-                FileResponse::from_row((
-                    file.id.clone(),
-                    file.name.clone(),
-                    file.content.clone(),
-                    file.owner.clone(),
-                ))
-            }))
+            .any(|code| code.user_id == uid && code.token == token.hash()))
     }
 
-    pub fn insert_file(
+    pub fn delete_recovery_code(&mut self, uid: &str, token: Token) -> Result<(), String> {
+        self.database
+            .recovery_codes
+            .retain(|code| !(code.user_id == uid && code.token == token.hash()));
+        Ok(())
+    }
+
+    pub fn delete_recovery_codes(&mut self, uid: &str) -> Result<(), String> {
+        self.database
+            .recovery_codes
+            .retain(|code| code.user_id != uid);
+        Ok(())
+    }
+
+    pub fn insert_login_challenge(
         &mut self,
-        id: &str,
-        name: &str,
-        content: Vec<u8>,
-        owner: &str,
+        token: Token,
+        uid: &str,
+        device: &Option<String>,
+        ip: &str,
+        lifetime: usize,
     ) -> Result<(), String> {
-        self.database.files.push(schema::File {
-            id: id.to_string(),
-            name: name.to_string(),
-            content,
-            owner: owner.to_string(),
+        self.database.login_challenges.push(schema::LoginChallenge {
+            token: token.hash(),
+            user_id: uid.to_string(),
+            device: device.clone(),
+            ip: ip.to_string(),
+            expiry: minutes_in_future(lifetime),
         });
         Ok(())
     }
 
-    pub fn select_username_by_subset_membership_token(
+    #[allow(clippy::type_complexity)]
+    pub fn select_login_challenge(
         &mut self,
-        token: &str,
-        subset: &str,
-    ) -> Result<Option<String>, String> {
+        token: Token,
+    ) -> Result<Option<(String, Option<String>, String, bool)>, String> {
         Ok(self
             .database
-            .subsets
+            .login_challenges
             .iter()
-            .find(|s| s.id == subset)
-            .and_then(|subset| {
-                self.database
-                    .users
-                    .iter()
-                    .find(|user| {
-                        user.token == Some(token.to_string())
-                            && self
-                                .database
-                                .memberships
-                                .iter()
-                                .any(|m| m.user_id == user.id && m.set_id == subset.set_id)
-                    })
-                    .map(|user| user.username.clone())
+            .find(|c| c.token == token.hash())
+            .map(|c| {
+                (
+                    c.user_id.clone(),
+                    c.device.clone(),
+                    c.ip.clone(),
+                    parse_date(c.expiry.clone()) <= now_u64(),
+                )
             }))
     }
 
-    pub fn select_messages_before(
+    pub fn delete_login_challenge(&mut self, token: Token) -> Result<(), String> {
+        self.database
+            .login_challenges
+            .retain(|c| c.token != token.hash());
+        Ok(())
+    }
+
+    pub fn delete_expired_login_challenges(&mut self) -> Result<(), String> {
+        self.database
+            .login_challenges
+            .retain(|c| parse_date(c.expiry.clone()) > now_u64());
+        Ok(())
+    }
+
+    pub fn select_export_memberships(
         &mut self,
-        subset: &str,
-        before: &str,
-        limit: usize,
-    ) -> Result<Vec<Message>, String> {
-        Ok(self
+        uid: &str,
+    ) -> Result<Vec<ExportedMembership>, String> {
+        let mut memberships = self
             .database
-            .messages
+            .memberships
             .iter()
-            .find(|message| message.id == before)
-            .map(|before_message| {
-                self.database
-                    .messages
-                    .iter()
-                    .filter(|m| {
-                        m.subset == subset
-                            && parse_date(m.send_time.clone())
-                                < parse_date(before_message.send_time.clone())
-                    })
-                    .rev()
-                    .take(limit)
-                    .map(|m| {
-                        let user = self
-                            .database
-                            .users
-                            .iter()
-                            .find(|u| u.id == m.sender)
-                            .unwrap();
-
-                        let file_name = m.attachment.clone().and_then(|id| {
-                            self.database
-                                .files
-                                .iter()
-                                .find(|f| f.id == id)
-                                .map(|f| f.name.clone())
-                        });
-
-                        Message::from_row((
-                            m.id.clone(),
-                            m.content.clone(),
-                            m.sender.clone(),
-                            user.display_name.clone(),
-                            user.image.clone(),
-                            m.send_time.clone(),
-                            m.attachment.clone(),
-                            file_name,
-                        ))
-                    })
-                    .collect::<Vec<_>>()
+            .filter(|m| m.user_id == uid)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        memberships.sort_by_key(|m| parse_date(m.creation_date.clone()));
+
+        Ok(memberships
+            .into_iter()
+            .filter_map(|m| {
+                let set = self.database.sets.iter().find(|s| s.id == m.set_id)?;
+                let role = self.database.roles.iter().find(|r| r.id == m.role)?;
+
+                Some(ExportedMembership::from_row((
+                    set.id.clone(),
+                    set.name.clone(),
+                    role.name.clone(),
+                    m.creation_date,
+                )))
             })
-            .unwrap_or_default())
+            .collect())
     }
 
-    pub fn select_messages(&mut self, subset: &str, limit: usize) -> Result<Vec<Message>, String> {
+    pub fn select_export_messages(&mut self, uid: &str) -> Result<Vec<ExportedMessage>, String> {
         Ok(self
             .database
             .messages
             .iter()
-            .filter(|m| m.subset == subset)
-            .rev()
-            .take(limit)
-            .map(|m| {
-                let user = self
-                    .database
-                    .users
-                    .iter()
-                    .find(|u| u.id == m.sender)
-                    .unwrap();
-
-                let file_name = m.attachment.clone().and_then(|id| {
-                    self.database
-                        .files
-                        .iter()
-                        .find(|f| f.id == id)
-                        .map(|f| f.name.clone())
-                });
+            .filter(|m| m.sender == uid)
+            .filter_map(|m| {
+                let subset = self.database.subsets.iter().find(|s| s.id == m.subset)?;
 
-                Message::from_row((
+                Some(ExportedMessage::from_row((
                     m.id.clone(),
+                    subset.set_id.clone(),
+                    m.subset.clone(),
                     m.content.clone(),
-                    m.sender.clone(),
-                    user.display_name.clone(),
-                    user.image.clone(),
                     m.send_time.clone(),
-                    m.attachment.clone(),
-                    file_name,
-                ))
+                )))
             })
             .collect())
     }
 
-    pub fn select_message_by_id_and_token(
+    pub fn select_export_dm_messages(
         &mut self,
-        message: &str,
-        token: &str,
-    ) -> Result<Option<Message>, String> {
+        uid: &str,
+    ) -> Result<Vec<ExportedDmMessage>, String> {
         Ok(self
             .database
-            .messages
+            .dm_messages
             .iter()
-            .find(|m| m.id == message)
-            .and_then(|message| {
-                self.database
-                    .users
-                    .iter()
-                    .find(|u| u.id == message.sender)
-                    .map(|user| (user, message))
+            .filter(|m| m.sender == uid)
+            .map(|m| {
+                ExportedDmMessage::from_row((
+                    m.id.clone(),
+                    m.dm_id.clone(),
+                    m.content.clone(),
+                    m.send_time.clone(),
+                ))
             })
-            .and_then(|(user, message)| {
-                if user.token == Some(token.to_string()) {
-                    Some(Message::from_row((
-                        message.id.clone(),
-                        message.content.clone(),
-                        message.sender.clone(),
-                        user.display_name.clone(),
-                        user.image.clone(),
-                        message.send_time.clone(),
-                        message.attachment.clone(),
-                        message.attachment.clone().and_then(|id| {
-                            self.database
-                                .files
-                                .iter()
-                                .find(|f| f.id == id)
-                                .map(|f| f.name.clone())
-                        }),
-                    )))
-                } else {
-                    None
-                }
-            }))
+            .collect())
     }
 
-    #[allow(clippy::type_complexity)]
-    pub fn select_subset_metadata(
-        &mut self,
-        token: &str,
-        subset: &str,
-    ) -> Result<Option<(String, String, String, Option<String>)>, String> {
+    pub fn select_files_by_owner(&mut self, uid: &str) -> Result<Vec<FileResponse>, String> {
         Ok(self
             .database
-            .subsets
+            .files
             .iter()
-            .find(|s| s.id == subset)
-            .and_then(|s| {
-                let set = s.set_id.clone();
+            .filter(|f| f.owner == uid)
+            .map(|f| {
+                FileResponse::from_row((
+                    f.id.clone(),
+                    f.name.clone(),
+                    f.content.clone(),
+                    f.owner.clone(),
+                ))
+            })
+            .collect())
+    }
 
-                let user = self
-                    .database
-                    .users
-                    .iter()
-                    .find(|u| u.token == Some(token.to_string()))?;
+    pub fn select_set_successor(&mut self, set: &str, uid: &str) -> Result<Option<String>, String> {
+        let mut memberships = self
+            .database
+            .memberships
+            .iter()
+            .filter(|m| m.set_id == set && m.user_id != uid)
+            .collect::<Vec<_>>();
 
-                if !self
-                    .database
-                    .memberships
-                    .iter()
-                    .any(|m| m.user_id == user.id && m.set_id == set)
-                {
-                    return None;
-                }
+        memberships.sort_by_key(|m| {
+            let is_moderator = self
+                .database
+                .roles
+                .iter()
+                .any(|r| r.id == m.role && r.kind == "moderator");
 
-                Some((
-                    set,
-                    user.id.clone(),
-                    user.display_name.clone(),
-                    user.image.clone(),
+            (!is_moderator, parse_date(m.creation_date.clone()))
+        });
+
+        Ok(memberships.first().map(|m| m.user_id.clone()))
+    }
+
+    pub fn update_messages_sender(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for message in self.database.messages.iter_mut() {
+            if message.sender == uid {
+                message.sender = new_uid.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_messages_deleted_by(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for message in self.database.messages.iter_mut() {
+            if message.deleted_by.as_deref() == Some(uid) {
+                message.deleted_by = Some(new_uid.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_pins_pinned_by(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for pin in self.database.pins.iter_mut() {
+            if pin.pinned_by == uid {
+                pin.pinned_by = new_uid.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_mentions_user(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for mention in self.database.mentions.iter_mut() {
+            if mention.user_id.as_deref() == Some(uid) {
+                mention.user_id = Some(new_uid.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_dm_messages_sender(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for message in self.database.dm_messages.iter_mut() {
+            if message.sender == uid {
+                message.sender = new_uid.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn update_files_owner(&mut self, new_uid: &str, uid: &str) -> Result<(), String> {
+        for file in self.database.files.iter_mut() {
+            if file.owner == uid {
+                file.owner = new_uid.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn delete_user_reactions(&mut self, uid: &str) -> Result<(), String> {
+        self.database.reactions.retain(|r| r.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_user_read_markers(&mut self, uid: &str) -> Result<(), String> {
+        self.database.read_markers.retain(|r| r.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_user_memberships(&mut self, uid: &str) -> Result<(), String> {
+        self.database.memberships.retain(|m| m.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_user_dm_participants(&mut self, uid: &str) -> Result<(), String> {
+        self.database.dm_participants.retain(|p| p.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_all_friends_for_user(&mut self, uid: &str) -> Result<(), String> {
+        self.database
+            .friends
+            .retain(|f| f.user_id != uid && f.friend_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_all_blocks_for_user(&mut self, uid: &str) -> Result<(), String> {
+        self.database
+            .blocks
+            .retain(|b| b.user_id != uid && b.blocked_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_user_login_challenges(&mut self, uid: &str) -> Result<(), String> {
+        self.database.login_challenges.retain(|c| c.user_id != uid);
+        Ok(())
+    }
+
+    pub fn insert_data_export(
+        &mut self,
+        token: Token,
+        uid: &str,
+        content: Vec<u8>,
+        lifetime: usize,
+    ) -> Result<(), String> {
+        self.database.data_exports.push(schema::DataExport {
+            token: token.hash(),
+            user_id: uid.to_string(),
+            content,
+            expiry: minutes_in_future(lifetime),
+        });
+        Ok(())
+    }
+
+    pub fn select_data_export(
+        &mut self,
+        token: Token,
+    ) -> Result<Option<(String, Vec<u8>, bool)>, String> {
+        Ok(self
+            .database
+            .data_exports
+            .iter()
+            .find(|e| e.token == token.hash())
+            .map(|e| {
+                (
+                    e.user_id.clone(),
+                    e.content.clone(),
+                    parse_date(e.expiry.clone()) <= now_u64(),
+                )
+            }))
+    }
+
+    pub fn delete_data_export(&mut self, token: Token) -> Result<(), String> {
+        self.database
+            .data_exports
+            .retain(|e| e.token != token.hash());
+        Ok(())
+    }
+
+    pub fn delete_user_data_exports(&mut self, uid: &str) -> Result<(), String> {
+        self.database.data_exports.retain(|e| e.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_expired_data_exports(&mut self) -> Result<(), String> {
+        self.database
+            .data_exports
+            .retain(|e| parse_date(e.expiry.clone()) > now_u64());
+        Ok(())
+    }
+
+    pub fn delete_user(&mut self, uid: &str) -> Result<(), String> {
+        self.database.users.retain(|u| u.id != uid);
+        Ok(())
+    }
+
+    pub fn select_email_verified(&mut self, uid: &str) -> Result<bool, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .any(|user| user.id == uid && user.email_verified))
+    }
+
+    pub fn update_user_email_verified(&mut self, uid: &str) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.email_verified = true;
+        }
+        Ok(())
+    }
+
+    pub fn insert_email_verification(
+        &mut self,
+        uid: &str,
+        token: Token,
+        lifetime: usize,
+    ) -> Result<(), String> {
+        self.database
+            .email_verifications
+            .retain(|v| v.user_id != uid);
+        self.database
+            .email_verifications
+            .push(schema::EmailVerification {
+                user_id: uid.to_string(),
+                token: token.hash(),
+                expiry: minutes_in_future(lifetime),
+            });
+        Ok(())
+    }
+
+    pub fn select_email_verification(
+        &mut self,
+        token: Token,
+    ) -> Result<Option<(String, bool)>, String> {
+        Ok(self
+            .database
+            .email_verifications
+            .iter()
+            .find(|v| v.token == token.hash())
+            .map(|v| (v.user_id.clone(), parse_date(v.expiry.clone()) <= now_u64())))
+    }
+
+    pub fn delete_email_verification(&mut self, uid: &str) -> Result<(), String> {
+        self.database
+            .email_verifications
+            .retain(|v| v.user_id != uid);
+        Ok(())
+    }
+
+    pub fn select_id_by_token(&mut self, token: Token) -> Result<Option<String>, String> {
+        Ok(self.session_user(token))
+    }
+
+    pub fn select_username_by_token(&mut self, token: Token) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|user| self.is_session_of(token, &user.id))
+            .map(|user| user.username.clone()))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_session(
+        &mut self,
+        id: &str,
+        token: Token,
+        refresh_token: Token,
+        uid: &str,
+        device: &Option<String>,
+        ip: &str,
+        lifetime: usize,
+        refresh_lifetime: usize,
+    ) -> Result<(), String> {
+        self.database.sessions.push(schema::Session {
+            id: id.to_string(),
+            token: token.hash(),
+            user_id: uid.to_string(),
+            device: device.clone(),
+            ip: ip.to_string(),
+            creation_date: now(),
+            last_used: now(),
+            expiry: minutes_in_future(lifetime),
+            refresh_token: refresh_token.hash(),
+            refresh_expiry: minutes_in_future(refresh_lifetime),
+        });
+        Ok(())
+    }
+
+    pub fn select_is_token_expired(&mut self, token: Token) -> Result<bool, String> {
+        Ok(self
+            .database
+            .sessions
+            .iter()
+            .any(|s| s.token == token.hash() && parse_date(s.expiry.clone()) <= now_u64()))
+    }
+
+    pub fn select_session_by_refresh_token(
+        &mut self,
+        refresh_token: Token,
+    ) -> Result<Option<(String, String, bool)>, String> {
+        Ok(self
+            .database
+            .sessions
+            .iter()
+            .find(|s| s.refresh_token == refresh_token.hash())
+            .map(|s| {
+                (
+                    s.id.clone(),
+                    s.user_id.clone(),
+                    parse_date(s.refresh_expiry.clone()) <= now_u64(),
+                )
+            }))
+    }
+
+    pub fn update_session_tokens(
+        &mut self,
+        token: Token,
+        refresh_token: Token,
+        lifetime: usize,
+        refresh_lifetime: usize,
+        id: &str,
+    ) -> Result<(), String> {
+        if let Some(session) = self.database.sessions.iter_mut().find(|s| s.id == id) {
+            session.token = token.hash();
+            session.refresh_token = refresh_token.hash();
+            session.last_used = now();
+            session.expiry = minutes_in_future(lifetime);
+            session.refresh_expiry = minutes_in_future(refresh_lifetime);
+        }
+        Ok(())
+    }
+
+    pub fn select_session_id_by_token(&mut self, token: Token) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .sessions
+            .iter()
+            .find(|s| s.token == token.hash() && parse_date(s.expiry.clone()) > now_u64())
+            .map(|s| s.id.clone()))
+    }
+
+    pub fn select_sessions_by_user(
+        &mut self,
+        token: Token,
+        uid: &str,
+    ) -> Result<Vec<Session>, String> {
+        let mut sessions = self
+            .database
+            .sessions
+            .iter()
+            .filter(|s| s.user_id == uid)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        sessions.sort_by_key(|s| std::cmp::Reverse(parse_date(s.last_used.clone())));
+
+        Ok(sessions
+            .into_iter()
+            .map(|s| {
+                Session::from_row((
+                    s.id,
+                    s.device,
+                    s.ip,
+                    s.creation_date,
+                    s.last_used,
+                    s.token == token.hash(),
+                ))
+            })
+            .collect())
+    }
+
+    pub fn select_session_owner(&mut self, id: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .sessions
+            .iter()
+            .find(|s| s.id == id)
+            .map(|s| s.user_id.clone()))
+    }
+
+    pub fn update_session_last_used(&mut self, token: Token) -> Result<(), String> {
+        if let Some(session) = self
+            .database
+            .sessions
+            .iter_mut()
+            .find(|s| s.token == token.hash())
+        {
+            session.last_used = now();
+        }
+        Ok(())
+    }
+
+    pub fn delete_session(&mut self, id: &str) -> Result<(), String> {
+        self.database.sessions.retain(|s| s.id != id);
+        Ok(())
+    }
+
+    pub fn delete_session_by_token(&mut self, token: Token) -> Result<(), String> {
+        self.database.sessions.retain(|s| s.token != token.hash());
+        Ok(())
+    }
+
+    pub fn delete_user_sessions(&mut self, uid: &str) -> Result<(), String> {
+        self.database.sessions.retain(|s| s.user_id != uid);
+        Ok(())
+    }
+
+    pub fn delete_expired_sessions(&mut self) -> Result<(), String> {
+        self.database
+            .sessions
+            .retain(|s| parse_date(s.refresh_expiry.clone()) > now_u64());
+        Ok(())
+    }
+
+    pub fn select_file_by_id(&mut self, id: &str) -> Result<Option<FileResponse>, String> {
+        Ok(self
+            .database
+            .files
+            .iter()
+            .find(|file| file.id == id)
+            .map(|file| {
+                FileResponse::from_row((
+                    file.id.clone(),
+                    file.name.clone(),
+                    file.content.clone(),
+                    file.owner.clone(),
                 ))
             }))
     }
 
-    pub fn insert_message(
+    pub fn insert_file(
         &mut self,
         id: &str,
-        content: &str,
-        subset: &str,
-        sender: &str,
-        attachment_id: &Option<String>,
+        name: &str,
+        content: Vec<u8>,
+        owner: &str,
     ) -> Result<(), String> {
-        self.database.messages.push(schema::Message {
+        self.database.files.push(schema::File {
             id: id.to_string(),
-            content: content.to_string(),
-            subset: subset.to_string(),
-            sender: sender.to_string(),
-            send_time: now(),
-            attachment: attachment_id.clone(),
+            name: name.to_string(),
+            content,
+            owner: owner.to_string(),
         });
         Ok(())
     }
 
-    pub fn select_set_by_subset(&mut self, subset: &str) -> Result<Option<String>, String> {
-        Ok(self
+    pub fn select_username_by_subset_membership_token(
+        &mut self,
+        token: Token,
+        subset: &str,
+        manage_subsets: u64,
+    ) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .and_then(|subset| {
+                self.database
+                    .users
+                    .iter()
+                    .find(|user| {
+                        self.is_session_of(token, &user.id)
+                            && self
+                                .subset_access(&user.id, &subset.id, manage_subsets)
+                                .map(|(view, _)| view)
+                                .unwrap_or(false)
+                    })
+                    .map(|user| user.username.clone())
+            }))
+    }
+
+    pub fn select_messages_before(
+        &mut self,
+        subset: &str,
+        before: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|message| message.id == before)
+            .map(|before_message| {
+                self.database
+                    .messages
+                    .iter()
+                    .filter(|m| {
+                        m.subset == subset
+                            && m.thread.is_none()
+                            && (m.deleted_by.is_none() || self.has_live_thread_replies(&m.id))
+                            && parse_date(m.send_time.clone())
+                                < parse_date(before_message.send_time.clone())
+                    })
+                    .rev()
+                    .take(limit)
+                    .map(|m| self.message_row(m))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn select_messages(&mut self, subset: &str, limit: usize) -> Result<Vec<Message>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .filter(|m| {
+                m.subset == subset
+                    && m.thread.is_none()
+                    && (m.deleted_by.is_none() || self.has_live_thread_replies(&m.id))
+            })
+            .rev()
+            .take(limit)
+            .map(|m| self.message_row(m))
+            .collect())
+    }
+
+    pub fn select_thread_messages_before(
+        &mut self,
+        thread: &str,
+        before: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|message| message.id == before)
+            .map(|before_message| {
+                self.database
+                    .messages
+                    .iter()
+                    .filter(|m| {
+                        m.thread.as_deref() == Some(thread)
+                            && m.deleted_by.is_none()
+                            && parse_date(m.send_time.clone())
+                                < parse_date(before_message.send_time.clone())
+                    })
+                    .rev()
+                    .take(limit)
+                    .map(|m| self.message_row(m))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn select_thread_messages(
+        &mut self,
+        thread: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .filter(|m| m.thread.as_deref() == Some(thread) && m.deleted_by.is_none())
+            .rev()
+            .take(limit)
+            .map(|m| self.message_row(m))
+            .collect())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn select_search_messages(
+        &mut self,
+        uid: &str,
+        query: &str,
+        manage_subsets: u64,
+        set: &Option<String>,
+        subset: &Option<String>,
+        author: &Option<String>,
+        has_attachment: bool,
+        after: &Option<u64>,
+        before: &Option<u64>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Vec<(Message, String, String)>, String> {
+        let terms = query
+            .split_whitespace()
+            .map(|term| term.trim_start_matches('+').trim_end_matches('*'))
+            .collect::<Vec<_>>();
+
+        let mut messages = self
+            .database
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| {
+                let content = m.content.to_lowercase();
+                let send_time = parse_date(m.send_time.clone());
+                let message_set = self
+                    .database
+                    .subsets
+                    .iter()
+                    .find(|s| s.id == m.subset)
+                    .map(|s| s.set_id.clone());
+
+                // Full-text search matches the start of words
+                m.deleted_by.is_none()
+                    && terms.iter().all(|term| {
+                        content
+                            .split(|c: char| !c.is_alphanumeric() && c != '_')
+                            .any(|word| word.starts_with(term))
+                    })
+                    && self
+                        .subset_access(uid, &m.subset, manage_subsets)
+                        .map(|(view, _)| view)
+                        .unwrap_or(false)
+                    && (set.is_none() || set == &message_set)
+                    && subset.as_ref().map(|s| s == &m.subset).unwrap_or(true)
+                    && author.as_ref().map(|a| a == &m.sender).unwrap_or(true)
+                    && (!has_attachment || m.attachment.is_some())
+                    && after.map(|after| send_time >= after).unwrap_or(true)
+                    && before.map(|before| send_time < before).unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+
+        messages.sort_by_key(|m| std::cmp::Reverse(parse_date(m.send_time.clone())));
+
+        Ok(messages
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|m| {
+                let set = self
+                    .database
+                    .subsets
+                    .iter()
+                    .find(|s| s.id == m.subset)
+                    .map(|s| s.set_id.clone())
+                    .unwrap_or_default();
+
+                (self.message_row(m), set, m.subset.clone())
+            })
+            .collect())
+    }
+
+    pub fn select_thread_summaries(
+        &mut self,
+        messages: &str,
+    ) -> Result<Vec<(String, u64, Value)>, String> {
+        Ok(message_ids(messages)
+            .into_iter()
+            .filter_map(|message| {
+                let replies = self.database.messages.iter().filter(|m| {
+                    m.thread.as_deref() == Some(message.as_str()) && m.deleted_by.is_none()
+                });
+
+                let count = replies.clone().count() as u64;
+                let last_reply_time = replies
+                    .map(|m| m.send_time.clone())
+                    .max_by_key(|time| parse_date(time.clone()))?;
+
+                Some((message, count, last_reply_time))
+            })
+            .collect())
+    }
+
+    pub fn select_message_subset_and_thread(
+        &mut self,
+        message: &str,
+    ) -> Result<Option<(String, Option<String>)>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|m| m.id == message && m.deleted_by.is_none())
+            .map(|m| (m.subset.clone(), m.thread.clone())))
+    }
+
+    pub fn select_message_by_id(&mut self, message: &str) -> Result<Option<Message>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|m| m.id == message && m.deleted_by.is_none())
+            .map(|m| self.message_row(m)))
+    }
+
+    pub fn soft_delete_message(
+        &mut self,
+        deleted_by: &str,
+        reason: &Option<String>,
+        message: &str,
+    ) -> Result<(), String> {
+        if let Some(m) = self.database.messages.iter_mut().find(|m| m.id == message) {
+            m.content = String::new();
+            m.attachment = None;
+            m.deleted_by = Some(deleted_by.to_string());
+            m.delete_reason = reason.clone();
+        }
+        Ok(())
+    }
+
+    pub fn select_message_is_deleted(&mut self, message: &str) -> Result<bool, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .any(|m| m.id == message && m.deleted_by.is_some()))
+    }
+
+    pub fn select_delete_reasons(
+        &mut self,
+        messages: &str,
+    ) -> Result<Vec<(String, String)>, String> {
+        let messages = message_ids(messages);
+
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .filter(|m| messages.contains(&m.id))
+            .filter_map(|m| Some((m.id.clone(), m.delete_reason.clone()?)))
+            .collect())
+    }
+
+    pub fn delete_file(&mut self, id: &str) -> Result<(), String> {
+        self.database.files.retain(|f| f.id != id);
+        Ok(())
+    }
+
+    fn has_live_thread_replies(&self, message: &str) -> bool {
+        self.database
+            .messages
+            .iter()
+            .any(|m| m.thread.as_deref() == Some(message) && m.deleted_by.is_none())
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn select_subset_metadata(
+        &mut self,
+        token: Token,
+        subset: &str,
+        manage_subsets: u64,
+    ) -> Result<Option<(String, String, String, Option<String>)>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .and_then(|s| {
+                let set = s.set_id.clone();
+
+                let user = self
+                    .database
+                    .users
+                    .iter()
+                    .find(|u| self.is_session_of(token, &u.id))?;
+
+                if !self
+                    .subset_access(&user.id, subset, manage_subsets)
+                    .map(|(_, post)| post)
+                    .unwrap_or(false)
+                {
+                    return None;
+                }
+
+                Some((
+                    set,
+                    user.id.clone(),
+                    user.display_name.clone(),
+                    user.image.clone(),
+                ))
+            }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert_message(
+        &mut self,
+        id: &str,
+        content: &str,
+        subset: &str,
+        sender: &str,
+        attachment_id: &Option<String>,
+        reply_to: &Option<String>,
+        thread: &Option<String>,
+    ) -> Result<(), String> {
+        self.database.messages.push(schema::Message {
+            id: id.to_string(),
+            content: content.to_string(),
+            subset: subset.to_string(),
+            sender: sender.to_string(),
+            send_time: now(),
+            attachment: attachment_id.clone(),
+            reply_to: reply_to.clone(),
+            thread: thread.clone(),
+            edited_at: None,
+            deleted_by: None,
+            delete_reason: None,
+        });
+        Ok(())
+    }
+
+    pub fn select_reply_previews(&mut self, messages: &str) -> Result<Vec<Reply>, String> {
+        Ok(message_ids(messages)
+            .iter()
+            .filter_map(|message| self.select_reply_preview(message).ok().flatten())
+            .collect())
+    }
+
+    pub fn select_reply_preview(&mut self, message: &str) -> Result<Option<Reply>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|m| m.id == message && m.deleted_by.is_none())
+            .and_then(|m| {
+                self.database
+                    .users
+                    .iter()
+                    .find(|u| u.id == m.sender)
+                    .map(|u| {
+                        Reply::from_row((m.id.clone(), u.display_name.clone(), m.content.clone()))
+                    })
+            }))
+    }
+
+    pub fn select_set_by_subset(&mut self, subset: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .map(|s| s.set_id.clone()))
+    }
+
+    pub fn select_message_set_and_subset(
+        &mut self,
+        message: &str,
+    ) -> Result<Option<(String, String)>, String> {
+        Ok(self
+            .database
+            .messages
+            .iter()
+            .find(|m| m.id == message)
+            .and_then(|m| {
+                self.database
+                    .subsets
+                    .iter()
+                    .find(|s| s.id == m.subset)
+                    .map(|s| (s.set_id.clone(), s.id.clone()))
+            }))
+    }
+
+    pub fn select_sets_by_token(&mut self, token: Token) -> Result<Vec<Set>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|u| self.is_session_of(token, &u.id))
+            .map(|user| {
+                self.database
+                    .memberships
+                    .iter()
+                    .filter(|m| m.user_id == user.id)
+                    .flat_map(|m| {
+                        self.database
+                            .sets
+                            .iter()
+                            .find(|s| s.id == m.set_id)
+                            .map(|s| {
+                                Set::from_row((
+                                    s.id.clone(),
+                                    s.name.clone(),
+                                    s.icon.clone(),
+                                    s.owner.clone(),
+                                    m.role.clone(),
+                                    self.role_permissions(&m.role),
+                                ))
+                            })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn select_subsets_by_set_and_role(
+        &mut self,
+        manage_subsets: u64,
+        role: &str,
+        set: &str,
+    ) -> Result<Vec<Subset>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .filter(|s| s.set_id == set)
+            .filter_map(|s| {
+                let (view, post) = self.role_subset_access(role, &s.id, manage_subsets);
+
+                if view {
+                    Some(Subset::from_row((s.id.clone(), s.name.clone(), post)))
+                } else {
+                    None
+                }
+            })
+            .collect())
+    }
+
+    pub fn select_user_can_post_in_subset(
+        &mut self,
+        uid: &str,
+        subset: &str,
+        manage_subsets: u64,
+    ) -> Result<bool, String> {
+        Ok(self
+            .subset_access(uid, subset, manage_subsets)
+            .map(|(_, post)| post)
+            .unwrap_or(false))
+    }
+
+    pub fn select_user_ids_by_subset_access(
+        &mut self,
+        subset: &str,
+        manage_subsets: u64,
+    ) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .map(|s| {
+                self.database
+                    .memberships
+                    .iter()
+                    .filter(|m| {
+                        m.set_id == s.set_id
+                            && self.role_subset_access(&m.role, subset, manage_subsets).0
+                    })
+                    .map(|m| m.user_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn select_subset_permissions(
+        &mut self,
+        subset: &str,
+    ) -> Result<Vec<SubsetPermission>, String> {
+        Ok(self
+            .database
+            .subset_permissions
+            .iter()
+            .filter(|p| p.subset_id == subset)
+            .map(|p| SubsetPermission::from_row((p.role_id.clone(), p.can_view, p.can_post)))
+            .collect())
+    }
+
+    pub fn insert_subset_permission(
+        &mut self,
+        _id: &str,
+        subset: &str,
+        role: &str,
+        can_view: bool,
+        can_post: bool,
+    ) -> Result<(), String> {
+        self.database
+            .subset_permissions
+            .push(schema::SubsetPermission {
+                subset_id: subset.to_string(),
+                role_id: role.to_string(),
+                can_view,
+                can_post,
+            });
+        Ok(())
+    }
+
+    pub fn delete_subset_permission(&mut self, subset: &str, role: &str) -> Result<(), String> {
+        self.database
+            .subset_permissions
+            .retain(|p| !(p.subset_id == subset && p.role_id == role));
+        Ok(())
+    }
+
+    pub fn delete_subset_permissions(&mut self, subset: &str) -> Result<(), String> {
+        self.database
+            .subset_permissions
+            .retain(|p| p.subset_id != subset);
+        Ok(())
+    }
+
+    pub fn delete_set_subset_permissions(&mut self, set: &str) -> Result<(), String> {
+        let subsets: Vec<String> = self
+            .database
+            .subsets
+            .iter()
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect();
+
+        self.database
+            .subset_permissions
+            .retain(|p| !subsets.contains(&p.subset_id));
+
+        Ok(())
+    }
+
+    pub fn select_users_by_set(&mut self, set: &str) -> Result<Vec<User>, String> {
+        Ok(self
+            .database
+            .memberships
+            .iter()
+            .filter(|m| m.set_id == set)
+            .flat_map(|m| {
+                self.database
+                    .users
+                    .iter()
+                    .find(|u| u.id == m.user_id)
+                    .map(|u| {
+                        User::from_row((
+                            u.id.clone(),
+                            u.username.clone(),
+                            u.display_name.clone(),
+                            u.email.clone(),
+                            u.image.clone(),
+                            u.bio.clone(),
+                            u.presence.clone(),
+                            user_status(u),
+                            u.email_visibility.clone(),
+                            u.bio_visibility.clone(),
+                        ))
+                    })
+            })
+            .collect())
+    }
+
+    pub fn select_membership(
+        &mut self,
+        token: Token,
+        set: &str,
+    ) -> Result<Option<(u64, String)>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|u| self.is_session_of(token, &u.id))
+            .and_then(|user| {
+                self.database
+                    .memberships
+                    .iter()
+                    .find(|m| m.user_id == user.id && m.set_id == set)
+                    .map(|m| (self.role_permissions(&m.role), user.id.clone()))
+            }))
+    }
+
+    pub fn select_set_by_id_and_token(
+        &mut self,
+        token: Token,
+        id: &str,
+    ) -> Result<Option<Set>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|u| self.is_session_of(token, &u.id))
+            .and_then(|user| {
+                self.database
+                    .memberships
+                    .iter()
+                    .find(|m| m.user_id == user.id && m.set_id == id)
+                    .and_then(|m| {
+                        self.database.sets.iter().find(|s| s.id == id).map(|s| {
+                            Set::from_row((
+                                s.id.clone(),
+                                s.name.clone(),
+                                s.icon.clone(),
+                                s.owner.clone(),
+                                m.role.clone(),
+                                self.role_permissions(&m.role),
+                            ))
+                        })
+                    })
+            }))
+    }
+
+    pub fn insert_set(
+        &mut self,
+        id: &str,
+        name: &str,
+        icon: &str,
+        owner: &str,
+    ) -> Result<(), String> {
+        self.database.sets.push(schema::Set {
+            id: id.to_string(),
+            name: name.to_string(),
+            icon: icon.to_string(),
+            owner: Some(owner.to_string()),
+        });
+        Ok(())
+    }
+
+    pub fn select_set_owner(&mut self, set: &str) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .sets
+            .iter()
+            .find(|s| s.id == set)
+            .and_then(|s| s.owner.clone()))
+    }
+
+    pub fn update_set_owner(&mut self, owner: &str, set: &str) -> Result<(), String> {
+        if let Some(set) = self.database.sets.iter_mut().find(|s| s.id == set) {
+            set.owner = Some(owner.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn insert_membership(
+        &mut self,
+        _id: &str,
+        user_id: &str,
+        set_id: &str,
+        role: &str,
+    ) -> Result<(), String> {
+        self.database.memberships.push(schema::Membership {
+            user_id: user_id.to_string(),
+            set_id: set_id.to_string(),
+            role: role.to_string(),
+            creation_date: now(),
+        });
+        Ok(())
+    }
+
+    pub fn insert_subset(&mut self, id: &str, name: &str, set_id: &str) -> Result<(), String> {
+        self.database.subsets.push(schema::Subset {
+            id: id.to_string(),
+            name: name.to_string(),
+            set_id: set_id.to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn select_subset_metadata_for_update(
+        &mut self,
+        token: Token,
+        subset: &str,
+    ) -> Result<Option<(u64, String, String, String)>, String> {
+        Ok(self
+            .database
+            .subsets
+            .iter()
+            .find(|s| s.id == subset)
+            .map(|s| s.set_id.clone())
+            .and_then(|set_id| {
+                self.database
+                    .users
+                    .iter()
+                    .find(|u| self.is_session_of(token, &u.id))
+                    .and_then(|user| {
+                        self.database
+                            .memberships
+                            .iter()
+                            .find(|m| m.user_id == user.id && m.set_id == set_id)
+                            .and_then(|m| {
+                                self.database
+                                    .subsets
+                                    .iter()
+                                    .find(|s| s.id == subset)
+                                    .map(|s| {
+                                        (
+                                            self.role_permissions(&m.role),
+                                            s.set_id.clone(),
+                                            s.name.clone(),
+                                            user.id.clone(),
+                                        )
+                                    })
+                            })
+                    })
+            }))
+    }
+
+    pub fn delete_subset_messages(&mut self, subset: &str) -> Result<(), String> {
+        self.database.messages.retain(|m| m.subset != subset);
+        Ok(())
+    }
+
+    pub fn delete_subset(&mut self, subset: &str) -> Result<(), String> {
+        self.database.subsets.retain(|s| s.id != subset);
+        Ok(())
+    }
+
+    pub fn select_reactions_by_messages(
+        &mut self,
+        uid: &str,
+        messages: &str,
+    ) -> Result<Vec<(String, Reaction)>, String> {
+        let messages = message_ids(messages);
+        let mut reactions: Vec<(String, Reaction)> = Vec::new();
+
+        for reaction in self
+            .database
+            .reactions
+            .iter()
+            .filter(|r| messages.contains(&r.message_id))
+        {
+            if let Some((_, existing)) = reactions
+                .iter_mut()
+                .find(|(m, r)| *m == reaction.message_id && r.emoji == reaction.emoji)
+            {
+                existing.count += 1;
+                existing.me |= reaction.user_id == uid;
+            } else {
+                reactions.push((
+                    reaction.message_id.clone(),
+                    Reaction::from_row((reaction.emoji.clone(), 1, reaction.user_id == uid)),
+                ));
+            }
+        }
+
+        Ok(reactions)
+    }
+
+    pub fn select_reaction_exists(
+        &mut self,
+        message: &str,
+        uid: &str,
+        emoji: &str,
+    ) -> Result<bool, String> {
+        Ok(self
+            .database
+            .reactions
+            .iter()
+            .any(|r| r.message_id == message && r.user_id == uid && r.emoji == emoji))
+    }
+
+    pub fn select_reaction_count(&mut self, message: &str, emoji: &str) -> Result<u64, String> {
+        Ok(self
+            .database
+            .reactions
+            .iter()
+            .filter(|r| r.message_id == message && r.emoji == emoji)
+            .count() as u64)
+    }
+
+    pub fn insert_reaction(&mut self, message: &str, uid: &str, emoji: &str) -> Result<(), String> {
+        self.database.reactions.push(schema::Reaction {
+            message_id: message.to_string(),
+            user_id: uid.to_string(),
+            emoji: emoji.to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn delete_reaction(&mut self, message: &str, uid: &str, emoji: &str) -> Result<(), String> {
+        self.database
+            .reactions
+            .retain(|r| !(r.message_id == message && r.user_id == uid && r.emoji == emoji));
+        Ok(())
+    }
+
+    pub fn delete_message_reactions(&mut self, message: &str) -> Result<(), String> {
+        self.database.reactions.retain(|r| r.message_id != message);
+        Ok(())
+    }
+
+    pub fn delete_subset_reactions(&mut self, subset: &str) -> Result<(), String> {
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| m.subset == subset)
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .reactions
+            .retain(|r| !messages.contains(&r.message_id));
+        Ok(())
+    }
+
+    pub fn delete_set_reactions(&mut self, set: &str) -> Result<(), String> {
+        let subsets = self
+            .database
+            .subsets
+            .iter()
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| subsets.contains(&m.subset))
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .reactions
+            .retain(|r| !messages.contains(&r.message_id));
+        Ok(())
+    }
+
+    pub fn update_set_name(&mut self, name: &str, set: &str) -> Result<(), String> {
+        if let Some(set) = self.database.sets.iter_mut().find(|s| s.id == set) {
+            set.name = name.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn update_set_icon(&mut self, icon: &str, set: &str) -> Result<(), String> {
+        if let Some(set) = self.database.sets.iter_mut().find(|s| s.id == set) {
+            set.icon = icon.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn update_subset_name(&mut self, name: &str, subset: &str) -> Result<(), String> {
+        if let Some(subset) = self.database.subsets.iter_mut().find(|s| s.id == subset) {
+            subset.name = name.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn select_user_by_token(&mut self, token: Token) -> Result<Option<User>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|u| self.is_session_of(token, &u.id))
+            .map(|u| {
+                User::from_row((
+                    u.id.clone(),
+                    u.username.clone(),
+                    u.display_name.clone(),
+                    u.email.clone(),
+                    u.image.clone(),
+                    u.bio.clone(),
+                    u.presence.clone(),
+                    user_status(u),
+                    u.email_visibility.clone(),
+                    u.bio_visibility.clone(),
+                ))
+            }))
+    }
+
+    pub fn select_user_by_uid(&mut self, uid: &str) -> Result<Option<User>, String> {
+        Ok(self.database.users.iter().find(|u| u.id == uid).map(|u| {
+            User::from_row((
+                u.id.clone(),
+                u.username.clone(),
+                u.display_name.clone(),
+                u.email.clone(),
+                u.image.clone(),
+                u.bio.clone(),
+                u.presence.clone(),
+                user_status(u),
+                u.email_visibility.clone(),
+                u.bio_visibility.clone(),
+            ))
+        }))
+    }
+
+    pub fn select_user_by_username(&mut self, username: &str) -> Result<Option<User>, String> {
+        Ok(self
+            .database
+            .users
+            .iter()
+            .find(|u| u.username == username)
+            .map(|u| {
+                User::from_row((
+                    u.id.clone(),
+                    u.username.clone(),
+                    u.display_name.clone(),
+                    u.email.clone(),
+                    u.image.clone(),
+                    u.bio.clone(),
+                    u.presence.clone(),
+                    user_status(u),
+                    u.email_visibility.clone(),
+                    u.bio_visibility.clone(),
+                ))
+            }))
+    }
+
+    pub fn select_search_users(
+        &mut self,
+        uid: &str,
+        requester: &str,
+        deleted: &str,
+        username: &str,
+        display_name: &str,
+        limit: usize,
+    ) -> Result<Vec<User>, String> {
+        // `LIKE` is case-insensitive, and the prefixes have their wildcards escaped
+        let matches = |value: &str, prefix: &str| {
+            let prefix = prefix
+                .replace("\\\\", "\\")
+                .replace("\\%", "%")
+                .replace("\\_", "_");
+
+            value.to_lowercase().starts_with(&prefix.to_lowercase())
+        };
+
+        let shared_sets = self
+            .database
+            .memberships
+            .iter()
+            .filter(|m| m.user_id == uid)
+            .map(|m| m.set_id.clone())
+            .collect::<Vec<_>>();
+
+        let mut users = self
+            .database
+            .users
+            .iter()
+            .filter(|u| u.id != requester && u.id != deleted)
+            .filter(|u| {
+                self.database
+                    .memberships
+                    .iter()
+                    .any(|m| m.user_id == u.id && shared_sets.contains(&m.set_id))
+            })
+            .filter(|u| matches(&u.username, username) || matches(&u.display_name, display_name))
+            .collect::<Vec<_>>();
+
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+
+        Ok(users
+            .into_iter()
+            .take(limit)
+            .map(|u| {
+                User::from_row((
+                    u.id.clone(),
+                    u.username.clone(),
+                    u.display_name.clone(),
+                    u.email.clone(),
+                    u.image.clone(),
+                    u.bio.clone(),
+                    u.presence.clone(),
+                    user_status(u),
+                    u.email_visibility.clone(),
+                    u.bio_visibility.clone(),
+                ))
+            })
+            .collect())
+    }
+
+    pub fn update_user_presence(
+        &mut self,
+        presence: &str,
+        status: &Option<String>,
+        uid: &str,
+    ) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.presence = presence.to_string();
+            user.status = status.clone();
+            user.status_expiry = None;
+        }
+        Ok(())
+    }
+
+    pub fn update_user_presence_with_duration(
+        &mut self,
+        presence: &str,
+        status: &Option<String>,
+        minutes: usize,
+        uid: &str,
+    ) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            user.presence = presence.to_string();
+            user.status = status.clone();
+            user.status_expiry = Some(minutes_in_future(minutes));
+        }
+        Ok(())
+    }
+
+    pub fn select_user_has_membership(&mut self, uid: &str, set: &str) -> Result<bool, String> {
+        Ok(self
+            .database
+            .memberships
+            .iter()
+            .any(|m| m.user_id == uid && m.set_id == set))
+    }
+
+    pub fn delete_membership(&mut self, user_id: &str, set_id: &str) -> Result<(), String> {
+        self.database
+            .memberships
+            .retain(|m| !(m.user_id == user_id && m.set_id == set_id));
+        Ok(())
+    }
+
+    pub fn select_user_set_ids(&mut self, user_id: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .memberships
+            .iter()
+            .filter(|m| m.user_id == user_id)
+            .map(|m| m.set_id.clone())
+            .collect())
+    }
+
+    pub fn update_user_username(&mut self, username: &str, token: Token) -> Result<(), String> {
+        let uid = self.session_user(token);
+
+        if let Some(user) = self
+            .database
+            .users
+            .iter_mut()
+            .find(|u| Some(&u.id) == uid.as_ref())
+        {
+            user.username = username.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn update_user_privacy(
+        &mut self,
+        email_visibility: &Option<String>,
+        bio_visibility: &Option<String>,
+        uid: &str,
+    ) -> Result<(), String> {
+        if let Some(user) = self.database.users.iter_mut().find(|u| u.id == uid) {
+            if let Some(email_visibility) = email_visibility {
+                user.email_visibility = email_visibility.clone();
+            }
+
+            if let Some(bio_visibility) = bio_visibility {
+                user.bio_visibility = bio_visibility.clone();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn select_shares_set(&mut self, uid: &str, other_uid: &str) -> Result<bool, String> {
+        Ok(self.database.memberships.iter().any(|mine| {
+            mine.user_id == uid
+                && self
+                    .database
+                    .memberships
+                    .iter()
+                    .any(|theirs| theirs.set_id == mine.set_id && theirs.user_id == other_uid)
+        }))
+    }
+
+    pub fn update_user_display_name(
+        &mut self,
+        display_name: &str,
+        token: Token,
+    ) -> Result<(), String> {
+        let uid = self.session_user(token);
+
+        if let Some(user) = self
+            .database
+            .users
+            .iter_mut()
+            .find(|u| Some(&u.id) == uid.as_ref())
+        {
+            user.display_name = display_name.to_string();
+        }
+        Ok(())
+    }
+
+    pub fn update_user_email(&mut self, email: &str, token: Token) -> Result<(), String> {
+        let uid = self.session_user(token);
+
+        if let Some(user) = self
+            .database
+            .users
+            .iter_mut()
+            .find(|u| Some(&u.id) == uid.as_ref())
+        {
+            user.email = email.to_string();
+            user.email_verified = false;
+        }
+        Ok(())
+    }
+
+    pub fn update_user_bio(&mut self, bio: &str, token: Token) -> Result<(), String> {
+        let uid = self.session_user(token);
+
+        if let Some(user) = self
+            .database
+            .users
+            .iter_mut()
+            .find(|u| Some(&u.id) == uid.as_ref())
+        {
+            user.bio = Some(bio.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn update_user_image(&mut self, image: &str, token: Token) -> Result<(), String> {
+        let uid = self.session_user(token);
+
+        if let Some(user) = self
+            .database
+            .users
+            .iter_mut()
+            .find(|u| Some(&u.id) == uid.as_ref())
+        {
+            user.image = Some(image.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn update_message(&mut self, message: &str, id: &str) -> Result<(), String> {
+        if let Some(m) = self.database.messages.iter_mut().find(|m| m.id == id) {
+            m.content = message.to_string();
+            m.edited_at = Some(now());
+        }
+        Ok(())
+    }
+
+    pub fn insert_message_revision(
+        &mut self,
+        _id: &str,
+        message: &str,
+        content: &str,
+    ) -> Result<(), String> {
+        self.database
+            .message_revisions
+            .push(schema::MessageRevision {
+                message_id: message.to_string(),
+                content: content.to_string(),
+                edit_time: now(),
+            });
+        Ok(())
+    }
+
+    pub fn select_message_revisions(&mut self, message: &str) -> Result<Vec<Revision>, String> {
+        Ok(self
+            .database
+            .message_revisions
+            .iter()
+            .filter(|r| r.message_id == message)
+            .map(|r| Revision::from_row((r.content.clone(), r.edit_time.clone())))
+            .collect())
+    }
+
+    pub fn delete_subset_revisions(&mut self, subset: &str) -> Result<(), String> {
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| m.subset == subset)
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .message_revisions
+            .retain(|r| !messages.contains(&r.message_id));
+        Ok(())
+    }
+
+    pub fn delete_set_revisions(&mut self, set: &str) -> Result<(), String> {
+        let subsets = self
             .database
             .subsets
             .iter()
-            .find(|s| s.id == subset)
-            .map(|s| s.set_id.clone()))
-    }
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
 
-    pub fn select_message_set_and_subset(
-        &mut self,
-        message: &str,
-    ) -> Result<Option<(String, String)>, String> {
-        Ok(self
+        let messages = self
             .database
             .messages
             .iter()
-            .find(|m| m.id == message)
-            .and_then(|m| {
-                self.database
-                    .subsets
-                    .iter()
-                    .find(|s| s.id == m.subset)
-                    .map(|s| (s.set_id.clone(), s.id.clone()))
-            }))
+            .filter(|m| subsets.contains(&m.subset))
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .message_revisions
+            .retain(|r| !messages.contains(&r.message_id));
+        Ok(())
     }
 
-    pub fn select_sets_by_token(&mut self, token: &str) -> Result<Vec<Set>, String> {
+    pub fn select_pinned_messages(&mut self, subset: &str) -> Result<Vec<Message>, String> {
         Ok(self
             .database
-            .users
+            .pins
             .iter()
-            .find(|u| u.token == Some(token.to_string()))
-            .map(|user| {
+            .filter_map(|p| {
                 self.database
-                    .memberships
+                    .messages
                     .iter()
-                    .filter(|m| m.user_id == user.id)
-                    .flat_map(|m| {
-                        self.database
-                            .sets
-                            .iter()
-                            .find(|s| s.id == m.set_id)
-                            .map(|s| {
-                                Set::from_row((
-                                    s.id.clone(),
-                                    s.name.clone(),
-                                    s.icon.clone(),
-                                    m.admin,
-                                ))
-                            })
-                    })
-                    .collect()
+                    .find(|m| m.id == p.message_id && m.subset == subset)
             })
-            .unwrap_or_default())
+            .map(|m| self.message_row(m))
+            .collect())
     }
 
-    pub fn select_subsets_by_set(&mut self, set: &str) -> Result<Vec<Subset>, String> {
-        Ok(self
+    pub fn select_pin_exists(&mut self, message: &str) -> Result<bool, String> {
+        Ok(self.database.pins.iter().any(|p| p.message_id == message))
+    }
+
+    pub fn insert_pin(&mut self, message: &str, uid: &str) -> Result<(), String> {
+        self.database.pins.push(schema::Pin {
+            message_id: message.to_string(),
+            pinned_by: uid.to_string(),
+        });
+        Ok(())
+    }
+
+    pub fn delete_pin(&mut self, message: &str) -> Result<(), String> {
+        self.database.pins.retain(|p| p.message_id != message);
+        Ok(())
+    }
+
+    pub fn delete_subset_pins(&mut self, subset: &str) -> Result<(), String> {
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| m.subset == subset)
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .pins
+            .retain(|p| !messages.contains(&p.message_id));
+        Ok(())
+    }
+
+    pub fn delete_set_pins(&mut self, set: &str) -> Result<(), String> {
+        let subsets = self
             .database
             .subsets
             .iter()
             .filter(|s| s.set_id == set)
-            .map(|s| Subset::from_row((s.id.clone(), s.name.clone())))
-            .collect())
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| subsets.contains(&m.subset))
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .pins
+            .retain(|p| !messages.contains(&p.message_id));
+        Ok(())
     }
 
-    pub fn select_users_by_set(&mut self, set: &str) -> Result<Vec<User>, String> {
-        Ok(self
+    pub fn select_mentions_by_messages(
+        &mut self,
+        messages: &str,
+    ) -> Result<Vec<(String, Mention)>, String> {
+        let messages = message_ids(messages);
+        let mut mentions = self
             .database
-            .memberships
+            .mentions
             .iter()
-            .filter(|m| m.set_id == set)
-            .flat_map(|m| {
-                self.database
-                    .users
-                    .iter()
-                    .find(|u| u.id == m.user_id)
-                    .map(|u| {
-                        User::from_row((
-                            u.id.clone(),
-                            u.username.clone(),
-                            u.display_name.clone(),
-                            u.email.clone(),
-                            u.image.clone(),
-                            u.bio.clone(),
-                        ))
-                    })
+            .filter(|m| messages.contains(&m.message_id))
+            .collect::<Vec<_>>();
+
+        mentions.sort_by_key(|m| m.position);
+
+        Ok(mentions
+            .into_iter()
+            .map(|m| {
+                (
+                    m.message_id.clone(),
+                    Mention::from_row((m.kind.clone(), m.user_id.clone())),
+                )
             })
             .collect())
     }
 
-    pub fn select_membership(
+    pub fn insert_mention(
         &mut self,
-        token: &str,
-        set: &str,
-    ) -> Result<Option<(bool, String)>, String> {
-        Ok(self
+        message: &str,
+        position: usize,
+        kind: &str,
+        uid: &Option<String>,
+    ) -> Result<(), String> {
+        self.database.mentions.push(schema::Mention {
+            message_id: message.to_string(),
+            position,
+            kind: kind.to_string(),
+            user_id: uid.clone(),
+        });
+        Ok(())
+    }
+
+    pub fn delete_message_mentions(&mut self, message: &str) -> Result<(), String> {
+        self.database.mentions.retain(|m| m.message_id != message);
+        Ok(())
+    }
+
+    pub fn delete_subset_mentions(&mut self, subset: &str) -> Result<(), String> {
+        let messages = self
             .database
-            .users
+            .messages
             .iter()
-            .find(|u| u.token == Some(token.to_string()))
-            .and_then(|user| {
-                self.database
-                    .memberships
-                    .iter()
-                    .find(|m| m.user_id == user.id && m.set_id == set)
-                    .map(|m| (m.admin, user.id.clone()))
-            }))
+            .filter(|m| m.subset == subset)
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .mentions
+            .retain(|m| !messages.contains(&m.message_id));
+        Ok(())
     }
 
-    pub fn select_set_by_id_and_token(
+    pub fn delete_set_mentions(&mut self, set: &str) -> Result<(), String> {
+        let subsets = self
+            .database
+            .subsets
+            .iter()
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+
+        let messages = self
+            .database
+            .messages
+            .iter()
+            .filter(|m| subsets.contains(&m.subset))
+            .map(|m| m.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .mentions
+            .retain(|m| !messages.contains(&m.message_id));
+        Ok(())
+    }
+
+    pub fn select_unread_counts(
         &mut self,
-        token: &str,
-        id: &str,
-    ) -> Result<Option<Set>, String> {
+        uid: &str,
+        set: &str,
+    ) -> Result<Vec<(String, u64, u64)>, String> {
         Ok(self
             .database
-            .users
+            .subsets
             .iter()
-            .find(|u| u.token == Some(token.to_string()))
-            .and_then(|user| {
-                self.database
-                    .memberships
+            .filter(|s| s.set_id == set)
+            .filter_map(|s| {
+                let messages = self.unread_messages(uid, &s.id);
+
+                if messages.is_empty() {
+                    return None;
+                }
+
+                let unread = messages.iter().filter(|m| m.thread.is_none()).count() as u64;
+                let mentions = messages
                     .iter()
-                    .find(|m| m.user_id == user.id && m.set_id == id)
-                    .and_then(|m| {
-                        self.database.sets.iter().find(|s| s.id == id).map(|s| {
-                            Set::from_row((s.id.clone(), s.name.clone(), s.icon.clone(), m.admin))
-                        })
+                    .filter(|m| {
+                        self.database.mentions.iter().any(|mention| {
+                            mention.message_id == m.id
+                                && (mention.user_id.as_deref() == Some(uid)
+                                    || mention.kind == "here"
+                                    || mention.kind == "everyone")
+                        }) && !self
+                            .database
+                            .blocks
+                            .iter()
+                            .any(|b| b.user_id == uid && b.blocked_id == m.sender)
                     })
-            }))
+                    .count() as u64;
+
+                Some((s.id.clone(), unread, mentions))
+            })
+            .collect())
     }
 
-    pub fn insert_set(&mut self, id: &str, name: &str, icon: &str) -> Result<(), String> {
-        self.database.sets.push(schema::Set {
-            id: id.to_string(),
-            name: name.to_string(),
-            icon: icon.to_string(),
-            creation_date: now(),
-        });
+    pub fn update_read_marker(
+        &mut self,
+        uid: &str,
+        subset: &str,
+        message: &str,
+    ) -> Result<(), String> {
+        let read_time = self
+            .database
+            .messages
+            .iter()
+            .find(|m| m.id == message)
+            .map(|m| m.send_time.clone());
+
+        if let Some(read_time) = read_time {
+            self.set_read_marker(uid, subset, read_time);
+        }
+
         Ok(())
     }
 
-    pub fn insert_membership(
+    pub fn update_read_marker_to_latest(
         &mut self,
-        id: &str,
-        user_id: &str,
-        set_id: &str,
-        admin: bool,
+        uid: &str,
+        subset: &str,
+        _latest_in_subset: &str,
     ) -> Result<(), String> {
-        self.database.memberships.push(schema::Membership {
-            id: id.to_string(),
-            user_id: user_id.to_string(),
-            set_id: set_id.to_string(),
-            admin,
-            creation_date: now(),
-        });
+        self.set_read_marker(uid, subset, now());
+
         Ok(())
     }
 
-    pub fn insert_subset(&mut self, id: &str, name: &str, set_id: &str) -> Result<(), String> {
-        self.database.subsets.push(schema::Subset {
-            id: id.to_string(),
-            name: name.to_string(),
-            set_id: set_id.to_string(),
-            creation_date: now(),
-        });
+    pub fn delete_membership_read_markers(&mut self, uid: &str, set: &str) -> Result<(), String> {
+        let subsets = self
+            .database
+            .subsets
+            .iter()
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .read_markers
+            .retain(|r| !(r.user_id == uid && subsets.contains(&r.subset_id)));
         Ok(())
     }
 
-    pub fn select_subset_metadata_for_update(
-        &mut self,
-        token: &str,
-        subset: &str,
-    ) -> Result<Option<(bool, String, String, String)>, String> {
-        Ok(self
+    pub fn delete_subset_read_markers(&mut self, subset: &str) -> Result<(), String> {
+        self.database.read_markers.retain(|r| r.subset_id != subset);
+        Ok(())
+    }
+
+    pub fn delete_set_read_markers(&mut self, set: &str) -> Result<(), String> {
+        let subsets = self
             .database
             .subsets
             .iter()
-            .find(|s| s.id == subset)
-            .map(|s| s.set_id.clone())
-            .and_then(|set_id| {
+            .filter(|s| s.set_id == set)
+            .map(|s| s.id.clone())
+            .collect::<Vec<_>>();
+
+        self.database
+            .read_markers
+            .retain(|r| !subsets.contains(&r.subset_id));
+        Ok(())
+    }
+
+    pub fn select_dms_by_user(&mut self, uid: &str) -> Result<Vec<Dm>, String> {
+        let mut dms = self
+            .database
+            .dms
+            .iter()
+            .filter(|dm| {
                 self.database
-                    .users
+                    .dm_participants
                     .iter()
-                    .find(|u| u.token == Some(token.to_string()))
-                    .and_then(|user| {
-                        self.database
-                            .memberships
-                            .iter()
-                            .find(|m| m.user_id == user.id && m.set_id == set_id)
-                            .and_then(|m| {
-                                self.database
-                                    .subsets
-                                    .iter()
-                                    .find(|s| s.id == subset)
-                                    .map(|s| {
-                                        (m.admin, s.set_id.clone(), s.name.clone(), user.id.clone())
-                                    })
-                            })
-                    })
-            }))
+                    .any(|p| p.dm_id == dm.id && p.user_id == uid)
+            })
+            .map(|dm| {
+                let last_message_time = self
+                    .database
+                    .dm_messages
+                    .iter()
+                    .filter(|m| m.dm_id == dm.id)
+                    .map(|m| m.send_time.clone())
+                    .max_by_key(|time| parse_date(time.clone()));
+
+                let activity = parse_date(
+                    last_message_time
+                        .clone()
+                        .unwrap_or_else(|| dm.creation_date.clone()),
+                );
+
+                (activity, Dm::from_row((dm.id.clone(), last_message_time)))
+            })
+            .collect::<Vec<_>>();
+
+        dms.sort_by_key(|(activity, _)| std::cmp::Reverse(*activity));
+
+        Ok(dms.into_iter().map(|(_, dm)| dm).collect())
     }
 
-    pub fn delete_subset_messages(&mut self, subset: &str) -> Result<(), String> {
-        self.database.messages.retain(|m| m.subset != subset);
-        Ok(())
+    pub fn select_dm_participants(&mut self, dm: &str) -> Result<Vec<User>, String> {
+        Ok(self
+            .database
+            .dm_participants
+            .iter()
+            .filter(|p| p.dm_id == dm)
+            .flat_map(|p| {
+                self.database
+                    .users
+                    .iter()
+                    .find(|u| u.id == p.user_id)
+                    .map(|u| {
+                        User::from_row((
+                            u.id.clone(),
+                            u.username.clone(),
+                            u.display_name.clone(),
+                            u.email.clone(),
+                            u.image.clone(),
+                            u.bio.clone(),
+                            u.presence.clone(),
+                            user_status(u),
+                            u.email_visibility.clone(),
+                            u.bio_visibility.clone(),
+                        ))
+                    })
+            })
+            .collect())
     }
 
-    pub fn delete_subset(&mut self, subset: &str) -> Result<(), String> {
-        self.database.subsets.retain(|s| s.id != subset);
-        Ok(())
+    pub fn select_dm_participant_ids(&mut self, dm: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .dm_participants
+            .iter()
+            .filter(|p| p.dm_id == dm)
+            .map(|p| p.user_id.clone())
+            .collect())
     }
 
-    pub fn delete_message(&mut self, message: &str) -> Result<(), String> {
-        self.database.messages.retain(|m| m.id != message);
-        Ok(())
+    pub fn select_dm_ids_by_user(&mut self, uid: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .dm_participants
+            .iter()
+            .filter(|p| p.user_id == uid)
+            .map(|p| p.dm_id.clone())
+            .collect())
     }
 
-    pub fn update_set_name(&mut self, name: &str, set: &str) -> Result<(), String> {
-        if let Some(set) = self.database.sets.iter_mut().find(|s| s.id == set) {
-            set.name = name.to_string();
-        }
-        Ok(())
+    pub fn select_is_dm_participant(&mut self, dm: &str, uid: &str) -> Result<bool, String> {
+        Ok(self
+            .database
+            .dm_participants
+            .iter()
+            .any(|p| p.dm_id == dm && p.user_id == uid))
     }
 
-    pub fn update_set_icon(&mut self, icon: &str, set: &str) -> Result<(), String> {
-        if let Some(set) = self.database.sets.iter_mut().find(|s| s.id == set) {
-            set.icon = icon.to_string();
-        }
+    pub fn insert_dm(&mut self, id: &str) -> Result<(), String> {
+        self.database.dms.push(schema::Dm {
+            id: id.to_string(),
+            creation_date: now(),
+        });
         Ok(())
     }
 
-    pub fn update_subset_name(&mut self, name: &str, subset: &str) -> Result<(), String> {
-        if let Some(subset) = self.database.subsets.iter_mut().find(|s| s.id == subset) {
-            subset.name = name.to_string();
-        }
+    pub fn insert_dm_participant(&mut self, dm: &str, uid: &str) -> Result<(), String> {
+        self.database.dm_participants.push(schema::DmParticipant {
+            dm_id: dm.to_string(),
+            user_id: uid.to_string(),
+        });
         Ok(())
     }
 
-    pub fn select_user_by_token(&mut self, token: &str) -> Result<Option<User>, String> {
+    pub fn select_dm_messages(&mut self, dm: &str, limit: usize) -> Result<Vec<Message>, String> {
         Ok(self
             .database
-            .users
+            .dm_messages
             .iter()
-            .find(|u| u.token == Some(token.to_string()))
-            .map(|u| {
-                User::from_row((
-                    u.id.clone(),
-                    u.username.clone(),
-                    u.display_name.clone(),
-                    u.email.clone(),
-                    u.image.clone(),
-                    u.bio.clone(),
-                ))
-            }))
+            .filter(|m| m.dm_id == dm)
+            .rev()
+            .take(limit)
+            .map(|m| self.dm_message_row(m))
+            .collect())
     }
 
-    pub fn select_user_by_uid(&mut self, uid: &str) -> Result<Option<User>, String> {
-        Ok(self.database.users.iter().find(|u| u.id == uid).map(|u| {
-            User::from_row((
-                u.id.clone(),
-                u.username.clone(),
-                u.display_name.clone(),
-                u.email.clone(),
-                u.image.clone(),
-                u.bio.clone(),
-            ))
-        }))
-    }
+    pub fn select_dm_messages_before(
+        &mut self,
+        dm: &str,
+        before: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, String> {
+        let before_time = self
+            .database
+            .dm_messages
+            .iter()
+            .find(|m| m.id == before)
+            .map(|m| parse_date(m.send_time.clone()));
 
-    pub fn select_user_has_membership(&mut self, uid: &str, set: &str) -> Result<bool, String> {
         Ok(self
             .database
-            .memberships
+            .dm_messages
             .iter()
-            .any(|m| m.user_id == uid && m.set_id == set))
+            .filter(|m| {
+                m.dm_id == dm
+                    && before_time
+                        .map(|before_time| parse_date(m.send_time.clone()) < before_time)
+                        .unwrap_or(false)
+            })
+            .rev()
+            .take(limit)
+            .map(|m| self.dm_message_row(m))
+            .collect())
     }
 
-    pub fn delete_membership(&mut self, user_id: &str, set_id: &str) -> Result<(), String> {
-        self.database
-            .memberships
-            .retain(|m| !(m.user_id == user_id && m.set_id == set_id));
+    pub fn insert_dm_message(
+        &mut self,
+        id: &str,
+        dm: &str,
+        sender: &str,
+        content: &str,
+        attachment: &Option<String>,
+    ) -> Result<(), String> {
+        self.database.dm_messages.push(schema::DmMessage {
+            id: id.to_string(),
+            dm_id: dm.to_string(),
+            sender: sender.to_string(),
+            content: content.to_string(),
+            attachment: attachment.clone(),
+            send_time: now(),
+        });
         Ok(())
     }
 
-    pub fn select_user_set_ids(&mut self, user_id: &str) -> Result<Vec<String>, String> {
+    pub fn select_friends(
+        &mut self,
+        requester: &str,
+        recipient: &str,
+    ) -> Result<Vec<Friend>, String> {
         Ok(self
             .database
-            .memberships
+            .friends
             .iter()
-            .filter(|m| m.user_id == user_id)
-            .map(|m| m.set_id.clone())
+            .filter_map(|f| {
+                let (other, status) = if f.user_id == requester {
+                    (&f.friend_id, "outgoing")
+                } else if f.friend_id == recipient {
+                    (&f.user_id, "incoming")
+                } else {
+                    return None;
+                };
+
+                let status = if f.accepted { "friend" } else { status };
+
+                self.database
+                    .users
+                    .iter()
+                    .find(|u| &u.id == other)
+                    .map(|u| {
+                        Friend::from_row((
+                            u.id.clone(),
+                            u.username.clone(),
+                            u.display_name.clone(),
+                            u.email.clone(),
+                            u.image.clone(),
+                            u.bio.clone(),
+                            u.presence.clone(),
+                            user_status(u),
+                            u.email_visibility.clone(),
+                            u.bio_visibility.clone(),
+                            status.to_string(),
+                        ))
+                    })
+            })
             .collect())
     }
 
-    pub fn update_user_display_name(
-        &mut self,
-        display_name: &str,
-        token: &str,
-    ) -> Result<(), String> {
-        if let Some(user) = self
+    pub fn select_friend_request(&mut self, from: &str, to: &str) -> Result<Option<bool>, String> {
+        Ok(self
             .database
-            .users
-            .iter_mut()
-            .find(|u| u.token == Some(token.to_string()))
-        {
-            user.display_name = display_name.to_string();
-        }
+            .friends
+            .iter()
+            .find(|f| f.user_id == from && f.friend_id == to)
+            .map(|f| f.accepted))
+    }
+
+    pub fn insert_friend_request(&mut self, from: &str, to: &str) -> Result<(), String> {
+        self.database.friends.push(schema::Friend {
+            user_id: from.to_string(),
+            friend_id: to.to_string(),
+            accepted: false,
+        });
         Ok(())
     }
 
-    pub fn update_user_email(&mut self, email: &str, token: &str) -> Result<(), String> {
-        if let Some(user) = self
-            .database
-            .users
+    pub fn update_accept_friend_request(&mut self, from: &str, to: &str) -> Result<(), String> {
+        self.database
+            .friends
             .iter_mut()
-            .find(|u| u.token == Some(token.to_string()))
-        {
-            user.email = email.to_string();
-        }
+            .filter(|f| f.user_id == from && f.friend_id == to)
+            .for_each(|f| f.accepted = true);
         Ok(())
     }
 
-    pub fn update_user_bio(&mut self, bio: &str, token: &str) -> Result<(), String> {
-        if let Some(user) = self
-            .database
-            .users
-            .iter_mut()
-            .find(|u| u.token == Some(token.to_string()))
-        {
-            user.bio = Some(bio.to_string());
-        }
+    pub fn delete_friend_request(&mut self, from: &str, to: &str) -> Result<(), String> {
+        self.database
+            .friends
+            .retain(|f| !(f.user_id == from && f.friend_id == to));
         Ok(())
     }
 
-    pub fn update_user_image(&mut self, image: &str, token: &str) -> Result<(), String> {
-        if let Some(user) = self
+    pub fn select_is_blocked(&mut self, uid: &str, blocked: &str) -> Result<bool, String> {
+        Ok(self
             .database
-            .users
-            .iter_mut()
-            .find(|u| u.token == Some(token.to_string()))
-        {
-            user.image = Some(image.to_string());
-        }
+            .blocks
+            .iter()
+            .any(|b| b.user_id == uid && b.blocked_id == blocked))
+    }
+
+    pub fn select_blocked_ids(&mut self, uid: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .blocks
+            .iter()
+            .filter(|b| b.user_id == uid)
+            .map(|b| b.blocked_id.clone())
+            .collect())
+    }
+
+    pub fn select_blocker_ids(&mut self, uid: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .blocks
+            .iter()
+            .filter(|b| b.blocked_id == uid)
+            .map(|b| b.user_id.clone())
+            .collect())
+    }
+
+    pub fn insert_block(&mut self, uid: &str, blocked: &str) -> Result<(), String> {
+        self.database.blocks.push(schema::Block {
+            user_id: uid.to_string(),
+            blocked_id: blocked.to_string(),
+        });
         Ok(())
     }
 
-    pub fn update_message(&mut self, message: &str, id: &str) -> Result<(), String> {
-        if let Some(m) = self.database.messages.iter_mut().find(|m| m.id == id) {
-            m.content = message.to_string();
-        }
+    pub fn delete_block(&mut self, uid: &str, blocked: &str) -> Result<(), String> {
+        self.database
+            .blocks
+            .retain(|b| !(b.user_id == uid && b.blocked_id == blocked));
         Ok(())
     }
 
@@ -865,4 +2888,130 @@ impl<'a> MockTransaction<'a> {
         self.database.sets.retain(|s| s.id != set);
         Ok(())
     }
+
+    pub fn delete_set_roles(&mut self, set: &str) -> Result<(), String> {
+        self.database.roles.retain(|r| r.set_id != set);
+        Ok(())
+    }
+
+    pub fn select_roles_by_set(&mut self, set: &str) -> Result<Vec<Role>, String> {
+        Ok(self
+            .database
+            .roles
+            .iter()
+            .filter(|r| r.set_id == set)
+            .map(|r| Role::from_row((r.id.clone(), r.name.clone(), r.kind.clone(), r.permissions)))
+            .collect())
+    }
+
+    pub fn select_user_ids_by_role(&mut self, role: &str) -> Result<Vec<String>, String> {
+        Ok(self
+            .database
+            .memberships
+            .iter()
+            .filter(|m| m.role == role)
+            .map(|m| m.user_id.clone())
+            .collect())
+    }
+
+    pub fn select_role_by_id_and_set(
+        &mut self,
+        role: &str,
+        set: &str,
+    ) -> Result<Option<Role>, String> {
+        Ok(self
+            .database
+            .roles
+            .iter()
+            .find(|r| r.id == role && r.set_id == set)
+            .map(|r| Role::from_row((r.id.clone(), r.name.clone(), r.kind.clone(), r.permissions))))
+    }
+
+    pub fn select_role_by_user_and_set(
+        &mut self,
+        user_id: &str,
+        set: &str,
+    ) -> Result<Option<Role>, String> {
+        Ok(self
+            .database
+            .memberships
+            .iter()
+            .find(|m| m.user_id == user_id && m.set_id == set)
+            .and_then(|m| self.database.roles.iter().find(|r| r.id == m.role))
+            .map(|r| Role::from_row((r.id.clone(), r.name.clone(), r.kind.clone(), r.permissions))))
+    }
+
+    pub fn select_role_id_by_kind(
+        &mut self,
+        set: &str,
+        kind: &str,
+    ) -> Result<Option<String>, String> {
+        Ok(self
+            .database
+            .roles
+            .iter()
+            .find(|r| r.set_id == set && r.kind == kind)
+            .map(|r| r.id.clone()))
+    }
+
+    pub fn insert_role(
+        &mut self,
+        id: &str,
+        set_id: &str,
+        name: &str,
+        kind: &str,
+        permissions: u64,
+    ) -> Result<(), String> {
+        self.database.roles.push(schema::Role {
+            id: id.to_string(),
+            set_id: set_id.to_string(),
+            name: name.to_string(),
+            kind: kind.to_string(),
+            permissions,
+        });
+        Ok(())
+    }
+
+    pub fn update_membership_role(
+        &mut self,
+        role: &str,
+        user_id: &str,
+        set_id: &str,
+    ) -> Result<(), String> {
+        if let Some(membership) = self
+            .database
+            .memberships
+            .iter_mut()
+            .find(|m| m.user_id == user_id && m.set_id == set_id)
+        {
+            membership.role = role.to_string();
+        }
+        Ok(())
+    }
+}
+
+/// Returns the user's custom status if it has not expired.
+fn user_status(user: &schema::User) -> Option<String> {
+    let expired = user
+        .status_expiry
+        .clone()
+        .map(|d| parse_date(d) <= now_u64())
+        .unwrap_or(false);
+
+    if expired {
+        None
+    } else {
+        user.status.clone()
+    }
+}
+
+/// Parses a JSON array of message IDs, as passed to the batched message queries.
+fn message_ids(messages: &str) -> Vec<String> {
+    match humphrey_json::Value::parse(messages) {
+        Ok(humphrey_json::Value::Array(ids)) => ids
+            .into_iter()
+            .filter_map(|id| id.as_str().map(|id| id.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    }
 }
@@ -28,10 +28,19 @@ macro_rules! declare_tests {
 declare_tests! {
     // Auth tests
     mod auth {
+        expired_token: "./testcases/auth/expired_token.json",
         login_incorrect_password: "./testcases/auth/login_incorrect_password.json",
         login_logout_flow: "./testcases/auth/login_logout_flow.json",
+        logout_everywhere: "./testcases/auth/logout_everywhere.json",
+        logout_everywhere_disconnects: "./testcases/auth/logout_everywhere_disconnects.json",
+        multiple_sessions: "./testcases/auth/multiple_sessions.json",
+        refresh_token: "./testcases/auth/refresh_token.json",
+        revoke_session_disconnects: "./testcases/auth/revoke_session_disconnects.json",
+        revoke_session_of_other_user: "./testcases/auth/revoke_session_of_other_user.json",
+        revoke_session_then_dm: "./testcases/auth/revoke_session_then_dm.json",
         signup_empty_display_name: "./testcases/auth/signup_empty_display_name.json",
         signup_flow: "./testcases/auth/signup_flow.json",
+        signup_invalid_email: "./testcases/auth/signup_invalid_email.json",
         signup_invalid_username: "./testcases/auth/signup_invalid_username.json",
         signup_password_too_short: "./testcases/auth/signup_password_too_short.json",
         signup_username_already_exists: "./testcases/auth/signup_username_already_exists.json",
@@ -39,22 +48,88 @@ declare_tests! {
         validate_invalid_token: "./testcases/auth/validate_invalid_token.json"
     }
 
+    // Password tests
+    mod passwords {
+        change_password: "./testcases/passwords/change_password.json",
+        change_password_disconnects: "./testcases/passwords/change_password_disconnects.json",
+        request_reset_for_unknown_user: "./testcases/passwords/request_reset_for_unknown_user.json",
+        reset_password: "./testcases/passwords/reset_password.json"
+    }
+
+    // Email verification tests
+    mod verification {
+        resend_verification: "./testcases/verification/resend_verification.json",
+        update_email_requires_verification: "./testcases/verification/update_email_requires_verification.json",
+        verify_email: "./testcases/verification/verify_email.json",
+        verify_email_of_other_user: "./testcases/verification/verify_email_of_other_user.json"
+    }
+
+    // Two-factor authentication tests
+    mod two_factor {
+        disable_2fa: "./testcases/two_factor/disable_2fa.json",
+        enable_2fa: "./testcases/two_factor/enable_2fa.json",
+        login_with_2fa: "./testcases/two_factor/login_with_2fa.json",
+        login_with_recovery_code: "./testcases/two_factor/login_with_recovery_code.json",
+        login_with_replayed_code: "./testcases/two_factor/login_with_replayed_code.json"
+    }
+
+    // Rate limiting tests
+    mod rate_limits {
+        login_rate_limited: "./testcases/rate_limits/login_rate_limited.json",
+        typing_rate_limited: "./testcases/rate_limits/typing_rate_limited.json",
+        typing_rate_limited_across_sessions: "./testcases/rate_limits/typing_rate_limited_across_sessions.json"
+    }
+
+    // Account tests
+    mod account {
+        delete_account: "./testcases/account/delete_account.json",
+        delete_account_as_only_member: "./testcases/account/delete_account_as_only_member.json",
+        delete_account_with_2fa: "./testcases/account/delete_account_with_2fa.json",
+        export_data: "./testcases/account/export_data.json"
+    }
+
     // User tests
     mod user {
+        change_username: "./testcases/user/change_username.json",
+        change_username_invalid: "./testcases/user/change_username_invalid.json",
         get_and_update_details: "./testcases/user/get_and_update_details.json",
+        get_deleted_user: "./testcases/user/get_deleted_user.json",
         get_nonexistant_user: "./testcases/user/get_nonexistant_user.json",
+        get_user_by_username: "./testcases/user/get_user_by_username.json",
+        privacy_settings: "./testcases/user/privacy_settings.json",
+        search_users: "./testcases/user/search_users.json",
+        set_invalid_privacy: "./testcases/user/set_invalid_privacy.json",
+        set_invalid_presence: "./testcases/user/set_invalid_presence.json",
+        set_presence: "./testcases/user/set_presence.json",
+        set_presence_persists: "./testcases/user/set_presence_persists.json",
+        set_presence_with_duration: "./testcases/user/set_presence_with_duration.json",
         update_with_invalid_token: "./testcases/user/update_with_invalid_token.json"
     }
 
     // Message tests
     mod message {
+        delete_message_without_permissions: "./testcases/messages/delete_message_without_permissions.json",
+        edit_message_as_moderator: "./testcases/messages/edit_message_as_moderator.json",
+        edit_message_mentions: "./testcases/messages/edit_message_mentions.json",
         get_messages_without_permissions: "./testcases/messages/get_messages_without_permissions.json",
         get_messages: "./testcases/messages/get_messages.json",
+        message_history: "./testcases/messages/message_history.json",
+        message_history_after_delete: "./testcases/messages/message_history_after_delete.json",
+        mention_blocker: "./testcases/messages/mention_blocker.json",
+        mention_everyone: "./testcases/messages/mention_everyone.json",
+        mention_unsubscribed_user: "./testcases/messages/mention_unsubscribed_user.json",
+        mention_user: "./testcases/messages/mention_user.json",
+        moderator_delete_message: "./testcases/messages/moderator_delete_message.json",
+        reply_in_nested_thread: "./testcases/messages/reply_in_nested_thread.json",
+        reply_to_deleted_message: "./testcases/messages/reply_to_deleted_message.json",
+        reply_to_message: "./testcases/messages/reply_to_message.json",
+        reply_to_nonexistent_message: "./testcases/messages/reply_to_nonexistent_message.json",
         send_attachment_without_content: "./testcases/messages/send_attachment_without_content.json",
         send_invalid_attachment: "./testcases/messages/send_invalid_attachment.json",
         send_message_invalid_subset: "./testcases/messages/send_message_invalid_subset.json",
         send_message_without_permissions: "./testcases/messages/send_message_without_permissions.json",
         send_message: "./testcases/messages/send_message.json",
+        thread_messages: "./testcases/messages/thread_messages.json",
         typing_notification_invalid_subset: "./testcases/messages/typing_notification_invalid_subset.json",
         typing_notification_invalid_token: "./testcases/messages/typing_notification_invalid_token.json",
         typing_notification_without_permissions: "./testcases/messages/typing_notification_without_permissions.json",
@@ -64,6 +139,63 @@ declare_tests! {
         update_nonexistant_message: "./testcases/messages/update_nonexistant_message.json"
     }
 
+    // Reaction tests
+    mod reactions {
+        react_and_unreact: "./testcases/reactions/react_and_unreact.json",
+        react_invalid_emoji: "./testcases/reactions/react_invalid_emoji.json",
+        react_twice: "./testcases/reactions/react_twice.json",
+        react_without_permissions: "./testcases/reactions/react_without_permissions.json"
+    }
+
+    // Pin tests
+    mod pins {
+        delete_pinned_message: "./testcases/pins/delete_pinned_message.json",
+        pin_and_unpin: "./testcases/pins/pin_and_unpin.json",
+        pin_nonexistent_message: "./testcases/pins/pin_nonexistent_message.json",
+        pin_without_permissions: "./testcases/pins/pin_without_permissions.json",
+        pins_without_permissions: "./testcases/pins/pins_without_permissions.json"
+    }
+
+    // Read marker tests
+    mod read_markers {
+        mark_read: "./testcases/read_markers/mark_read.json",
+        mark_read_invalid_message: "./testcases/read_markers/mark_read_invalid_message.json",
+        mark_read_without_permissions: "./testcases/read_markers/mark_read_without_permissions.json",
+        unread_here_mention: "./testcases/read_markers/unread_here_mention.json",
+        unread_mention_from_blocked_user: "./testcases/read_markers/unread_mention_from_blocked_user.json"
+    }
+
+    // Direct message tests
+    mod dms {
+        create_dm: "./testcases/dms/create_dm.json",
+        create_dm_with_blocked_user: "./testcases/dms/create_dm_with_blocked_user.json",
+        create_dm_with_self: "./testcases/dms/create_dm_with_self.json",
+        create_dm_with_stranger: "./testcases/dms/create_dm_with_stranger.json",
+        create_dm_with_unknown_user: "./testcases/dms/create_dm_with_unknown_user.json",
+        dm_without_permissions: "./testcases/dms/dm_without_permissions.json",
+        send_dm: "./testcases/dms/send_dm.json",
+        send_dm_to_blocker: "./testcases/dms/send_dm_to_blocker.json",
+        send_dm_without_sets: "./testcases/dms/send_dm_without_sets.json"
+    }
+
+    // Friend and blocking tests
+    mod friends {
+        block_friend: "./testcases/friends/block_friend.json",
+        block_user: "./testcases/friends/block_user.json",
+        decline_friend_request: "./testcases/friends/decline_friend_request.json",
+        friend_request: "./testcases/friends/friend_request.json",
+        friend_request_to_deleted_user: "./testcases/friends/friend_request_to_deleted_user.json",
+        invalid_friend_request: "./testcases/friends/invalid_friend_request.json",
+        mutual_friend_request: "./testcases/friends/mutual_friend_request.json"
+    }
+
+    // Search tests
+    mod search {
+        search_empty_query: "./testcases/search/search_empty_query.json",
+        search_messages: "./testcases/search/search_messages.json",
+        search_without_membership: "./testcases/search/search_without_membership.json"
+    }
+
     // Set and subset tests
     mod sets {
         create_invite_invalid_set: "./testcases/sets/create_invite_invalid_set.json",
@@ -74,28 +206,46 @@ declare_tests! {
         create_subset_invalid_set: "./testcases/sets/create_subset_invalid_set.json",
         create_subset_without_permissions: "./testcases/sets/create_subset_without_permissions.json",
         create_subset: "./testcases/sets/create_subset.json",
+        delete_set_without_ownership: "./testcases/sets/delete_set_without_ownership.json",
         get_invites_and_invite: "./testcases/sets/get_invites_and_invite.json",
         get_sets_and_set: "./testcases/sets/get_sets_and_set.json",
         join_set_invalid_code: "./testcases/sets/join_set_invalid_code.json",
+        kick_owner: "./testcases/sets/kick_owner.json",
         kick_user_invalid_set: "./testcases/sets/kick_user_invalid_set.json",
         kick_user_invalid_user: "./testcases/sets/kick_user_invalid_user.json",
         kick_user_without_permissions: "./testcases/sets/kick_user_without_permissions.json",
         kick_user: "./testcases/sets/kick_user.json",
         leave_and_join_set: "./testcases/sets/leave_and_join_set.json",
+        leave_set_as_owner: "./testcases/sets/leave_set_as_owner.json",
         leave_set_invalid_set: "./testcases/sets/leave_set_invalid_set.json",
+        private_subset: "./testcases/sets/private_subset.json",
+        read_only_subset: "./testcases/sets/read_only_subset.json",
         revoke_invite_invalid_invite: "./testcases/sets/revoke_invite_invalid_invite.json",
         revoke_invite_invalid_set: "./testcases/sets/revoke_invite_invalid_set.json",
         revoke_invite_unmatching_set_and_invite: "./testcases/sets/revoke_invite_unmatching_set_and_invite.json",
         revoke_invite_without_permissions: "./testcases/sets/revoke_invite_without_permissions.json",
         revoke_invite: "./testcases/sets/revoke_invite.json",
+        transfer_ownership_without_ownership: "./testcases/sets/transfer_ownership_without_ownership.json",
+        transfer_ownership: "./testcases/sets/transfer_ownership.json",
         update_and_delete_set: "./testcases/sets/update_and_delete_set.json",
         update_and_delete_subset: "./testcases/sets/update_and_delete_subset.json",
         update_set_invalid_set: "./testcases/sets/update_set_invalid_set.json",
         update_set_without_permissions: "./testcases/sets/update_set_without_permissions.json",
         update_subset_invalid_subset: "./testcases/sets/update_subset_invalid_subset.json",
+        update_subset_permissions_without_permissions: "./testcases/sets/update_subset_permissions_without_permissions.json",
         update_subset_without_permissions: "./testcases/sets/update_subset_without_permissions.json"
     }
 
+    // Role tests
+    mod roles {
+        assign_owner_role: "./testcases/roles/assign_owner_role.json",
+        assign_role: "./testcases/roles/assign_role.json",
+        create_role_without_permissions: "./testcases/roles/create_role_without_permissions.json",
+        create_role: "./testcases/roles/create_role.json",
+        create_role_invalid_permissions: "./testcases/roles/create_role_invalid_permissions.json",
+        get_roles: "./testcases/roles/get_roles.json"
+    }
+
     // Event tests
     mod event {
         events_user_online_event: "./testcases/events/user_online_event.json"
@@ -163,6 +313,12 @@ fn parse_stages(stages: &str, path: &str) -> impl Iterator<Item = TestStage> {
                     }
                 }
 
+                "mail" => {
+                    let data = stage.get("data").unwrap();
+
+                    TestStage::Mail { data: data.clone() }
+                }
+
                 "import" => {
                     let import_path = stage.get("path").unwrap().as_str().unwrap().to_string();
                     let import_path_absolute = path.join(import_path).canonicalize().unwrap();
@@ -0,0 +1,179 @@
+//! Provides time-based one-time passwords for two-factor authentication, as specified in [RFC 6238](https://datatracker.ietf.org/doc/html/rfc6238).
+//!
+//! Codes are six digits long, change every 30 seconds and are generated with HMAC-SHA1, since these are the defaults supported by all authenticator apps.
+//!
+//! Secrets are encrypted with AES-256-GCM before being stored, using a key derived from the server's token secret, so that a database leak alone does not reveal them.
+
+use crate::util::TOKEN_SECRET;
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use std::time::UNIX_EPOCH;
+
+/// The number of seconds for which each code is valid.
+const PERIOD: u64 = 30;
+
+/// The number of digits in each code.
+const DIGITS: u32 = 6;
+
+/// The number of periods either side of the current one for which codes are accepted, to allow for clock drift.
+const WINDOW: u64 = 1;
+
+/// The number of random bytes in a secret.
+const SECRET_LENGTH: usize = 20;
+
+/// The number of bytes in the nonce used to encrypt a secret.
+const NONCE_LENGTH: usize = 12;
+
+/// The alphabet used to encode secrets in base 32, as specified in [RFC 4648](https://datatracker.ietf.org/doc/html/rfc4648).
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generates a new random secret, encoded in base 32.
+pub fn generate_secret() -> String {
+    let mut secret = [0; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+
+    base32_encode(&secret)
+}
+
+/// Creates the URI used to add the given secret to an authenticator app, usually by scanning it as a QR code.
+pub fn provisioning_uri(secret: &str, username: &str) -> String {
+    format!(
+        "otpauth://totp/Equion:{}?secret={}&issuer=Equion&algorithm=SHA1&digits={}&period={}",
+        username, secret, DIGITS, PERIOD
+    )
+}
+
+/// Gets the code for the given base 32 secret the given number of periods after the current one.
+#[cfg(test)]
+pub fn code_at_offset(secret: &str, offset: u64) -> Option<String> {
+    let key = base32_decode(secret)?;
+
+    Some(code(
+        &key,
+        UNIX_EPOCH.elapsed().unwrap().as_secs() / PERIOD + offset,
+    ))
+}
+
+/// Checks whether the given code is valid for the given base 32 secret at the current time, returning the counter it was generated for.
+///
+/// Codes generated for the last accepted counter or any before it are rejected, so that each code can only be used once.
+pub fn verify(secret: &str, code: &str, last_counter: Option<u64>) -> Option<u64> {
+    let key = base32_decode(secret)?;
+
+    let counter = UNIX_EPOCH.elapsed().unwrap().as_secs() / PERIOD;
+
+    (counter.saturating_sub(WINDOW)..=counter + WINDOW)
+        .filter(|&counter| last_counter.is_none_or(|last| counter > last))
+        .find(|&counter| self::code(&key, counter) == code.trim())
+}
+
+/// Encrypts the given secret for storage, returning the nonce and ciphertext encoded in base 64.
+pub fn encrypt_secret(secret: &str) -> String {
+    let mut nonce = [0; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher()
+        .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+        .expect("secrets are small enough to encrypt");
+
+    base64::encode([&nonce[..], &ciphertext].concat())
+}
+
+/// Decrypts a secret encrypted with `encrypt_secret`, or returns `None` if it is invalid.
+pub fn decrypt_secret(encrypted: &str) -> Option<String> {
+    let bytes = base64::decode(encrypted).ok()?;
+
+    if bytes.len() < NONCE_LENGTH {
+        return None;
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+    let secret = cipher()
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .ok()?;
+
+    String::from_utf8(secret).ok()
+}
+
+/// Creates the cipher used to encrypt secrets, with a key derived from the server's token secret.
+fn cipher() -> Aes256Gcm {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(
+        TOKEN_SECRET
+            .as_deref()
+            .expect("token secret is checked at startup")
+            .as_bytes(),
+    )
+    .expect("HMAC accepts keys of any length");
+    mac.update(b"totp secret");
+
+    Aes256Gcm::new(&mac.finalize().into_bytes())
+}
+
+/// Generates the code for the given key and counter, as specified in [RFC 4226](https://datatracker.ietf.org/doc/html/rfc4226).
+fn code(key: &[u8], counter: u64) -> String {
+    let mut mac =
+        <Hmac<Sha1> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[19] & 0xf) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fffffff;
+
+    format!(
+        "{:0width$}",
+        binary % 10_u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Encodes the given bytes in base 32 without padding.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Decodes the given base 32 string, ignoring padding, or returns `None` if it is invalid.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for ch in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&c| c == ch.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(decoded)
+}
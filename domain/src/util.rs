@@ -1,7 +1,25 @@
 //! Provides utility functions.
 
 use chrono::{TimeZone, Utc};
+use hmac::{Hmac, Mac};
 use mysql::Value;
+use once_cell::sync::Lazy;
+use sha2::Sha256;
+
+/// The secret used to hash tokens, taken from the `EQUION_TOKEN_SECRET` environment variable.
+///
+/// This is `None` if the variable is missing or empty, in which case the server refuses to start, since tokens and
+/// codes hashed with a different secret could no longer be verified.
+#[cfg(not(test))]
+pub static TOKEN_SECRET: Lazy<Option<String>> = Lazy::new(|| {
+    std::env::var("EQUION_TOKEN_SECRET")
+        .ok()
+        .filter(|secret| !secret.trim().is_empty())
+});
+
+/// The secret used to hash tokens in tests.
+#[cfg(test)]
+pub static TOKEN_SECRET: Lazy<Option<String>> = Lazy::new(|| Some("test secret".to_string()));
 
 /// Returns the Greek letter corresponding to the character.
 /// This is done visually and is only used for generating set icons.
@@ -50,3 +68,37 @@ pub fn parse_date(value: Value) -> u64 {
         _ => panic!("Invalid date"),
     }
 }
+
+/// Hashes the given token with the server's secret, so that tokens are never stored verbatim.
+///
+/// The token is prefixed with a label so that its hash can never coincide with other values derived from the secret.
+pub fn hash_token(token: &str) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(
+        TOKEN_SECRET
+            .as_deref()
+            .expect("token secret is checked at startup")
+            .as_bytes(),
+    )
+    .expect("HMAC accepts keys of any length");
+    mac.update(b"token:");
+    mac.update(token.as_bytes());
+
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// A token, which is hashed when it is bound to a database command so that tokens are only ever stored and looked up by their hash.
+#[derive(Clone, Copy)]
+pub struct Token<'a>(pub &'a str);
+
+impl Token<'_> {
+    /// Hashes the token with the server's secret.
+    pub fn hash(&self) -> String {
+        hash_token(self.0)
+    }
+}
+
+impl From<Token<'_>> for Value {
+    fn from(token: Token<'_>) -> Self {
+        Value::from(token.hash())
+    }
+}
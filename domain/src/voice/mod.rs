@@ -124,7 +124,7 @@ impl VoiceServer {
             return Err("User not connected to voice server".to_string());
         };
 
-        let entry = voice_channels.entry(channel).or_insert_with(Vec::new);
+        let entry = voice_channels.entry(channel).or_default();
 
         if !entry.contains(&user) {
             entry.push(user);
@@ -15,10 +15,13 @@ pub fn connect_user_voice(state: Arc<State>, json: Value, addr: SocketAddr) -> V
     error_context(|| {
         let token = get_string(&json, "token")?;
         let peer_id = get_string(&json, "peerId")?;
-        let user = state.get_user_by_token(token)?;
+        let user = state.get_user_by_token(&token)?;
+
+        // Authenticating the connection lets it receive direct events, and drops it from voice chat when the session ends
+        state.authenticate(&token, addr)?;
 
         state.voice.connect_user_voice(&user.uid, peer_id, addr);
-        state.broadcast_user_online(user.uid);
+        state.broadcast_presence(user.uid);
 
         Ok(json!({ "success": true }))
     })
@@ -31,7 +34,7 @@ pub fn disconnect_user_voice(state: Arc<State>, json: Value, _: SocketAddr) -> V
         let user = state.get_user_by_token(token)?;
 
         state.voice.disconnect_user_voice(&user.uid);
-        state.broadcast_user_offline(user.uid);
+        state.broadcast_presence(user.uid);
 
         Ok(json!({ "success": true }))
     })